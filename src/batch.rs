@@ -0,0 +1,66 @@
+use crate::config::AppConfig;
+use crate::error::IherbError;
+use crate::scraper::navigation::Navigator;
+use anyhow::Result;
+
+/// Shared bookkeeping for commands that process many items (product IDs, search
+/// queries): paces live fetches with the product delay and applies the
+/// `--fail-fast` / `--continue` error policy.
+pub struct BatchRunner<'a> {
+    navigator: Navigator,
+    fail_fast: bool,
+    total: usize,
+    fetched_live: bool,
+    failures: Vec<(&'a str, anyhow::Error)>,
+}
+
+impl<'a> BatchRunner<'a> {
+    pub fn new(config: &AppConfig, total: usize) -> Self {
+        Self {
+            navigator: Navigator::new(config),
+            fail_fast: config.fail_fast,
+            total,
+            fetched_live: false,
+            failures: Vec::new(),
+        }
+    }
+
+    /// Wait out the product delay if the previous item went to iHerb.
+    pub async fn pace(&self) {
+        if self.fetched_live {
+            self.navigator.product_delay().await;
+        }
+    }
+
+    /// Record a processed item. Items served from the cache don't delay the next one.
+    pub fn record_success(&mut self, from_cache: bool) {
+        self.fetched_live = !from_cache;
+    }
+
+    /// Record a failed item. With `--fail-fast` the error is returned to abort the batch.
+    pub fn record_failure(&mut self, item: &'a str, error: anyhow::Error) -> Result<()> {
+        tracing::warn!("{} failed: {:#}", item, error);
+        self.fetched_live = true;
+        if self.fail_fast {
+            return Err(error);
+        }
+        self.failures.push((item, error));
+        Ok(())
+    }
+
+    /// List the failures on stderr after the caller's summary line, and fail with
+    /// `BatchFailed` if there were any.
+    pub fn finish(self) -> Result<()> {
+        if self.failures.is_empty() {
+            return Ok(());
+        }
+        for (item, e) in &self.failures {
+            eprintln!("- {}: {:#}", item, e);
+        }
+        Err(IherbError::BatchFailed {
+            failed: self.failures.len(),
+            total: self.total,
+        }
+        .into())
+    }
+}
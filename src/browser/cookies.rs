@@ -0,0 +1,128 @@
+use chromiumoxide::browser::Browser;
+use chromiumoxide::cdp::browser_protocol::network::{Cookie, CookieParam, TimeSinceEpoch};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// How long cookies without an expiry date (session cookies) are kept between runs.
+const SESSION_COOKIE_TTL: Duration = Duration::from_secs(12 * 60 * 60);
+
+/// Only cookies for these domains are persisted (iHerb and its Cloudflare clearance).
+const COOKIE_DOMAIN: &str = "iherb.com";
+
+#[derive(Serialize, Deserialize)]
+struct StoredCookies {
+    /// Unix time in seconds when the cookies were saved.
+    saved_at: u64,
+    cookies: Vec<CookieParam>,
+}
+
+/// Cookie jar persisted under the data dir, so a `cf_clearance` cookie earned in one
+/// run spares the next run a fresh Cloudflare challenge.
+pub struct CookieJar {
+    path: PathBuf,
+}
+
+impl CookieJar {
+    pub fn new(data_dir: &Path) -> Self {
+        Self {
+            path: data_dir.join("cookies.json"),
+        }
+    }
+
+    /// Load unexpired cookies from disk into the browser.
+    pub async fn restore(&self, browser: &Browser) {
+        let Some(cookies) = self.load() else {
+            return;
+        };
+        let count = cookies.len();
+        match browser.set_cookies(cookies).await {
+            Ok(_) => tracing::debug!("Restored {} cookies from {}", count, self.path.display()),
+            Err(e) => tracing::debug!("Failed to restore cookies: {}", e),
+        }
+    }
+
+    /// Write the browser's iHerb cookies to disk.
+    pub async fn save(&self, browser: &Browser) {
+        let cookies: Vec<CookieParam> = match browser.get_cookies().await {
+            Ok(cookies) => cookies
+                .into_iter()
+                .filter(|c| c.domain.trim_start_matches('.').ends_with(COOKIE_DOMAIN))
+                .map(to_cookie_param)
+                .collect(),
+            Err(e) => {
+                tracing::debug!("Failed to read cookies: {}", e);
+                return;
+            }
+        };
+        if cookies.is_empty() {
+            return;
+        }
+
+        let stored = StoredCookies {
+            saved_at: unix_now(),
+            cookies,
+        };
+        let result = serde_json::to_string_pretty(&stored)
+            .map_err(std::io::Error::from)
+            .and_then(|content| {
+                if let Some(dir) = self.path.parent() {
+                    std::fs::create_dir_all(dir)?;
+                }
+                std::fs::write(&self.path, content)
+            });
+        match result {
+            Ok(()) => tracing::debug!(
+                "Saved {} cookies to {}",
+                stored.cookies.len(),
+                self.path.display()
+            ),
+            Err(e) => tracing::debug!("Failed to save cookies: {}", e),
+        }
+    }
+
+    fn load(&self) -> Option<Vec<CookieParam>> {
+        let content = std::fs::read_to_string(&self.path).ok()?;
+        let stored: StoredCookies = serde_json::from_str(&content)
+            .map_err(|e| tracing::debug!("Ignoring unreadable cookie jar: {}", e))
+            .ok()?;
+
+        let now = unix_now();
+        let session_valid = now.saturating_sub(stored.saved_at) < SESSION_COOKIE_TTL.as_secs();
+        let cookies: Vec<CookieParam> = stored
+            .cookies
+            .into_iter()
+            .filter(|c| match c.expires {
+                Some(ref expires) => *expires.inner() > now as f64,
+                None => session_valid,
+            })
+            .collect();
+        (!cookies.is_empty()).then_some(cookies)
+    }
+}
+
+fn to_cookie_param(cookie: Cookie) -> CookieParam {
+    CookieParam {
+        name: cookie.name,
+        value: cookie.value,
+        url: None,
+        domain: Some(cookie.domain),
+        path: Some(cookie.path),
+        secure: Some(cookie.secure),
+        http_only: Some(cookie.http_only),
+        same_site: cookie.same_site,
+        expires: (!cookie.session).then(|| TimeSinceEpoch::new(cookie.expires)),
+        priority: Some(cookie.priority),
+        same_party: None,
+        source_scheme: Some(cookie.source_scheme),
+        source_port: Some(cookie.source_port),
+        partition_key: cookie.partition_key,
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
@@ -1,19 +1,27 @@
 use crate::error::IherbError;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 const CHROME_VERSIONS_URL: &str =
     "https://googlechromelabs.github.io/chrome-for-testing/last-known-good-versions-with-downloads.json";
 
-pub async fn download_chrome(data_dir: &PathBuf) -> Result<PathBuf, IherbError> {
+pub async fn download_chrome(data_dir: &Path, quiet: bool) -> Result<PathBuf, IherbError> {
+    let progress = |message: &str| {
+        if quiet {
+            tracing::info!("{}", message);
+        } else {
+            eprintln!("{}", message);
+        }
+    };
+
     let chrome_dir = data_dir.join("chrome");
     std::fs::create_dir_all(&chrome_dir)
         .map_err(|e| IherbError::ChromeDownload(format!("Failed to create dir: {}", e)))?;
 
-    eprintln!("Fetching Chrome for Testing download URL...");
+    progress("Fetching Chrome for Testing download URL...");
     let download_url = get_download_url().await?;
 
-    eprintln!("Downloading Chrome for Testing...");
+    progress("Downloading Chrome for Testing...");
     let response = reqwest::get(&download_url)
         .await
         .map_err(|e| IherbError::ChromeDownload(format!("Download failed: {}", e)))?;
@@ -23,7 +31,7 @@ pub async fn download_chrome(data_dir: &PathBuf) -> Result<PathBuf, IherbError>
         .await
         .map_err(|e| IherbError::ChromeDownload(format!("Failed to read response: {}", e)))?;
 
-    eprintln!("Extracting Chrome...");
+    progress("Extracting Chrome...");
     extract_zip(&bytes, &chrome_dir)?;
 
     let binary = super::resolve::downloaded_chrome_path(data_dir);
@@ -46,17 +54,34 @@ pub async fn download_chrome(data_dir: &PathBuf) -> Result<PathBuf, IherbError>
             .map_err(|e| IherbError::ChromeDownload(format!("Failed to set permissions: {}", e)))?;
     }
 
-    eprintln!("Chrome for Testing installed at: {}", binary.display());
+    progress(&format!(
+        "Chrome for Testing installed at: {}",
+        binary.display()
+    ));
     Ok(binary)
 }
 
-async fn get_download_url() -> Result<String, IherbError> {
-    let resp: serde_json::Value = reqwest::get(CHROME_VERSIONS_URL)
+/// Fetch the Chrome for Testing release list.
+async fn fetch_versions() -> Result<serde_json::Value, IherbError> {
+    reqwest::get(CHROME_VERSIONS_URL)
         .await
         .map_err(|e| IherbError::ChromeDownload(format!("Failed to fetch versions: {}", e)))?
         .json()
         .await
-        .map_err(|e| IherbError::ChromeDownload(format!("Failed to parse versions: {}", e)))?;
+        .map_err(|e| IherbError::ChromeDownload(format!("Failed to parse versions: {}", e)))
+}
+
+/// The current Chrome for Testing stable version, e.g. "141.0.7390.54".
+pub async fn latest_stable_version() -> Result<String, IherbError> {
+    let resp = fetch_versions().await?;
+    resp["channels"]["Stable"]["version"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| IherbError::ChromeDownload("No stable version listed".to_string()))
+}
+
+async fn get_download_url() -> Result<String, IherbError> {
+    let resp = fetch_versions().await?;
 
     let platform = get_platform();
 
@@ -76,7 +101,7 @@ async fn get_download_url() -> Result<String, IherbError> {
     Ok(url)
 }
 
-fn get_platform() -> &'static str {
+pub fn get_platform() -> &'static str {
     if cfg!(target_os = "macos") {
         if cfg!(target_arch = "aarch64") {
             "mac-arm64"
@@ -96,7 +121,7 @@ fn get_platform() -> &'static str {
     }
 }
 
-fn extract_zip(data: &[u8], dest: &PathBuf) -> Result<(), IherbError> {
+fn extract_zip(data: &[u8], dest: &Path) -> Result<(), IherbError> {
     let cursor = std::io::Cursor::new(data);
     let mut archive = zip::ZipArchive::new(cursor)
         .map_err(|e| IherbError::ChromeDownload(format!("Failed to open zip: {}", e)))?;
@@ -120,7 +145,7 @@ fn extract_zip(data: &[u8], dest: &PathBuf) -> Result<(), IherbError> {
         let out_path = dest.join(stripped);
 
         // Protect against zip path traversal
-        if !out_path.starts_with(&dest) {
+        if !out_path.starts_with(dest) {
             tracing::warn!("Skipping zip entry with path traversal: {}", name);
             continue;
         }
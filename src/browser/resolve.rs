@@ -1,97 +1,300 @@
+use crate::cli::BrowserFamily;
 use crate::error::IherbError;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How long `<browser> --version` gets to answer before the binary counts as broken.
+const VERSION_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Resolves the Chrome binary path. Priority:
 /// 1. User-configured path (from config)
-/// 2. System-installed Chrome detection
+/// 2. System-installed browser detection, limited to `family` unless it is `Auto`
 /// 3. Previously downloaded Chrome for Testing
-/// 4. Auto-download Chrome for Testing
+/// 4. Auto-download Chrome for Testing, unless `no_download` is set
+///
+/// Each candidate must answer `--version` before it is used; a broken download is
+/// replaced by a fresh one. `quiet` keeps the download progress off stderr.
 pub async fn resolve_chrome(
     user_path: Option<&PathBuf>,
-    data_dir: &PathBuf,
+    data_dir: &Path,
+    family: BrowserFamily,
+    no_download: bool,
+    quiet: bool,
 ) -> Result<PathBuf, IherbError> {
     // 1. User-configured path
     if let Some(path) = user_path {
         if path.exists() {
-            tracing::info!("Using user-configured browser: {}", path.display());
-            return Ok(path.clone());
+            match probe_version(path).await {
+                Ok(version) => {
+                    tracing::info!(
+                        "Using user-configured browser: {} ({})",
+                        path.display(),
+                        version
+                    );
+                    return Ok(path.clone());
+                }
+                Err(e) => tracing::warn!("User-configured browser does not run: {}", e),
+            }
+        } else {
+            tracing::warn!(
+                "User-configured browser path does not exist: {}",
+                path.display()
+            );
         }
-        tracing::warn!(
-            "User-configured browser path does not exist: {}",
-            path.display()
-        );
     }
 
-    // 2. System-installed Chrome
-    if let Some(path) = detect_system_chrome() {
-        tracing::info!("Using system Chrome: {}", path.display());
-        return Ok(path);
+    // 2. System-installed browser
+    if let Some(path) = detect_system_chrome(family) {
+        match probe_version(&path).await {
+            Ok(version) => {
+                tracing::info!("Using system browser: {} ({})", path.display(), version);
+                return Ok(path);
+            }
+            Err(e) => tracing::warn!("System browser does not run: {}", e),
+        }
     }
 
     // 3. Previously downloaded Chrome
     let downloaded = downloaded_chrome_path(data_dir);
     if downloaded.exists() {
-        tracing::info!("Using downloaded Chrome: {}", downloaded.display());
-        return Ok(downloaded);
+        match probe_version(&downloaded).await {
+            Ok(version) => {
+                tracing::info!(
+                    "Using downloaded Chrome: {} ({})",
+                    downloaded.display(),
+                    version
+                );
+                return Ok(downloaded);
+            }
+            Err(e) if !no_download => {
+                tracing::warn!(
+                    "Downloaded Chrome does not run, downloading it again: {}",
+                    e
+                );
+                if let Err(e) = std::fs::remove_dir_all(data_dir.join("chrome")) {
+                    tracing::debug!("Failed to remove the broken Chrome download: {}", e);
+                }
+            }
+            Err(e) => tracing::warn!("Downloaded Chrome does not run: {}", e),
+        }
     }
 
     // 4. Auto-download
+    if no_download {
+        return Err(IherbError::BrowserLaunch(
+            "no browser found and auto-download is disabled (--no-download or no_download in \
+             config.toml). Install Chrome, or point IHERB_BROWSER_PATH or browser_path under \
+             [defaults] in config.toml at a Chromium-based browser"
+                .to_string(),
+        ));
+    }
     tracing::info!("No Chrome found. Downloading Chrome for Testing...");
-    let path = super::download::download_chrome(data_dir).await?;
+    let path = super::download::download_chrome(data_dir, quiet).await?;
+    let version = probe_version(&path)
+        .await
+        .map_err(|e| IherbError::ChromeDownload(e.to_string()))?;
+    tracing::info!("Downloaded {}", version);
     Ok(path)
 }
 
-fn detect_system_chrome() -> Option<PathBuf> {
-    let candidates = if cfg!(target_os = "macos") {
-        vec![
-            "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
-            "/Applications/Chromium.app/Contents/MacOS/Chromium",
-            "/Applications/Google Chrome Canary.app/Contents/MacOS/Google Chrome Canary",
-        ]
+/// Run `<binary> --version` to check that the binary starts on this machine (not
+/// corrupted, right architecture) and return the version it prints, e.g.
+/// "Google Chrome 141.0.7390.54". On Windows, Chrome opens a window instead of
+/// printing its version, so the probe is skipped there.
+pub async fn probe_version(path: &Path) -> Result<String, IherbError> {
+    if cfg!(target_os = "windows") {
+        return Ok("version not checked on Windows".to_string());
+    }
+    let run = tokio::process::Command::new(path)
+        .arg("--version")
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(VERSION_PROBE_TIMEOUT, run).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            return Err(IherbError::BrowserLaunch(format!(
+                "{} could not be started: {}",
+                path.display(),
+                e
+            )))
+        }
+        Err(_) => {
+            return Err(IherbError::BrowserLaunch(format!(
+                "{} did not answer --version within {}s",
+                path.display(),
+                VERSION_PROBE_TIMEOUT.as_secs()
+            )))
+        }
+    };
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || version.is_empty() {
+        return Err(IherbError::BrowserLaunch(format!(
+            "{} --version failed ({}): {}",
+            path.display(),
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(version)
+}
+
+/// Find an installed browser of the given family, or with `Auto` of any family in
+/// the order Chrome, Chromium, Edge, Brave. Each family's usual install locations are
+/// checked before a PATH lookup (`which` on unix, `where` on Windows).
+fn detect_system_chrome(family: BrowserFamily) -> Option<PathBuf> {
+    let families: &[BrowserFamily] = match family {
+        BrowserFamily::Auto => &[
+            BrowserFamily::Chrome,
+            BrowserFamily::Chromium,
+            BrowserFamily::Edge,
+            BrowserFamily::Brave,
+        ],
+        _ => std::slice::from_ref(&family),
+    };
+
+    for &family in families {
+        if let Some(path) = install_paths(family).into_iter().find(|path| path.exists()) {
+            return Some(path);
+        }
+        let lookup = if cfg!(target_os = "windows") {
+            "where"
+        } else {
+            "which"
+        };
+        if let Some(path) = path_names(family)
+            .iter()
+            .find_map(|name| find_on_path(lookup, name))
+        {
+            return Some(path);
+        }
+    }
+
+    None
+}
+
+/// The usual install locations of a browser family on this platform.
+fn install_paths(family: BrowserFamily) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = if cfg!(target_os = "macos") {
+        match family {
+            BrowserFamily::Chrome => vec![
+                "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome".into(),
+                "/Applications/Google Chrome Canary.app/Contents/MacOS/Google Chrome Canary".into(),
+            ],
+            BrowserFamily::Chromium => {
+                vec!["/Applications/Chromium.app/Contents/MacOS/Chromium".into()]
+            }
+            BrowserFamily::Edge => {
+                vec!["/Applications/Microsoft Edge.app/Contents/MacOS/Microsoft Edge".into()]
+            }
+            BrowserFamily::Brave => {
+                vec!["/Applications/Brave Browser.app/Contents/MacOS/Brave Browser".into()]
+            }
+            BrowserFamily::Auto => vec![],
+        }
     } else if cfg!(target_os = "linux") {
-        vec![
-            "/usr/bin/google-chrome",
-            "/usr/bin/google-chrome-stable",
-            "/usr/bin/chromium",
-            "/usr/bin/chromium-browser",
-            "/snap/bin/chromium",
-        ]
+        match family {
+            BrowserFamily::Chrome => vec![
+                "/usr/bin/google-chrome".into(),
+                "/usr/bin/google-chrome-stable".into(),
+            ],
+            BrowserFamily::Chromium => vec![
+                "/usr/bin/chromium".into(),
+                "/usr/bin/chromium-browser".into(),
+                "/snap/bin/chromium".into(),
+            ],
+            BrowserFamily::Edge => vec![
+                "/usr/bin/microsoft-edge".into(),
+                "/usr/bin/microsoft-edge-stable".into(),
+            ],
+            BrowserFamily::Brave => vec![
+                "/usr/bin/brave-browser".into(),
+                "/usr/bin/brave".into(),
+                "/snap/bin/brave".into(),
+            ],
+            BrowserFamily::Auto => vec![],
+        }
     } else if cfg!(target_os = "windows") {
-        vec![
-            r"C:\Program Files\Google\Chrome\Application\chrome.exe",
-            r"C:\Program Files (x86)\Google\Chrome\Application\chrome.exe",
-        ]
+        match family {
+            BrowserFamily::Chrome => vec![
+                r"C:\Program Files\Google\Chrome\Application\chrome.exe".into(),
+                r"C:\Program Files (x86)\Google\Chrome\Application\chrome.exe".into(),
+            ],
+            BrowserFamily::Edge => vec![
+                r"C:\Program Files (x86)\Microsoft\Edge\Application\msedge.exe".into(),
+                r"C:\Program Files\Microsoft\Edge\Application\msedge.exe".into(),
+            ],
+            BrowserFamily::Brave => vec![
+                r"C:\Program Files\BraveSoftware\Brave-Browser\Application\brave.exe".into(),
+                r"C:\Program Files (x86)\BraveSoftware\Brave-Browser\Application\brave.exe".into(),
+            ],
+            BrowserFamily::Chromium | BrowserFamily::Auto => vec![],
+        }
     } else {
         vec![]
     };
 
-    for candidate in candidates {
-        let path = PathBuf::from(candidate);
-        if path.exists() {
-            return Some(path);
+    // Per-user installs on Windows
+    if cfg!(target_os = "windows") {
+        let per_user: &[&str] = match family {
+            BrowserFamily::Chrome => &["Google", "Chrome", "Application", "chrome.exe"],
+            BrowserFamily::Chromium => &["Chromium", "Application", "chrome.exe"],
+            BrowserFamily::Brave => &["BraveSoftware", "Brave-Browser", "Application", "brave.exe"],
+            BrowserFamily::Edge | BrowserFamily::Auto => &[],
+        };
+        if let (Some(local_app_data), false) =
+            (std::env::var_os("LOCALAPPDATA"), per_user.is_empty())
+        {
+            paths.push(
+                per_user
+                    .iter()
+                    .fold(PathBuf::from(local_app_data), |p, c| p.join(c)),
+            );
         }
     }
 
-    // Try `which` on unix
-    #[cfg(unix)]
-    {
-        if let Ok(output) = std::process::Command::new("which")
-            .arg("google-chrome")
-            .output()
-        {
-            if output.status.success() {
-                let path_str = String::from_utf8_lossy(&output.stdout).trim().to_string();
-                if !path_str.is_empty() {
-                    return Some(PathBuf::from(path_str));
-                }
-            }
+    paths
+}
+
+/// Executable names of a browser family to look up on the PATH.
+fn path_names(family: BrowserFamily) -> &'static [&'static str] {
+    if cfg!(target_os = "windows") {
+        match family {
+            BrowserFamily::Chrome => &["chrome"],
+            BrowserFamily::Chromium => &["chromium"],
+            BrowserFamily::Edge => &["msedge"],
+            BrowserFamily::Brave => &["brave"],
+            BrowserFamily::Auto => &[],
+        }
+    } else {
+        match family {
+            BrowserFamily::Chrome => &["google-chrome", "google-chrome-stable"],
+            BrowserFamily::Chromium => &["chromium", "chromium-browser"],
+            BrowserFamily::Edge => &["microsoft-edge", "microsoft-edge-stable"],
+            BrowserFamily::Brave => &["brave-browser", "brave"],
+            BrowserFamily::Auto => &[],
         }
     }
+}
 
-    None
+/// Locate `program` with a PATH lookup command such as `which` or `where`, taking
+/// the first match when it lists several.
+fn find_on_path(lookup: &str, program: &str) -> Option<PathBuf> {
+    let output = std::process::Command::new(lookup)
+        .arg(program)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first = stdout
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())?;
+    Some(PathBuf::from(first))
 }
 
-pub fn downloaded_chrome_path(data_dir: &PathBuf) -> PathBuf {
+pub fn downloaded_chrome_path(data_dir: &Path) -> PathBuf {
     let chrome_dir = data_dir.join("chrome");
     if cfg!(target_os = "macos") {
         chrome_dir
@@ -1,13 +1,44 @@
+use super::cookies::CookieJar;
+use crate::cli::HeadlessMode;
 use crate::config::AppConfig;
 use crate::error::IherbError;
-use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::browser::{self, Browser, BrowserConfig};
+use chromiumoxide::cdp::browser_protocol::network::{Headers, SetExtraHttpHeadersParams};
 use chromiumoxide::Page;
 use futures::StreamExt;
+use rand::seq::SliceRandom;
+use std::ops::Deref;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
-const STEALTH_USER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
+/// Current desktop Chrome user agents, grouped by platform so the UA matches
+/// the OS fingerprint the browser actually exposes.
+const MAC_USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/140.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/139.0.0.0 Safari/537.36",
+];
+const WINDOWS_USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/140.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/139.0.0.0 Safari/537.36",
+];
+const LINUX_USER_AGENTS: &[&str] = &[
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/141.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/140.0.0.0 Safari/537.36",
+    "Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/139.0.0.0 Safari/537.36",
+];
+
+/// Idle tabs kept for reuse when tab reuse is enabled. Fetches run one at a time,
+/// so a single tab covers every request in a run.
+const MAX_IDLE_PAGES: usize = 1;
+
+/// How many times a crashed browser is replaced within one run before giving up.
+const MAX_RELAUNCHES: u32 = 2;
+/// How long the CDP connection gets to answer a liveness check.
+const HEALTH_CHECK_SECS: u64 = 5;
 
 const STEALTH_ARGS: &[&str] = &[
     "--disable-blink-features=AutomationControlled",
@@ -28,21 +59,61 @@ const STEALTH_ARGS: &[&str] = &[
 
 pub struct BrowserSession {
     browser: Arc<Mutex<Browser>>,
-    _handle: tokio::task::JoinHandle<()>,
+    handle: tokio::task::JoinHandle<()>,
+    chrome_path: PathBuf,
+    /// Crashed browsers this session has replaced so far.
+    relaunches: u32,
+    /// Browsers this session has replaced because Cloudflare kept blocking them.
+    cf_restarts: u32,
     user_data_dir: PathBuf,
+    user_agent: String,
+    extra_headers: Vec<(String, String)>,
+    cookie_jar: CookieJar,
+    /// Idle tabs to hand out again, or `None` when every fetch gets a fresh tab.
+    idle_pages: Option<Arc<std::sync::Mutex<Vec<Page>>>>,
+}
+
+/// A browser tab handed out by [`BrowserSession::new_page`]. With tab reuse enabled,
+/// the tab goes back to the session's pool when dropped.
+pub struct PooledPage {
+    page: Option<Page>,
+    pool: Option<Arc<std::sync::Mutex<Vec<Page>>>>,
+}
+
+impl Deref for PooledPage {
+    type Target = Page;
+
+    fn deref(&self) -> &Page {
+        self.page.as_ref().expect("page is present until drop")
+    }
+}
+
+impl Drop for PooledPage {
+    fn drop(&mut self) {
+        if let (Some(page), Some(pool)) = (self.page.take(), self.pool.as_ref()) {
+            if let Ok(mut idle) = pool.lock() {
+                if idle.len() < MAX_IDLE_PAGES {
+                    idle.push(page);
+                }
+            }
+        }
+    }
 }
 
 impl BrowserSession {
     pub async fn launch(chrome_path: PathBuf, config: &AppConfig) -> Result<Self, IherbError> {
         // Create a unique user data directory to avoid SingletonLock conflicts
         // when multiple instances run concurrently or after a stale lock is left behind.
+        // The sequence number keeps browsers launched in the same millisecond apart.
+        static LAUNCHES: AtomicU32 = AtomicU32::new(0);
         let user_data_dir = std::env::temp_dir().join(format!(
-            "iherb-cli-{}-{}",
+            "iherb-cli-{}-{}-{}",
             std::process::id(),
             std::time::SystemTime::now()
                 .duration_since(std::time::UNIX_EPOCH)
                 .unwrap_or_default()
-                .as_millis()
+                .as_millis(),
+            LAUNCHES.fetch_add(1, Ordering::Relaxed)
         ));
         std::fs::create_dir_all(&user_data_dir).map_err(|e| {
             IherbError::BrowserLaunch(format!(
@@ -52,19 +123,33 @@ impl BrowserSession {
             ))
         })?;
 
+        let user_agent = config
+            .user_agent
+            .clone()
+            .unwrap_or_else(|| pick_user_agent().to_string());
+        tracing::debug!("Using user agent: {}", user_agent);
+
         let mut builder = BrowserConfig::builder()
-            .chrome_executable(chrome_path)
+            .chrome_executable(chrome_path.clone())
             .user_data_dir(user_data_dir.clone())
-            .arg(format!("--user-agent={}", STEALTH_USER_AGENT))
+            .arg(format!("--user-agent={}", user_agent))
             .viewport(None);
 
         for arg in STEALTH_ARGS {
             builder = builder.arg(*arg);
         }
 
-        if !config.debug {
-            builder = builder.arg("--headless=new");
-        }
+        builder = match config.headless {
+            HeadlessMode::New => builder.headless_mode(browser::HeadlessMode::New),
+            // chromiumoxide's own "old" mode passes a bare --headless, which newer
+            // Chrome versions treat as the new mode
+            HeadlessMode::Old => {
+                builder
+                    .with_head()
+                    .args(["--headless=old", "--hide-scrollbars", "--mute-audio"])
+            }
+            HeadlessMode::False => builder.with_head(),
+        };
 
         // Chrome refuses to run as root without --no-sandbox
         #[cfg(target_os = "linux")]
@@ -74,11 +159,11 @@ impl BrowserSession {
 
         let browser_config = builder
             .build()
-            .map_err(|e| IherbError::BrowserLaunch(format!("{}", e)))?;
+            .map_err(|e| IherbError::BrowserLaunch(e.to_string()))?;
 
         let (browser, mut handler) = Browser::launch(browser_config)
             .await
-            .map_err(|e| IherbError::BrowserLaunch(format!("{}", e)))?;
+            .map_err(|e| IherbError::BrowserLaunch(e.to_string()))?;
 
         let handle = tokio::spawn(async move {
             while let Some(event) = handler.next().await {
@@ -86,23 +171,142 @@ impl BrowserSession {
             }
         });
 
+        let cookie_jar = CookieJar::new(&config.data_dir);
+        cookie_jar.restore(&browser).await;
+
         Ok(BrowserSession {
             browser: Arc::new(Mutex::new(browser)),
-            _handle: handle,
+            handle,
+            chrome_path,
+            relaunches: 0,
+            cf_restarts: 0,
             user_data_dir,
+            user_agent,
+            extra_headers: config.headers.clone(),
+            cookie_jar,
+            idle_pages: config
+                .reuse_tabs
+                .then(|| Arc::new(std::sync::Mutex::new(Vec::new()))),
         })
     }
 
-    pub async fn new_page(&self) -> Result<Page, IherbError> {
+    /// Whether Chrome is still running and answering CDP commands. The event handler
+    /// task ends when the connection drops, e.g. after Chrome crashed or was OOM-killed.
+    pub async fn is_alive(&self) -> bool {
+        if self.handle.is_finished() {
+            return false;
+        }
+        let browser = self.browser.lock().await;
+        matches!(
+            tokio::time::timeout(
+                std::time::Duration::from_secs(HEALTH_CHECK_SECS),
+                browser.version()
+            )
+            .await,
+            Ok(Ok(_))
+        )
+    }
+
+    /// Replace a dead browser with a freshly launched one, up to `MAX_RELAUNCHES`
+    /// times per run. Cookies come from the jar saved by earlier runs, since the
+    /// crashed browser's cookies can no longer be read.
+    pub async fn relaunch(&mut self, config: &AppConfig) -> Result<(), IherbError> {
+        if self.relaunches >= MAX_RELAUNCHES {
+            return Err(IherbError::BrowserLaunch(format!(
+                "Chrome crashed again after {} relaunches; giving up",
+                self.relaunches
+            )));
+        }
+        self.relaunches += 1;
+        tracing::warn!(
+            "Browser is no longer responding, relaunching ({}/{})",
+            self.relaunches,
+            MAX_RELAUNCHES
+        );
+
+        let mut fresh = Self::launch(self.chrome_path.clone(), config).await?;
+        fresh.relaunches = self.relaunches;
+        fresh.cf_restarts = self.cf_restarts;
+        std::mem::replace(self, fresh).discard();
+        Ok(())
+    }
+
+    /// How many times Cloudflare blocks have made this session restart the browser.
+    pub fn cf_restarts(&self) -> u32 {
+        self.cf_restarts
+    }
+
+    /// Replace a browser that Cloudflare keeps blocking with a fresh one on a new temp
+    /// profile. The blocked browser's cookies are not saved, so the jar keeps the last
+    /// clearance that worked; the fresh browser's cookies are saved on close as usual.
+    pub async fn restart(&mut self, config: &AppConfig) -> Result<(), IherbError> {
+        self.cf_restarts += 1;
+        tracing::warn!(
+            "Cloudflare keeps blocking this browser, restarting with a fresh profile ({}/{})",
+            self.cf_restarts,
+            config.cf_session_retries
+        );
+
+        let mut fresh = Self::launch(self.chrome_path.clone(), config).await?;
+        fresh.relaunches = self.relaunches;
+        fresh.cf_restarts = self.cf_restarts;
+        if let Err(e) = std::mem::replace(self, fresh).shutdown(false).await {
+            tracing::debug!("Failed to close the blocked browser: {}", e);
+        }
+        Ok(())
+    }
+
+    /// Tear down a session whose browser has died, without talking to it.
+    fn discard(self) {
+        self.handle.abort();
+        if let Err(e) = std::fs::remove_dir_all(&self.user_data_dir) {
+            tracing::debug!(
+                "Could not clean up temp dir {}: {}",
+                self.user_data_dir.display(),
+                e
+            );
+        }
+    }
+
+    /// Get a tab for a fetch: an idle pooled tab when tab reuse is enabled, otherwise
+    /// a fresh one.
+    pub async fn new_page(&self) -> Result<PooledPage, IherbError> {
+        let idle = self
+            .idle_pages
+            .as_ref()
+            .and_then(|pool| pool.lock().ok()?.pop());
+        let page = match idle {
+            Some(page) => {
+                tracing::debug!("Reusing pooled browser tab");
+                page
+            }
+            None => self.open_page().await?,
+        };
+        Ok(PooledPage {
+            page: Some(page),
+            pool: self.idle_pages.clone(),
+        })
+    }
+
+    /// Open a new tab with the stealth overrides installed. They are registered to run
+    /// on every new document, so a reused tab keeps them across navigations.
+    async fn open_page(&self) -> Result<Page, IherbError> {
         let browser = self.browser.lock().await;
         let page = browser
             .new_page("about:blank")
             .await
             .map_err(|e| IherbError::BrowserLaunch(format!("Failed to create page: {}", e)))?;
 
-        // Stealth: override navigator.webdriver and other detection vectors
+        // Stealth: override navigator.webdriver and other detection vectors.
+        // navigator.platform must agree with the OS in the user agent string.
+        let _ = page
+            .evaluate_on_new_document(format!(
+                "Object.defineProperty(navigator, 'platform', {{ get: () => '{}' }});",
+                navigator_platform(&self.user_agent)
+            ))
+            .await;
         let _ = page
-            .evaluate(
+            .evaluate_on_new_document(
                 r#"
                 Object.defineProperty(navigator, 'webdriver', { get: () => undefined });
                 Object.defineProperty(navigator, 'languages', { get: () => ['en-US', 'en'] });
@@ -122,11 +326,41 @@ impl BrowserSession {
             )
             .await;
 
+        if !self.extra_headers.is_empty() {
+            let headers: serde_json::Map<String, serde_json::Value> = self
+                .extra_headers
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone().into()))
+                .collect();
+            page.execute(SetExtraHttpHeadersParams::new(Headers::new(headers)))
+                .await
+                .map_err(|e| {
+                    IherbError::BrowserLaunch(format!("Failed to set extra headers: {}", e))
+                })?;
+        }
+
         Ok(page)
     }
 
+    /// Browser product and version, e.g. "HeadlessChrome/141.0.7390.54".
+    pub async fn version(&self) -> Result<String, IherbError> {
+        let browser = self.browser.lock().await;
+        browser
+            .version()
+            .await
+            .map(|v| v.product)
+            .map_err(|e| IherbError::BrowserLaunch(format!("Failed to query version: {}", e)))
+    }
+
     pub async fn close(self) -> Result<(), IherbError> {
+        self.shutdown(true).await
+    }
+
+    async fn shutdown(self, save_cookies: bool) -> Result<(), IherbError> {
         let mut browser = self.browser.lock().await;
+        if save_cookies {
+            self.cookie_jar.save(&browser).await;
+        }
         browser
             .close()
             .await
@@ -167,6 +401,32 @@ impl BrowserSession {
     }
 }
 
+/// Pick a random user agent from the pool matching the current platform.
+pub fn pick_user_agent() -> &'static str {
+    let platform = super::download::get_platform();
+    let pool = if platform.starts_with("mac") {
+        MAC_USER_AGENTS
+    } else if platform.starts_with("win") {
+        WINDOWS_USER_AGENTS
+    } else {
+        LINUX_USER_AGENTS
+    };
+    pool.choose(&mut rand::thread_rng())
+        .copied()
+        .unwrap_or(LINUX_USER_AGENTS[0])
+}
+
+/// The `navigator.platform` value a real browser with this user agent reports.
+fn navigator_platform(user_agent: &str) -> &'static str {
+    if user_agent.contains("Macintosh") {
+        "MacIntel"
+    } else if user_agent.contains("Windows") {
+        "Win32"
+    } else {
+        "Linux x86_64"
+    }
+}
+
 impl Drop for BrowserSession {
     fn drop(&mut self) {
         if self.user_data_dir.exists() {
@@ -1,13 +1,18 @@
 use crate::cli::SortOrder;
 use crate::error::IherbError;
+use crate::model::CacheEntry;
+use crate::scraper::search::{normalize_query, PriceRange};
 use serde::{de::DeserializeOwned, Serialize};
 use sha2::{Digest, Sha256};
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
-use std::time::{Duration, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 
 pub struct Cache {
     dir: PathBuf,
+    shard: usize,
     read_enabled: bool,
+    allow_stale: bool,
 }
 
 /// Result from a cache read, including the data and when it was cached.
@@ -18,12 +23,26 @@ pub struct CacheHit<T> {
 
 const CACHE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60); // 30 days
 
+/// How long a write waits for another writer of the same entry before giving up.
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+/// A lock older than this was left behind by a process that died mid-write.
+const STALE_LOCK_AGE: Duration = Duration::from_secs(30);
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Longest hash prefix `--cache-shard` accepts; 4 hex characters make 65536 subdirectories.
+pub const MAX_SHARD: u8 = 4;
+
 impl Cache {
     /// Create a cache. When `no_cache` is true, reads are skipped but writes still happen.
-    pub fn new(cache_dir: PathBuf, no_cache: bool) -> Self {
+    /// When `allow_stale` is true, entries older than the TTL are still returned.
+    /// With a `shard` above 0, new entries go into subdirectories named by that many
+    /// hex characters of a hash of their file name.
+    pub fn new(cache_dir: PathBuf, no_cache: bool, allow_stale: bool, shard: usize) -> Self {
         Self {
             dir: cache_dir,
+            shard,
             read_enabled: !no_cache,
+            allow_stale,
         }
     }
 
@@ -31,13 +50,16 @@ impl Cache {
         if !self.read_enabled {
             return None;
         }
-        let path = self.dir.join(format!("product_{}.json", product_id));
-        self.read_cached(&path, CACHE_TTL)
+        self.read_cached(&format!("product_{}.json", product_id), CACHE_TTL)
     }
 
-    pub fn set_product<T: Serialize>(&self, product_id: &str, data: &T) -> Result<(), IherbError> {
-        let path = self.dir.join(format!("product_{}.json", product_id));
-        self.write_cached(&path, data)
+    pub async fn set_product<T: Serialize>(
+        &self,
+        product_id: &str,
+        data: &T,
+    ) -> Result<(), IherbError> {
+        self.write_cached(&format!("product_{}.json", product_id), data)
+            .await
     }
 
     pub fn get_search<T: DeserializeOwned>(
@@ -45,49 +67,239 @@ impl Cache {
         query: &str,
         sort: SortOrder,
         category: Option<&str>,
+        price: PriceRange,
+        page: Option<usize>,
+    ) -> Option<CacheHit<T>> {
+        if !self.read_enabled {
+            return None;
+        }
+        let key = self.search_key(query, sort, category, price, page);
+        self.read_cached(&format!("search_{}.json", key), CACHE_TTL)
+    }
+
+    pub async fn set_search<T: Serialize>(
+        &self,
+        query: &str,
+        sort: SortOrder,
+        category: Option<&str>,
+        price: PriceRange,
+        page: Option<usize>,
+        data: &T,
+    ) -> Result<(), IherbError> {
+        let key = self.search_key(query, sort, category, price, page);
+        self.write_cached(&format!("search_{}.json", key), data)
+            .await
+    }
+
+    /// The pages saved so far by an unfinished paginated search.
+    pub fn get_search_partial<T: DeserializeOwned>(
+        &self,
+        query: &str,
+        sort: SortOrder,
+        category: Option<&str>,
+        price: PriceRange,
     ) -> Option<CacheHit<T>> {
         if !self.read_enabled {
             return None;
         }
-        let key = self.search_key(query, sort, category);
-        let path = self.dir.join(format!("search_{}.json", key));
-        self.read_cached(&path, CACHE_TTL)
+        self.read_cached(
+            &self.search_partial_name(query, sort, category, price),
+            CACHE_TTL,
+        )
     }
 
-    pub fn set_search<T: Serialize>(
+    pub async fn set_search_partial<T: Serialize>(
         &self,
         query: &str,
         sort: SortOrder,
         category: Option<&str>,
+        price: PriceRange,
         data: &T,
     ) -> Result<(), IherbError> {
-        let key = self.search_key(query, sort, category);
-        let path = self.dir.join(format!("search_{}.json", key));
-        self.write_cached(&path, data)
+        self.write_cached(
+            &self.search_partial_name(query, sort, category, price),
+            data,
+        )
+        .await
+    }
+
+    /// Drop the saved pages of a search once its full result is cached.
+    pub fn remove_search_partial(
+        &self,
+        query: &str,
+        sort: SortOrder,
+        category: Option<&str>,
+        price: PriceRange,
+    ) {
+        let name = self.search_partial_name(query, sort, category, price);
+        for path in self.layouts(&name) {
+            remove_if_present(&path);
+        }
+    }
+
+    pub fn get_brand<T: DeserializeOwned>(
+        &self,
+        slug: &str,
+        sort: SortOrder,
+    ) -> Option<CacheHit<T>> {
+        if !self.read_enabled {
+            return None;
+        }
+        let key = self.search_key(slug, sort, None, PriceRange::default(), None);
+        self.read_cached(&format!("brand_{}.json", key), CACHE_TTL)
+    }
+
+    pub async fn set_brand<T: Serialize>(
+        &self,
+        slug: &str,
+        sort: SortOrder,
+        data: &T,
+    ) -> Result<(), IherbError> {
+        let key = self.search_key(slug, sort, None, PriceRange::default(), None);
+        self.write_cached(&format!("brand_{}.json", key), data)
+            .await
+    }
+
+    pub fn get_categories<T: DeserializeOwned>(&self) -> Option<CacheHit<T>> {
+        if !self.read_enabled {
+            return None;
+        }
+        self.read_cached("category_tree.json", CACHE_TTL)
+    }
+
+    pub async fn set_categories<T: Serialize>(&self, data: &T) -> Result<(), IherbError> {
+        self.write_cached("category_tree.json", data).await
+    }
+
+    /// Every entry in the cache directory and its shard subdirectories, oldest first.
+    /// Searches and brands are keyed by a hash, so their query is read from the cached
+    /// result.
+    pub fn list(&self) -> Result<Vec<CacheEntry>, IherbError> {
+        let dir = match std::fs::read_dir(&self.dir) {
+            Ok(dir) => dir,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(IherbError::Cache(format!(
+                    "Failed to read cache dir: {}",
+                    e
+                )))
+            }
+        };
+        let mut files = Vec::new();
+        for file in dir.flatten() {
+            let path = file.path();
+            if is_shard_dir(&path) {
+                if let Ok(shard) = std::fs::read_dir(&path) {
+                    files.extend(shard.flatten().map(|f| f.path()));
+                }
+            } else {
+                files.push(path);
+            }
+        }
+        let now = SystemTime::now();
+        let mut entries = Vec::new();
+        for path in files {
+            let Some(stem) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_suffix(".json"))
+            else {
+                continue;
+            };
+            let (kind, key) = match stem.split_once('_') {
+                Some(("product", id)) => ("product", id.to_string()),
+                Some((kind @ ("search" | "brand"), hash)) => (
+                    kind,
+                    cached_query(&path).unwrap_or_else(|| hash.to_string()),
+                ),
+                _ if stem == "category_tree" => ("categories", String::new()),
+                _ => continue,
+            };
+            let Ok(modified) = std::fs::metadata(&path).and_then(|m| m.modified()) else {
+                continue;
+            };
+            let age = now.duration_since(modified).unwrap_or_default();
+            entries.push(CacheEntry {
+                kind: kind.to_string(),
+                key,
+                cached_at: modified
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                fresh: age <= CACHE_TTL,
+            });
+        }
+        entries.sort_by_key(|e| e.cached_at);
+        Ok(entries)
     }
 
-    fn search_key(&self, query: &str, sort: SortOrder, category: Option<&str>) -> String {
+    /// Cache key of a search. A single `--page` or a price range is cached apart from
+    /// the plain paginated results, whose keys are unchanged from before either existed.
+    fn search_key(
+        &self,
+        query: &str,
+        sort: SortOrder,
+        category: Option<&str>,
+        price: PriceRange,
+        page: Option<usize>,
+    ) -> String {
         let mut hasher = Sha256::new();
-        hasher.update(query.as_bytes());
+        hasher.update(normalize_query(query).as_bytes());
         hasher.update(b"\0");
         hasher.update(sort.as_cache_key().as_bytes());
         hasher.update(b"\0");
         if let Some(cat) = category {
             hasher.update(cat.as_bytes());
         }
+        if let Some(range) = price.facet_value() {
+            hasher.update(format!("\0price={}", range).as_bytes());
+        }
+        if let Some(page) = page {
+            hasher.update(format!("\0page={}", page).as_bytes());
+        }
         let result = hasher.finalize();
         hex::encode(&result[..8]) // 16 hex chars
     }
 
-    fn read_cached<T: DeserializeOwned>(&self, path: &Path, ttl: Duration) -> Option<CacheHit<T>> {
-        let metadata = std::fs::metadata(path).ok()?;
+    fn search_partial_name(
+        &self,
+        query: &str,
+        sort: SortOrder,
+        category: Option<&str>,
+        price: PriceRange,
+    ) -> String {
+        let key = self.search_key(query, sort, category, price, None);
+        format!("partial_search_{}.json", key)
+    }
+
+    /// Path of the entry `name` when sharded by a `len`-character prefix.
+    fn sharded_path(&self, name: &str, len: usize) -> PathBuf {
+        match len {
+            0 => self.dir.join(name),
+            len => self.dir.join(shard_prefix(name, len)).join(name),
+        }
+    }
+
+    /// Every place the entry `name` may be: where it is written now first, then the
+    /// flat layout and the other shard lengths, for entries written before a change of
+    /// `--cache-shard`.
+    fn layouts(&self, name: &str) -> Vec<PathBuf> {
+        std::iter::once(self.shard)
+            .chain((0..=MAX_SHARD as usize).filter(|&len| len != self.shard))
+            .map(|len| self.sharded_path(name, len))
+            .collect()
+    }
+
+    fn read_cached<T: DeserializeOwned>(&self, name: &str, ttl: Duration) -> Option<CacheHit<T>> {
+        let path = self.layouts(name).into_iter().find(|p| p.is_file())?;
+        let metadata = std::fs::metadata(&path).ok()?;
         let modified = metadata.modified().ok()?;
         let age = SystemTime::now().duration_since(modified).ok()?;
-        if age > ttl {
+        if age > ttl && !self.allow_stale {
             tracing::debug!("Cache expired for {}", path.display());
             return None;
         }
-        let content = std::fs::read_to_string(path).ok()?;
+        let content = std::fs::read_to_string(&path).ok()?;
         match serde_json::from_str(&content) {
             Ok(data) => {
                 tracing::info!("Cache hit for {}", path.display());
@@ -103,13 +315,160 @@ impl Cache {
         }
     }
 
-    fn write_cached<T: Serialize>(&self, path: &Path, data: &T) -> Result<(), IherbError> {
-        std::fs::create_dir_all(&self.dir)
+    /// Write an entry while holding its lock, through a temporary file renamed into
+    /// place, so concurrent writers don't interleave and readers never see a partial
+    /// file. Copies of the entry in another layout are removed, so an old flat entry
+    /// doesn't outlive its sharded replacement.
+    async fn write_cached<T: Serialize>(&self, name: &str, data: &T) -> Result<(), IherbError> {
+        let layouts = self.layouts(name);
+        let path = layouts[0].as_path();
+        std::fs::create_dir_all(path.parent().unwrap_or(&self.dir))
             .map_err(|e| IherbError::Cache(format!("Failed to create cache dir: {}", e)))?;
         let content = serde_json::to_string_pretty(data)?;
-        std::fs::write(path, content)
-            .map_err(|e| IherbError::Cache(format!("Failed to write cache: {}", e)))?;
+        let _lock = WriteLock::acquire(path).await?;
+        let tmp_path = with_suffix(path, ".tmp");
+        std::fs::write(&tmp_path, content)
+            .and_then(|()| std::fs::rename(&tmp_path, path))
+            .map_err(|e| {
+                let _ = std::fs::remove_file(&tmp_path);
+                IherbError::Cache(format!("Failed to write cache: {}", e))
+            })?;
         tracing::debug!("Cached to {}", path.display());
+        for old in &layouts[1..] {
+            remove_if_present(old);
+        }
         Ok(())
     }
 }
+
+/// An advisory lock on one cache entry: a `<entry>.lock` file created exclusively and
+/// removed on drop. Only writers take it; reads are safe without it since entries are
+/// replaced by rename.
+struct WriteLock {
+    path: PathBuf,
+}
+
+impl WriteLock {
+    /// Take the lock, waiting for another writer to release it without blocking the
+    /// runtime's worker thread.
+    async fn acquire(entry: &Path) -> Result<Self, IherbError> {
+        let path = with_suffix(entry, ".lock");
+        let started = Instant::now();
+        loop {
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Self { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if lock_age(&path).is_some_and(|age| age > STALE_LOCK_AGE) {
+                        tracing::debug!("Removing stale cache lock {}", path.display());
+                        let _ = std::fs::remove_file(&path);
+                        continue;
+                    }
+                    if started.elapsed() > LOCK_TIMEOUT {
+                        return Err(IherbError::Cache(format!(
+                            "Timed out waiting for lock {}",
+                            path.display()
+                        )));
+                    }
+                    tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                }
+                Err(e) => {
+                    return Err(IherbError::Cache(format!(
+                        "Failed to lock {}: {}",
+                        path.display(),
+                        e
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for WriteLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn lock_age(path: &Path) -> Option<Duration> {
+    let modified = std::fs::metadata(path).ok()?.modified().ok()?;
+    SystemTime::now().duration_since(modified).ok()
+}
+
+/// `path` with `suffix` appended to its file name, e.g. `product_1.json.lock`.
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// The first `len` hex characters of a hash of an entry's file name. Product IDs are
+/// largely sequential, so they are hashed to spread entries evenly.
+fn shard_prefix(name: &str, len: usize) -> String {
+    let digest = Sha256::digest(name.as_bytes());
+    let mut prefix = hex::encode(&digest[..len.div_ceil(2)]);
+    prefix.truncate(len);
+    prefix
+}
+
+/// Whether `path` is a shard subdirectory: a directory named by 1 to 4 hex characters.
+fn is_shard_dir(path: &Path) -> bool {
+    path.is_dir()
+        && path.file_name().and_then(|n| n.to_str()).is_some_and(|n| {
+            (1..=MAX_SHARD as usize).contains(&n.len()) && n.bytes().all(|b| b.is_ascii_hexdigit())
+        })
+}
+
+fn remove_if_present(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            tracing::debug!("Failed to remove {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// The `query` field of a cached search or brand listing.
+fn cached_query(path: &Path) -> Option<String> {
+    let content = std::fs::read_to_string(path).ok()?;
+    let value: serde_json::Value = serde_json::from_str(&content).ok()?;
+    value["query"].as_str().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(query: &str) -> String {
+        let cache = Cache::new(std::env::temp_dir(), false, false, 0);
+        cache.search_key(
+            query,
+            SortOrder::Relevance,
+            None,
+            PriceRange::default(),
+            None,
+        )
+    }
+
+    #[test]
+    fn normalize_query_ignores_case_and_spacing() {
+        assert_eq!(normalize_query("  Vitamin\t C \n"), "vitamin c");
+        assert_eq!(normalize_query("FISH   OIL"), "fish oil");
+    }
+
+    #[test]
+    fn case_and_whitespace_variants_share_a_search_key() {
+        let expected = key("vitamin c");
+        assert_eq!(key("Vitamin C"), expected);
+        assert_eq!(key("  vitamin   c "), expected);
+        assert_eq!(key("VITAMIN\tC\n"), expected);
+    }
+
+    #[test]
+    fn different_queries_have_different_search_keys() {
+        assert_ne!(key("vitamin c"), key("vitamin d"));
+        assert_ne!(key("vitamin c"), key("vitaminc"));
+    }
+}
@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(
@@ -14,7 +15,7 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub country: Option<String>,
 
-    /// Fallback currency label when auto-detection fails (e.g., USD, CHF, EUR)
+    /// Fallback currency label when auto-detection fails (e.g., USD, CHF, EUR). Defaults to the storefront's local currency
     #[arg(long, global = true)]
     pub currency: Option<String>,
 
@@ -22,13 +23,199 @@ pub struct Cli {
     #[arg(long, global = true)]
     pub no_cache: bool,
 
+    /// Answer only from the local cache and never launch the browser
+    #[arg(long, global = true, conflicts_with = "no_cache")]
+    pub offline: bool,
+
+    /// In offline mode, also serve cache entries older than the 30-day TTL
+    #[arg(long, global = true, requires = "offline")]
+    pub offline_stale_ok: bool,
+
+    /// Store new cache entries in subdirectories named by the first N hex characters of a hash of the entry (0 keeps them all in one directory; default: 0)
+    #[arg(long, global = true, value_name = "N", value_parser = clap::value_parser!(u8).range(0..=crate::cache::MAX_SHARD as i64))]
+    pub cache_shard: Option<u8>,
+
+    /// Label prices "UNKNOWN" instead of using --currency when the page doesn't show their currency
+    #[arg(long, global = true)]
+    pub strict_currency: bool,
+
     /// Delay between requests in milliseconds (default: 2000)
     #[arg(long, global = true)]
     pub delay: Option<u64>,
 
+    /// Pause in milliseconds between products (and --query-file queries) in batch runs (default: --delay)
+    #[arg(long, global = true, value_name = "MS")]
+    pub product_delay: Option<u64>,
+
+    /// Use the exact --delay between requests instead of randomizing it by ±30%
+    #[arg(long, global = true)]
+    pub no_jitter: bool,
+
+    /// Never send more than this many page requests per minute, across pages and retries
+    #[arg(long, global = true, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_rpm: Option<u32>,
+
+    /// Maximum time in seconds for a single page navigation, including Cloudflare waits (default: 90)
+    #[arg(long, global = true)]
+    pub timeout: Option<u64>,
+
+    /// After Cloudflare blocks every retry, restart the browser with a fresh profile up to this many times per run
+    #[arg(long, global = true, value_name = "N", default_value_t = 1)]
+    pub cf_session_retries: u32,
+
+    /// Retries for a page iHerb answers with an HTTP 5xx server error, waiting 5s, 10s, 20s, ... in between
+    #[arg(long, global = true, value_name = "N", default_value_t = 4)]
+    pub server_error_retries: u32,
+
+    /// When a page counts as loaded: ready (document.readyState is complete) or networkidle (no network requests for 500ms)
+    #[arg(long, global = true, value_enum, default_value_t = WaitStrategy::Ready)]
+    pub wait_strategy: WaitStrategy,
+
+    /// Try this product extraction strategy first: json-ld, js-globals, next-data or dom
+    #[arg(long, global = true, value_enum, value_name = "STRATEGY")]
+    pub prefer_strategy: Option<ExtractStrategy>,
+
+    /// Extract products with only this strategy, for diagnosing which source gives wrong fields
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "STRATEGY",
+        conflicts_with = "prefer_strategy"
+    )]
+    pub only_strategy: Option<ExtractStrategy>,
+
+    /// Build products from structured data alone, skipping the DOM pass, when it has the core fields
+    #[arg(long, global = true, visible_alias = "core-only")]
+    pub no_enrich_dom: bool,
+
+    /// Browser user agent string (default: a current desktop Chrome UA for this platform)
+    #[arg(long, global = true)]
+    pub user_agent: Option<String>,
+
+    /// Extra HTTP request header as 'Name: Value'; repeat for several (e.g. for a proxy or CDN)
+    #[arg(long = "header", global = true, value_name = "NAME: VALUE", value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Override a built-in CSS selector for this run as 'name=css' (e.g. product_name='h1.title'); repeat for several
+    #[arg(
+        long = "set-selector",
+        global = true,
+        value_name = "NAME=CSS",
+        value_parser = crate::scraper::selectors::parse_override,
+        conflicts_with = "offline"
+    )]
+    pub set_selectors: Vec<(String, String)>,
+
+    /// Allow --header to replace the browser's User-Agent or Accept-Language
+    #[arg(long, global = true)]
+    pub override_stealth_headers: bool,
+
+    /// Write the HTML of every fetched page into this directory, regardless of log level
+    #[arg(long, global = true, value_name = "DIR")]
+    pub dump_html: Option<PathBuf>,
+
+    /// When a page fails to load or yields no data, save its URL, status, HTML, a screenshot and the recent log in a folder under this directory
+    #[arg(long, global = true, value_name = "DIR")]
+    pub diagnostics_dir: Option<PathBuf>,
+
+    /// Also append the log to this file as JSON lines, at info level (debug with --debug) whatever stderr shows
+    #[arg(long, global = true, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// Rotate --log-file before it grows past this many megabytes, keeping the last 5 files
+    #[arg(long, global = true, value_name = "MB", requires = "log_file", value_parser = clap::value_parser!(u64).range(1..))]
+    pub log_max_size: Option<u64>,
+
+    /// Reuse one browser tab across fetches instead of opening a new tab for each
+    #[arg(long, global = true)]
+    pub reuse_tabs: bool,
+
+    /// In batch runs (several product IDs, --query-file), stop at the first failure
+    #[arg(long, global = true, conflicts_with = "continue_on_error")]
+    pub fail_fast: bool,
+
+    /// In batch runs, keep going after failures and list them at the end (default)
+    #[arg(long = "continue", global = true)]
+    pub continue_on_error: bool,
+
+    /// Which installed browser to use: auto, chrome, chromium, edge or brave. Chrome for Testing is downloaded only when none is found
+    #[arg(long, global = true, value_enum, default_value_t = BrowserFamily::Auto)]
+    pub browser: BrowserFamily,
+
+    /// Never download Chrome for Testing; fail instead when no installed browser is found
+    #[arg(long, global = true)]
+    pub no_download: bool,
+
     /// Run browser in headed mode for troubleshooting
     #[arg(long, global = true)]
     pub debug: bool,
+
+    /// Chrome's headless mode: new, old or false (a visible window). Overrides the headed window --debug implies (default: new, or false with --debug)
+    #[arg(long, global = true, value_enum, value_name = "MODE")]
+    pub headless: Option<HeadlessMode>,
+
+    /// With --debug, leave the browser open after the command finishes until Enter is pressed, to inspect the page
+    #[arg(long, global = true, requires = "debug")]
+    pub keep_browser_open: bool,
+
+    /// Only print hard errors to stderr: no Chrome download progress, warnings or batch summaries
+    #[arg(long, global = true, conflicts_with = "debug")]
+    pub quiet: bool,
+
+    /// Strip emoji and non-printable characters from product names and brands, for fragile spreadsheet and CSV tools
+    #[arg(long, global = true)]
+    pub sanitize: bool,
+
+    /// In JSON output, add a `provenance` map naming where each product field was read from: json-ld, js-globals, next-data, dom or config-fallback
+    #[arg(long, global = true)]
+    pub explain: bool,
+
+    /// Output format: markdown, json, or json-lines (one JSON object per line, streamed as scraped)
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Markdown)]
+    pub format: OutputFormat,
+
+    /// In the Markdown nutrition table, leave out nutrients with a zero amount or only a footnote mark (e.g. "0g", "†")
+    #[arg(long, global = true)]
+    pub compact_nutrition: bool,
+
+    /// Show Markdown prices as whole numbers: none, nearest, up or down. Prices per serving and JSON stay exact
+    #[arg(long, global = true, value_enum, default_value_t = PriceRounding::None)]
+    pub round_prices: PriceRounding,
+}
+
+/// Check that a search query has something to search for: not blank, and not only
+/// punctuation or symbols.
+pub fn validate_query(query: &str) -> Result<(), String> {
+    if query.trim().is_empty() {
+        return Err("search query cannot be empty".to_string());
+    }
+    if !query.chars().any(char::is_alphanumeric) {
+        return Err(format!(
+            "search query '{}' has no letters or digits to search for",
+            query.trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Parse a search query argument, trimmed, see [`validate_query`].
+fn parse_query(s: &str) -> Result<String, String> {
+    validate_query(s)?;
+    Ok(s.trim().to_string())
+}
+
+/// Parse a `--header` value of the form `Name: Value`.
+fn parse_header(s: &str) -> Result<(String, String), String> {
+    let (name, value) = s
+        .split_once(':')
+        .ok_or_else(|| format!("expected 'Name: Value', got '{}'", s))?;
+    let name = name.trim();
+    let is_token_char = |c: char| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c);
+    if name.is_empty() || !name.chars().all(is_token_char) {
+        return Err(format!("invalid header name '{}'", name));
+    }
+    Ok((name.to_string(), value.trim().to_string()))
 }
 
 #[derive(Subcommand)]
@@ -36,32 +223,208 @@ pub enum Commands {
     /// Search for products on iHerb
     Search {
         /// Search term (e.g., "vitamin c", "omega 3")
-        query: String,
+        #[arg(required_unless_present_any = ["query_file", "similar_to"], conflicts_with = "query_file", value_parser = parse_query)]
+        query: Option<String>,
+
+        /// Run every query in this file (one per line) in a single browser session
+        #[arg(long, value_name = "FILE")]
+        query_file: Option<PathBuf>,
+
+        /// Find alternatives to this product: search its category, best rated first, leaving it out. A search term narrows the results
+        #[arg(long, value_name = "ID_OR_URL", conflicts_with_all = ["query_file", "category", "sort", "replay"])]
+        similar_to: Option<String>,
 
         /// Max number of results to return (default: 20)
         #[arg(long, default_value = "20")]
         limit: usize,
 
-        /// Sort order: relevance, price-asc, price-desc, rating, best-selling
+        /// Fetch only this results page (about 48 products) instead of paginating up to --limit
+        #[arg(long, value_name = "N", conflicts_with = "limit", value_parser = clap::value_parser!(u32).range(1..))]
+        page: Option<u32>,
+
+        /// Sort order: relevance, price-asc, price-desc, rating, best-selling, newest, discount-desc, value (needs --enrich)
         #[arg(long, value_enum, default_value_t = SortOrder::Relevance)]
         sort: SortOrder,
 
-        /// Filter by category (e.g., supplements, vitamins, protein)
+        /// Weights for --sort value, e.g. rating=0.4,reviews=0.2,price=0.4
+        #[arg(long, value_name = "WEIGHTS", value_parser = parse_value_weights, default_value = "rating=0.4,reviews=0.2,price=0.4")]
+        value_weights: ValueWeights,
+
+        /// Filter by category name or numeric id (e.g., supplements, vitamins, 1855)
         #[arg(long)]
         category: Option<String>,
+
+        /// With --format json, print the bare result without the `meta` envelope
+        #[arg(long)]
+        no_envelope: bool,
+
+        /// Parse this saved search page (e.g. from --dump-html) instead of fetching, without a browser
+        #[arg(long, value_name = "FILE", conflicts_with = "query_file")]
+        replay: Option<PathBuf>,
+
+        /// Only keep products with a best-seller or category-rank badge. Further pages are searched to fill --limit
+        #[arg(long)]
+        bestsellers_only: bool,
+
+        /// Only keep products rated at least this (0-5); the adjusted rating with --bayesian-rating. Further pages are searched to fill --limit
+        #[arg(long, value_name = "RATING", value_parser = parse_min_rating)]
+        min_rating: Option<f64>,
+
+        /// Smooth ratings by review count (a Bayesian average) for --min-rating and --sort rating, and show the adjusted rating
+        #[arg(long)]
+        bayesian_rating: bool,
+
+        /// Prior for --bayesian-rating, e.g. mean=4.0,weight=50 (weight counts as that many reviews)
+        #[arg(long, value_name = "PRIOR", value_parser = parse_rating_prior, default_value = "mean=4.0,weight=50")]
+        rating_prior: RatingPrior,
+
+        /// Only keep products priced at least this much (storefront currency)
+        #[arg(long, value_name = "PRICE")]
+        min_price: Option<f64>,
+
+        /// Only keep products priced at most this much (storefront currency)
+        #[arg(long, value_name = "PRICE")]
+        max_price: Option<f64>,
+
+        /// Only keep products with at least this many servings per container (requires --enrich)
+        #[arg(long, value_name = "N", requires = "enrich")]
+        min_servings: Option<u32>,
+
+        /// Only keep products costing at most this much per serving (requires --enrich)
+        #[arg(long, value_name = "PRICE", requires = "enrich")]
+        max_price_per_serving: Option<f64>,
+
+        /// Fetch every result's product page and print full product details instead of summaries; also enables --min-servings, --max-price-per-serving and --sort value (one extra page load per result)
+        #[arg(long)]
+        enrich: bool,
+
+        /// Fetch at most this many product pages for --enrich; later results are dropped unchecked
+        #[arg(long, value_name = "N", requires = "enrich")]
+        enrich_limit: Option<usize>,
     },
 
     /// Get detailed product information
     Product {
-        /// Numeric product ID or full iHerb product URL
-        id_or_url: String,
+        /// One or more numeric product IDs or full iHerb product URLs
+        #[arg(required_unless_present = "search")]
+        id_or_url: Vec<String>,
+
+        /// Look the product up by name instead: search for this and fetch the top result
+        #[arg(long, value_name = "TERM", conflicts_with_all = ["id_or_url", "replay"])]
+        search: Option<String>,
+
+        /// With --search, fetch the Nth result instead of the first
+        #[arg(long, value_name = "N", requires = "search", conflicts_with = "id_or_url", value_parser = clap::value_parser!(u32).range(1..))]
+        pick: Option<u32>,
 
         /// Only show a specific section: overview, description, ingredients, nutrition, suggested-use, warnings, reviews
         #[arg(long, value_enum)]
         section: Option<Section>,
+
+        /// In the nutrition table, only show nutrients whose name contains this (e.g. "vitamin d")
+        #[arg(long, value_name = "NAME")]
+        nutrient: Option<String>,
+
+        /// Write each product to its own file in this directory instead of stdout
+        #[arg(long, value_name = "DIR")]
+        output_dir: Option<PathBuf>,
+
+        /// Skip products with fewer than this many servings per container
+        #[arg(long, value_name = "N")]
+        min_servings: Option<u32>,
+
+        /// Also load the product's customer Q&A (one extra page load per product)
+        #[arg(long, conflicts_with = "replay")]
+        include_qa: bool,
+
+        /// Give up on a product whose fetch takes longer than this, and move on to the next
+        #[arg(long, value_name = "SECS", conflicts_with = "replay", value_parser = clap::value_parser!(u64).range(1..))]
+        timeout_per_product: Option<u64>,
+
+        /// Parse this saved product page (e.g. from --dump-html) instead of fetching, without a browser
+        #[arg(long, value_name = "FILE")]
+        replay: Option<PathBuf>,
+    },
+
+    /// List products from a specific brand
+    Brand {
+        /// Brand name or iHerb brand slug (e.g., "NOW Foods", "california-gold-nutrition")
+        name: String,
+
+        /// Max number of results to return (default: 20)
+        #[arg(long, default_value = "20")]
+        limit: usize,
+
+        /// Sort order: relevance, price-asc, price-desc, rating, best-selling, newest, discount-desc
+        #[arg(long, value_enum, default_value_t = SortOrder::Relevance)]
+        sort: SortOrder,
+    },
+
+    /// List iHerb categories with the ids accepted by `--category`
+    Categories {
+        /// Only show the subcategories of this category id
+        #[arg(long)]
+        parent: Option<String>,
+    },
+
+    /// Check that Chrome can be found and launched and that iHerb is reachable
+    Doctor,
+
+    /// List what is in the cache: products, searches and brands, with their age
+    ListCache,
+
+    /// Print the iherb-cli version and platform
+    Version {
+        /// Also report the browser in use and whether a newer Chrome for Testing is out, for bug reports
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// One Markdown brief on a product: overview, supplement facts, review breakdown and related products
+    ///
+    /// Top reviews are not included, since review text is not scraped; the JSON
+    /// output has `top_reviews: null` with a note saying so.
+    Summary {
+        /// Numeric product ID or full iHerb product URL
+        id_or_url: String,
+
+        /// How many related products to list; 0 skips the extra search
+        #[arg(long, value_name = "N", default_value_t = 5)]
+        related: usize,
+    },
+
+    /// Compare products side by side
+    Compare {
+        /// Two or more numeric product IDs or full iHerb product URLs (one with --countries)
+        #[arg(required = true, num_args = 1..)]
+        ids: Vec<String>,
+
+        /// Leave out products with fewer than this many servings per container
+        #[arg(long, value_name = "N")]
+        min_servings: Option<u32>,
+
+        /// Only compare this nutrient (e.g. "vitamin d"), one row per product
+        #[arg(long, value_name = "NAME")]
+        nutrient: Option<String>,
+
+        /// Compare one product across these storefronts (e.g. us,de,ch), each fetched in its own browser
+        #[arg(long, value_name = "CODES", value_delimiter = ',', conflicts_with_all = ["min_servings", "nutrient"])]
+        countries: Vec<String>,
+
+        /// With --countries, how many browsers may run at once
+        #[arg(long, value_name = "N", default_value_t = 2, value_parser = clap::value_parser!(u32).range(1..=8))]
+        max_concurrent_browsers: u32,
     },
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    Markdown,
+    Json,
+    #[value(name = "json-lines")]
+    JsonLines,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum SortOrder {
     Relevance,
@@ -72,6 +435,11 @@ pub enum SortOrder {
     Rating,
     #[value(name = "best-selling")]
     BestSelling,
+    Newest,
+    #[value(name = "discount-desc")]
+    DiscountDesc,
+    /// Client-side "best value" score from rating, review count and price per serving.
+    Value,
 }
 
 impl SortOrder {
@@ -82,6 +450,10 @@ impl SortOrder {
             SortOrder::PriceDesc => "&sr=3",
             SortOrder::Rating => "&sr=1",
             SortOrder::BestSelling => "&sr=2",
+            SortOrder::Newest => "&sr=5",
+            SortOrder::DiscountDesc => "&sr=6",
+            // Scored locally from relevance-ordered results
+            SortOrder::Value => "",
         }
     }
 
@@ -92,10 +464,196 @@ impl SortOrder {
             SortOrder::PriceDesc => "price-desc",
             SortOrder::Rating => "rating",
             SortOrder::BestSelling => "best-selling",
+            SortOrder::Newest => "newest",
+            SortOrder::DiscountDesc => "discount-desc",
+            SortOrder::Value => "value",
+        }
+    }
+}
+
+/// Weights of the `--sort value` score components.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ValueWeights {
+    pub rating: f64,
+    pub reviews: f64,
+    pub price: f64,
+}
+
+impl Default for ValueWeights {
+    fn default() -> Self {
+        Self {
+            rating: 0.4,
+            reviews: 0.2,
+            price: 0.4,
         }
     }
 }
 
+/// Parse a `--value-weights` value such as `rating=0.4,reviews=0.2,price=0.4`.
+/// Components left out keep their default weight.
+fn parse_value_weights(s: &str) -> Result<ValueWeights, String> {
+    let mut weights = ValueWeights::default();
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("expected 'name=weight', got '{}'", part))?;
+        let value: f64 = value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+            .ok_or_else(|| format!("invalid weight '{}' for {}", value.trim(), key.trim()))?;
+        match key.trim() {
+            "rating" => weights.rating = value,
+            "reviews" => weights.reviews = value,
+            "price" => weights.price = value,
+            other => {
+                return Err(format!(
+                    "unknown weight '{}' (expected rating, reviews or price)",
+                    other
+                ))
+            }
+        }
+    }
+    Ok(weights)
+}
+
+/// The prior of `--bayesian-rating`: the rating a product is assumed to have, and
+/// how many reviews that assumption counts as.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RatingPrior {
+    pub mean: f64,
+    pub weight: f64,
+}
+
+/// Parse a `--rating-prior` value such as `mean=4.0,weight=50`. Parts left out keep
+/// their default.
+fn parse_rating_prior(s: &str) -> Result<RatingPrior, String> {
+    let mut prior = RatingPrior {
+        mean: 4.0,
+        weight: 50.0,
+    };
+    for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (key, value) = part
+            .split_once('=')
+            .ok_or_else(|| format!("expected 'name=value', got '{}'", part))?;
+        let value: f64 = value
+            .trim()
+            .parse()
+            .ok()
+            .filter(|v: &f64| v.is_finite() && *v >= 0.0)
+            .ok_or_else(|| format!("invalid value '{}' for {}", value.trim(), key.trim()))?;
+        match key.trim() {
+            "mean" if value <= 5.0 => prior.mean = value,
+            "mean" => return Err(format!("mean must be at most 5, got {}", value)),
+            "weight" => prior.weight = value,
+            other => {
+                return Err(format!(
+                    "unknown part '{}' (expected mean or weight)",
+                    other
+                ))
+            }
+        }
+    }
+    Ok(prior)
+}
+
+/// Parse a star rating between 0 and 5, for `--min-rating`.
+fn parse_min_rating(s: &str) -> Result<f64, String> {
+    s.trim()
+        .parse()
+        .ok()
+        .filter(|r: &f64| (0.0..=5.0).contains(r))
+        .ok_or_else(|| format!("expected a rating between 0 and 5, got '{}'", s))
+}
+
+/// How `--round-prices` rounds displayed prices to whole numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PriceRounding {
+    None,
+    Nearest,
+    Up,
+    Down,
+}
+
+impl PriceRounding {
+    pub fn apply(self, price: f64) -> f64 {
+        match self {
+            PriceRounding::None => price,
+            PriceRounding::Nearest => price.round(),
+            PriceRounding::Up => price.ceil(),
+            PriceRounding::Down => price.floor(),
+        }
+    }
+}
+
+/// How Chrome is run, for `--headless`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum HeadlessMode {
+    /// Chrome's current headless mode, which renders like a regular window.
+    New,
+    /// The legacy headless implementation. Chrome 132 and later only ship it as the
+    /// separate `chrome-headless-shell` binary.
+    Old,
+    /// A visible browser window.
+    False,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WaitStrategy {
+    Ready,
+    #[value(name = "networkidle")]
+    NetworkIdle,
+}
+
+/// Sources product details can be extracted from, in their default order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExtractStrategy {
+    /// The schema.org `<script type="application/ld+json">` block.
+    JsonLd,
+    /// Product globals set by iHerb's scripts.
+    JsGlobals,
+    /// The Next.js `__NEXT_DATA__` payload.
+    NextData,
+    /// Scraping the rendered page.
+    Dom,
+}
+
+impl ExtractStrategy {
+    pub const ALL: &[ExtractStrategy] = &[
+        ExtractStrategy::JsonLd,
+        ExtractStrategy::JsGlobals,
+        ExtractStrategy::NextData,
+        ExtractStrategy::Dom,
+    ];
+
+    /// Attempt order for `--prefer-strategy` (moved to the front) or `--only-strategy`.
+    pub fn order(prefer: Option<Self>, only: Option<Self>) -> Vec<Self> {
+        if let Some(only) = only {
+            return vec![only];
+        }
+        let mut order: Vec<Self> = prefer.into_iter().collect();
+        order.extend(Self::ALL.iter().filter(|&&s| Some(s) != prefer));
+        order
+    }
+
+    /// Whether the strategy works on saved HTML, without a live page.
+    pub fn reads_html(self) -> bool {
+        matches!(self, ExtractStrategy::JsonLd | ExtractStrategy::Dom)
+    }
+}
+
+/// Chromium-based browser families `--browser` can pick from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum BrowserFamily {
+    /// The first one found of Chrome, Chromium, Edge and Brave.
+    Auto,
+    Chrome,
+    Chromium,
+    Edge,
+    Brave,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
 pub enum Section {
     Overview,
@@ -1,19 +1,105 @@
+use crate::cli::{
+    BrowserFamily, ExtractStrategy, HeadlessMode, OutputFormat, PriceRounding, WaitStrategy,
+};
 use crate::error::IherbError;
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     pub country: String,
     pub currency: String,
+    pub strict_currency: bool,
     pub no_cache: bool,
+    pub offline: bool,
+    pub offline_stale_ok: bool,
+    /// Length of the hash prefix naming a cache entry's subdirectory; 0 for none.
+    pub cache_shard: usize,
     pub delay_ms: u64,
-    pub debug: bool,
+    /// Pause between distinct products or queries in a batch.
+    pub product_delay_ms: u64,
+    pub jitter: bool,
+    pub fail_fast: bool,
+    pub max_rpm: Option<u32>,
+    pub timeout_secs: u64,
+    /// Fresh browsers to try when Cloudflare keeps blocking one.
+    pub cf_session_retries: u32,
+    /// Extra, slower retries for pages answered with an HTTP 5xx.
+    pub server_error_retries: u32,
+    pub wait_strategy: WaitStrategy,
+    /// Product extraction strategies to try, in order.
+    pub extract_order: Vec<ExtractStrategy>,
+    /// Fill in structured-data products from the DOM; off with `--no-enrich-dom`.
+    pub enrich_dom: bool,
+    pub headless: HeadlessMode,
+    /// Keep stderr to hard errors.
+    pub quiet: bool,
+    pub format: OutputFormat,
+    /// Strip emoji and non-printable characters from names and brands in the output.
+    pub sanitize: bool,
+    /// Include each product field's source in JSON output.
+    pub explain: bool,
+    pub compact_nutrition: bool,
+    /// Rounding of prices in Markdown output.
+    pub round_prices: PriceRounding,
+    pub user_agent: Option<String>,
+    /// Extra request headers sent with every page load.
+    pub headers: Vec<(String, String)>,
+    /// `--set-selector` overrides of built-in CSS selectors, by selector name.
+    pub selector_overrides: Vec<(String, String)>,
+    pub dump_html_dir: Option<PathBuf>,
+    /// Where to write a diagnostics bundle for each failed page.
+    pub diagnostics_dir: Option<PathBuf>,
+    pub reuse_tabs: bool,
     pub browser_path: Option<PathBuf>,
+    pub browser: BrowserFamily,
+    /// Fail instead of downloading Chrome for Testing when no browser is installed.
+    pub no_download: bool,
     pub cache_dir: PathBuf,
     pub data_dir: PathBuf,
 }
 
+/// Settings given on the command line. These take priority over env vars and the config file.
+#[derive(Debug, Clone)]
+pub struct CliOverrides {
+    pub country: Option<String>,
+    pub currency: Option<String>,
+    pub strict_currency: bool,
+    pub no_cache: bool,
+    pub offline: bool,
+    pub offline_stale_ok: bool,
+    pub cache_shard: Option<u8>,
+    pub delay: Option<u64>,
+    pub product_delay: Option<u64>,
+    pub no_jitter: bool,
+    pub fail_fast: bool,
+    pub max_rpm: Option<u32>,
+    pub timeout: Option<u64>,
+    pub cf_session_retries: u32,
+    pub server_error_retries: u32,
+    pub wait_strategy: WaitStrategy,
+    pub prefer_strategy: Option<ExtractStrategy>,
+    pub only_strategy: Option<ExtractStrategy>,
+    pub no_enrich_dom: bool,
+    pub debug: bool,
+    pub headless: Option<HeadlessMode>,
+    pub quiet: bool,
+    pub format: OutputFormat,
+    pub sanitize: bool,
+    pub explain: bool,
+    pub compact_nutrition: bool,
+    pub round_prices: PriceRounding,
+    pub user_agent: Option<String>,
+    pub headers: Vec<(String, String)>,
+    pub set_selectors: Vec<(String, String)>,
+    pub override_stealth_headers: bool,
+    pub dump_html: Option<PathBuf>,
+    pub diagnostics_dir: Option<PathBuf>,
+    pub reuse_tabs: bool,
+    pub browser: BrowserFamily,
+    pub no_download: bool,
+}
+
 #[derive(Debug, Deserialize, Default)]
 struct ConfigFile {
     #[serde(default)]
@@ -25,17 +111,17 @@ struct ConfigDefaults {
     country: Option<String>,
     currency: Option<String>,
     browser_path: Option<String>,
+    no_download: Option<bool>,
     delay_ms: Option<u64>,
+    product_delay_ms: Option<u64>,
+    cache_shard: Option<u8>,
+    max_rpm: Option<u32>,
+    timeout_secs: Option<u64>,
+    user_agent: Option<String>,
 }
 
 impl AppConfig {
-    pub fn load(
-        country: Option<String>,
-        currency: Option<String>,
-        no_cache: bool,
-        delay: Option<u64>,
-        debug: bool,
-    ) -> Result<Self, IherbError> {
+    pub fn load(cli: CliOverrides) -> Result<Self, IherbError> {
         let config_dir = dirs::config_dir()
             .unwrap_or_else(|| PathBuf::from("."))
             .join("iherb-cli");
@@ -57,32 +143,105 @@ impl AppConfig {
             .or(file_config.defaults.browser_path)
             .map(PathBuf::from);
 
-        let country = country
+        let country = cli
+            .country
             .or(country_env)
             .or(file_config.defaults.country)
             .unwrap_or_else(|| "us".to_string());
 
-        let currency = currency
+        let currency = cli
+            .currency
             .or(currency_env)
             .or(file_config.defaults.currency)
-            .unwrap_or_else(|| "USD".to_string());
+            .unwrap_or_else(|| default_currency(&country).to_string());
 
-        let delay_ms = delay.or(file_config.defaults.delay_ms).unwrap_or(2000);
+        let delay_ms = cli.delay.or(file_config.defaults.delay_ms).unwrap_or(2000);
+        let product_delay_ms = cli
+            .product_delay
+            .or(file_config.defaults.product_delay_ms)
+            .unwrap_or(delay_ms);
+        let cache_shard = cli
+            .cache_shard
+            .or(file_config.defaults.cache_shard)
+            .unwrap_or(0)
+            .min(crate::cache::MAX_SHARD) as usize;
+        let max_rpm = cli
+            .max_rpm
+            .or(file_config.defaults.max_rpm)
+            .filter(|&rpm| rpm > 0);
+        let timeout_secs = cli
+            .timeout
+            .or(file_config.defaults.timeout_secs)
+            .unwrap_or(90);
+        let user_agent = cli.user_agent.or(file_config.defaults.user_agent);
 
         Self::validate_country(&country)?;
+        if !cli.override_stealth_headers {
+            Self::validate_headers(&cli.headers)?;
+        }
 
         Ok(AppConfig {
             country,
             currency,
-            no_cache,
+            strict_currency: cli.strict_currency,
+            // Results from experimental selectors shouldn't be stored, nor hidden by the cache
+            no_cache: cli.no_cache || !cli.set_selectors.is_empty(),
+            offline: cli.offline,
+            offline_stale_ok: cli.offline_stale_ok,
+            cache_shard,
             delay_ms,
-            debug,
+            product_delay_ms,
+            jitter: !cli.no_jitter,
+            fail_fast: cli.fail_fast,
+            max_rpm,
+            timeout_secs,
+            cf_session_retries: cli.cf_session_retries,
+            server_error_retries: cli.server_error_retries,
+            wait_strategy: cli.wait_strategy,
+            extract_order: ExtractStrategy::order(cli.prefer_strategy, cli.only_strategy),
+            enrich_dom: !cli.no_enrich_dom,
+            // --debug is for watching the browser, unless a mode was asked for
+            headless: cli.headless.unwrap_or(if cli.debug {
+                HeadlessMode::False
+            } else {
+                HeadlessMode::New
+            }),
+            quiet: cli.quiet,
+            format: cli.format,
+            sanitize: cli.sanitize,
+            explain: cli.explain,
+            compact_nutrition: cli.compact_nutrition,
+            round_prices: cli.round_prices,
+            user_agent,
+            headers: cli.headers,
+            selector_overrides: cli.set_selectors,
+            dump_html_dir: cli.dump_html,
+            diagnostics_dir: cli.diagnostics_dir,
+            reuse_tabs: cli.reuse_tabs,
             browser_path,
+            browser: cli.browser,
+            no_download: cli.no_download || file_config.defaults.no_download.unwrap_or(false),
             cache_dir,
             data_dir,
         })
     }
 
+    /// Reject headers the browser already sets to look like a regular visitor, since
+    /// overriding them makes the fingerprint inconsistent.
+    fn validate_headers(headers: &[(String, String)]) -> Result<(), IherbError> {
+        const STEALTH_HEADERS: &[&str] = &["user-agent", "accept-language"];
+        match headers
+            .iter()
+            .find(|(name, _)| STEALTH_HEADERS.contains(&name.to_ascii_lowercase().as_str()))
+        {
+            Some((name, _)) => Err(IherbError::InvalidHeader(format!(
+                "--header {} would override the browser's own {} header; use --user-agent instead, or pass --override-stealth-headers",
+                name, name
+            ))),
+            None => Ok(()),
+        }
+    }
+
     pub fn validate_country(country: &str) -> Result<(), IherbError> {
         const KNOWN_COUNTRIES: &[&str] = &[
             "us", "ca", "au", "nz", "sg", "hk", "tw", "kr", "jp",
@@ -103,6 +262,30 @@ impl AppConfig {
         Ok(())
     }
 
+    /// Currency label for prices whose currency isn't shown on the page: "UNKNOWN"
+    /// with `--strict-currency`, otherwise the configured currency.
+    pub fn fallback_currency(&self) -> &str {
+        if self.strict_currency {
+            "UNKNOWN"
+        } else {
+            &self.currency
+        }
+    }
+
+    /// This configuration pointed at another storefront, for fetching the same product
+    /// from several countries. A currency that was only the old storefront's default
+    /// follows the storefront. The product cache isn't keyed by storefront, so reads
+    /// from it are turned off.
+    pub fn for_country(&self, country: &str) -> AppConfig {
+        let mut config = self.clone();
+        if self.currency == default_currency(&self.country) {
+            config.currency = default_currency(country).to_string();
+        }
+        config.country = country.to_string();
+        config.no_cache = true;
+        config
+    }
+
     pub fn base_url(&self) -> String {
         if self.country == "us" {
             "https://www.iherb.com".to_string()
@@ -112,7 +295,52 @@ impl AppConfig {
     }
 }
 
-fn load_config_file(config_dir: &PathBuf) -> ConfigFile {
+/// The local currency of a storefront, used when no `--currency` is configured.
+/// Unknown storefronts fall back to USD.
+fn default_currency(country: &str) -> &'static str {
+    match country {
+        "ca" => "CAD",
+        "au" => "AUD",
+        "nz" => "NZD",
+        "sg" => "SGD",
+        "hk" => "HKD",
+        "tw" => "TWD",
+        "kr" => "KRW",
+        "jp" => "JPY",
+        "sa" => "SAR",
+        "ae" => "AED",
+        "kw" => "KWD",
+        "il" => "ILS",
+        "de" | "fr" | "es" | "it" | "nl" | "be" | "at" | "fi" | "ie" | "pt" | "gr" => "EUR",
+        "ch" => "CHF",
+        "se" => "SEK",
+        "no" => "NOK",
+        "dk" => "DKK",
+        "pl" => "PLN",
+        "cz" => "CZK",
+        "ru" => "RUB",
+        "tr" => "TRY",
+        "in" => "INR",
+        "th" => "THB",
+        "my" => "MYR",
+        "ph" => "PHP",
+        "id" => "IDR",
+        "vn" => "VND",
+        "br" => "BRL",
+        "mx" => "MXN",
+        "cl" => "CLP",
+        "co" => "COP",
+        "ar" => "ARS",
+        "za" => "ZAR",
+        "eg" => "EGP",
+        "ng" => "NGN",
+        "ke" => "KES",
+        "cn" => "CNY",
+        _ => "USD",
+    }
+}
+
+fn load_config_file(config_dir: &Path) -> ConfigFile {
     let config_path = config_dir.join("config.toml");
     if config_path.exists() {
         match std::fs::read_to_string(&config_path) {
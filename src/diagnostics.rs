@@ -0,0 +1,121 @@
+use chromiumoxide::page::ScreenshotParams;
+use chromiumoxide::Page;
+use std::collections::VecDeque;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::Subscriber;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Log lines kept for a diagnostics bundle.
+const TAIL_LINES: usize = 300;
+
+/// The most recent debug-level log lines, oldest first.
+static TAIL: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+
+/// A logging layer that records debug-level lines for `write_bundle`, whatever
+/// level stderr is logging at.
+pub fn log_tail_layer<S>() -> impl Layer<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    tracing_subscriber::fmt::layer()
+        .with_writer(LogTail)
+        .with_ansi(false)
+        .with_target(false)
+        .with_filter(EnvFilter::new("iherb_cli=debug"))
+}
+
+struct LogTail;
+
+impl<'a> MakeWriter<'a> for LogTail {
+    type Writer = LogTailWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogTailWriter(Vec::new())
+    }
+}
+
+/// Collects one formatted event and adds its lines to the tail when dropped.
+struct LogTailWriter(Vec<u8>);
+
+impl Write for LogTailWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogTailWriter {
+    fn drop(&mut self) {
+        let Ok(mut tail) = TAIL.lock() else {
+            return;
+        };
+        for line in String::from_utf8_lossy(&self.0).lines() {
+            if tail.len() == TAIL_LINES {
+                tail.pop_front();
+            }
+            tail.push_back(line.to_string());
+        }
+    }
+}
+
+/// Write what is known about a failed page load into a new folder under `dir`: the
+/// error with the page's final URL and HTTP status, its HTML, a full-page
+/// screenshot, and the recent log. The page parts are best effort, as the page may
+/// be in any state. Returns the folder.
+pub async fn write_bundle(
+    dir: &Path,
+    label: &str,
+    page: &Page,
+    status: Option<u16>,
+    error: &anyhow::Error,
+) -> std::io::Result<PathBuf> {
+    let stamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let bundle = dir.join(format!(
+        "{}-{}",
+        crate::scraper::helpers::file_safe_label(label),
+        stamp
+    ));
+    std::fs::create_dir_all(&bundle)?;
+
+    let url = page.url().await.ok().flatten();
+    let status = status.map_or_else(|| "unknown".to_string(), |s| s.to_string());
+    let report = format!(
+        "error: {:#}\nurl: {}\nstatus: {}\n",
+        error,
+        url.as_deref().unwrap_or("unknown"),
+        status
+    );
+    std::fs::write(bundle.join("error.txt"), report)?;
+
+    match page.content().await {
+        Ok(html) => std::fs::write(bundle.join("page.html"), html)?,
+        Err(e) => tracing::debug!("No HTML for the diagnostics bundle: {}", e),
+    }
+    let screenshot = ScreenshotParams::builder().full_page(true).build();
+    match page.screenshot(screenshot).await {
+        Ok(png) => std::fs::write(bundle.join("screenshot.png"), png)?,
+        Err(e) => tracing::debug!("No screenshot for the diagnostics bundle: {}", e),
+    }
+
+    let log = match TAIL.lock() {
+        Ok(tail) => tail.iter().fold(String::new(), |mut log, line| {
+            log.push_str(line);
+            log.push('\n');
+            log
+        }),
+        Err(_) => String::new(),
+    };
+    std::fs::write(bundle.join("log.txt"), log)?;
+    Ok(bundle)
+}
@@ -1,5 +1,28 @@
+use serde_json::json;
 use thiserror::Error;
 
+/// Process exit codes reported for failures.
+///
+/// | Code | Meaning |
+/// |---|---|
+/// | 0 | Success |
+/// | 1 | Any other error |
+/// | 2 | Invalid command-line usage (reported by clap) |
+/// | 3 | Product not found or discontinued |
+/// | 4 | Blocked by Cloudflare |
+/// | 5 | Browser could not be resolved, downloaded, or launched |
+/// | 6 | Navigation failed, or iHerb kept answering with a server error |
+/// | 7 | Some items in a multi-item run failed |
+/// | 8 | No cached data available in offline mode |
+/// | 130 | Interrupted (Ctrl+C) |
+pub const EXIT_GENERIC: u8 = 1;
+pub const EXIT_NOT_FOUND: u8 = 3;
+pub const EXIT_CLOUDFLARE: u8 = 4;
+pub const EXIT_BROWSER: u8 = 5;
+pub const EXIT_NAVIGATION: u8 = 6;
+pub const EXIT_BATCH: u8 = 7;
+pub const EXIT_OFFLINE: u8 = 8;
+
 #[derive(Error, Debug)]
 pub enum IherbError {
     #[error("Failed to launch browser: {0}")]
@@ -8,15 +31,36 @@ pub enum IherbError {
     #[error("Browser navigation failed: {0}")]
     Navigation(String),
 
+    #[error("iHerb returned a server error (HTTP {0})")]
+    ServerError(u16),
+
     #[error("Cloudflare challenge could not be solved after {0} attempts")]
     CloudflareBlocked(u32),
 
     #[error("Product not found: {0}")]
     ProductNotFound(String),
 
+    #[error("Product {id} is no longer available{}", alternatives_hint(.alternatives))]
+    Discontinued {
+        id: String,
+        alternatives: Vec<String>,
+    },
+
     #[error("Chrome download failed: {0}")]
     ChromeDownload(String),
 
+    #[error("{failed} of {total} items failed")]
+    BatchFailed { failed: usize, total: usize },
+
+    #[error("No cached data for {0}, and offline mode is enabled")]
+    Offline(String),
+
+    #[error("{0}")]
+    InvalidCategory(String),
+
+    #[error("{0}")]
+    InvalidHeader(String),
+
     #[error("Cache error: {0}")]
     Cache(String),
 
@@ -29,3 +73,83 @@ pub enum IherbError {
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
 }
+
+impl IherbError {
+    /// Exit code for this error, see the table at the top of this module.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            IherbError::ProductNotFound(_) | IherbError::Discontinued { .. } => EXIT_NOT_FOUND,
+            IherbError::CloudflareBlocked(_) => EXIT_CLOUDFLARE,
+            IherbError::BrowserLaunch(_) | IherbError::ChromeDownload(_) => EXIT_BROWSER,
+            IherbError::Navigation(_) | IherbError::ServerError(_) => EXIT_NAVIGATION,
+            IherbError::BatchFailed { .. } => EXIT_BATCH,
+            IherbError::Offline(_) => EXIT_OFFLINE,
+            _ => EXIT_GENERIC,
+        }
+    }
+
+    /// The HTTP status this error was caused by, if any.
+    pub fn http_status(&self) -> Option<u16> {
+        match self {
+            IherbError::ServerError(status) => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Stable machine-readable identifier for this error kind.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            IherbError::BrowserLaunch(_) => "browser_launch",
+            IherbError::Navigation(_) => "navigation",
+            IherbError::ServerError(_) => "server_error",
+            IherbError::CloudflareBlocked(_) => "cloudflare_blocked",
+            IherbError::ProductNotFound(_) => "product_not_found",
+            IherbError::Discontinued { .. } => "product_discontinued",
+            IherbError::BatchFailed { .. } => "batch_failed",
+            IherbError::Offline(_) => "offline_cache_miss",
+            IherbError::ChromeDownload(_) => "chrome_download",
+            IherbError::InvalidCategory(_) => "invalid_category",
+            IherbError::InvalidHeader(_) => "invalid_header",
+            IherbError::Cache(_) => "cache",
+            IherbError::Network(_) => "network",
+            IherbError::Io(_) => "io",
+            IherbError::Json(_) => "json",
+        }
+    }
+}
+
+/// The suggested replacements of a discontinued product, for its error message.
+fn alternatives_hint(alternatives: &[String]) -> String {
+    if alternatives.is_empty() {
+        String::new()
+    } else {
+        format!("; iHerb suggests: {}", alternatives.join(", "))
+    }
+}
+
+/// Find the underlying `IherbError` in an error chain, if any.
+pub fn find_iherb_error(err: &anyhow::Error) -> Option<&IherbError> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<IherbError>())
+}
+
+/// Exit code for an arbitrary error, falling back to `EXIT_GENERIC`.
+pub fn exit_code_for(err: &anyhow::Error) -> u8 {
+    find_iherb_error(err)
+        .map(IherbError::exit_code)
+        .unwrap_or(EXIT_GENERIC)
+}
+
+/// Render an error as a `{"error": {...}}` JSON object.
+pub fn error_to_json(err: &anyhow::Error) -> serde_json::Value {
+    let kind = find_iherb_error(err)
+        .map(IherbError::kind)
+        .unwrap_or("error");
+    json!({
+        "error": {
+            "kind": kind,
+            "message": format!("{:#}", err),
+            "exit_code": exit_code_for(err),
+        }
+    })
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, MakeWriter};
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::{EnvFilter, Layer};
+
+/// Rotated files kept next to the log, as `<path>.1` (newest) to `<path>.5`.
+const ROTATED_FILES: usize = 5;
+
+/// The `--log-file`, opened once at startup.
+static LOG: OnceLock<Mutex<RotatingFile>> = OnceLock::new();
+
+/// Open `path` for appending and return a layer writing every event at `filter` to it
+/// as one JSON object per line. With `max_bytes`, a log that would grow past it is
+/// rotated first.
+pub fn layer<S>(
+    path: &Path,
+    max_bytes: Option<u64>,
+    filter: EnvFilter,
+) -> std::io::Result<impl Layer<S>>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    let file = RotatingFile::open(path.to_path_buf(), max_bytes)?;
+    let _ = LOG.set(Mutex::new(file));
+    Ok(tracing_subscriber::fmt::layer()
+        .event_format(JsonLines)
+        .with_writer(LogFile)
+        .with_filter(filter))
+}
+
+/// Log the error a run ended with, which is otherwise only printed, along with the
+/// exit code. Does nothing without `--log-file`.
+pub fn record_exit(error: &anyhow::Error, exit_code: u8) {
+    let mut record = serde_json::Map::new();
+    record.insert("timestamp".to_string(), timestamp().into());
+    record.insert("level".to_string(), "ERROR".into());
+    record.insert("message".to_string(), format!("{:#}", error).into());
+    record.insert("exit_code".to_string(), exit_code.into());
+    write_line(&format!("{}\n", serde_json::Value::Object(record)));
+}
+
+fn timestamp() -> String {
+    let mut timestamp = String::new();
+    let _ = SystemTime.format_time(&mut Writer::new(&mut timestamp));
+    timestamp
+}
+
+fn write_line(line: &str) {
+    let Some(Ok(mut file)) = LOG.get().map(Mutex::lock) else {
+        return;
+    };
+    if let Err(e) = file.write(line.as_bytes()) {
+        // Not through tracing, which would try to log into the same file
+        eprintln!("Failed to write to {}: {}", file.path.display(), e);
+    }
+}
+
+/// Formats an event as a JSON object of its `timestamp`, `level`, `target` and
+/// fields, `message` included.
+struct JsonLines;
+
+impl<S, N> FormatEvent<S, N> for JsonLines
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        _ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let meta = event.metadata();
+        let mut record = serde_json::Map::new();
+        record.insert("timestamp".to_string(), timestamp().into());
+        record.insert("level".to_string(), meta.level().as_str().into());
+        record.insert("target".to_string(), meta.target().into());
+        event.record(&mut JsonFields(&mut record));
+        writeln!(writer, "{}", serde_json::Value::Object(record))
+    }
+}
+
+struct JsonFields<'a>(&'a mut serde_json::Map<String, serde_json::Value>);
+
+impl Visit for JsonFields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+}
+
+struct LogFile;
+
+impl<'a> MakeWriter<'a> for LogFile {
+    type Writer = LogFileWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LogFileWriter(Vec::new())
+    }
+}
+
+/// Collects one formatted event and appends it to the log when dropped, so a
+/// rotation never splits a line.
+struct LogFileWriter(Vec<u8>);
+
+impl Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for LogFileWriter {
+    fn drop(&mut self) {
+        write_line(&String::from_utf8_lossy(&self.0));
+    }
+}
+
+/// An append-only log file that is renamed to `<path>.1` (shifting older ones up)
+/// before a write would take it past `max_bytes`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    max_bytes: Option<u64>,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: Option<u64>) -> std::io::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            len,
+            max_bytes,
+        })
+    }
+
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        if self
+            .max_bytes
+            .is_some_and(|max| self.len > 0 && self.len + buf.len() as u64 > max)
+        {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.len += buf.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        for n in (1..ROTATED_FILES).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+        *self = Self::open(self.path.clone(), self.max_bytes)?;
+        Ok(())
+    }
+}
+
+/// `path` with `.n` appended to its file name, e.g. `run.log.1`.
+fn rotated_path(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
@@ -1,46 +1,156 @@
+mod batch;
 mod browser;
 mod cache;
 mod cli;
 mod config;
+mod diagnostics;
 mod error;
+mod logfile;
 mod model;
 mod output;
 mod scraper;
 
 use anyhow::{Context, Result};
 use clap::Parser;
-use cli::{Cli, Commands, Section, SortOrder};
-use config::AppConfig;
-use std::time::SystemTime;
+use cli::{Cli, Commands, OutputFormat, Section, SortOrder, ValueWeights};
+use config::{AppConfig, CliOverrides};
+use futures::StreamExt;
+use std::collections::{HashMap, VecDeque};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, SystemTime};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::Layer;
 
-use crate::browser::session::BrowserSession;
+use crate::batch::BatchRunner;
+use crate::browser::session::{BrowserSession, PooledPage};
 use crate::cache::Cache;
-use crate::scraper::navigation::Navigator;
+use crate::error::IherbError;
+use crate::model::CurrencySource;
+use crate::scraper::navigation::{Navigator, PRODUCT_READY_SELECTOR, SEARCH_READY_SELECTOR};
+
+/// Extra navigations when a page loads but extraction finds nothing (not hydrated yet).
+const EXTRACTION_RETRIES: u32 = 2;
+/// Additional wait before re-navigating after an empty extraction.
+const HYDRATION_WAIT: Duration = Duration::from_secs(3);
+/// Listing pages a filtered search may fetch to find `--limit` products that pass.
+const MAX_FILTERED_PAGES: usize = 10;
 
 #[tokio::main]
-async fn main() -> Result<()> {
+async fn main() -> ExitCode {
     let cli = Cli::parse();
+    let format = cli.format;
+
+    match run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            // Batch failures have already been itemized alongside the results,
+            // so only the summary goes to stderr to keep stdout parseable.
+            let is_batch = matches!(
+                error::find_iherb_error(&e),
+                Some(IherbError::BatchFailed { .. })
+            );
+            match format {
+                OutputFormat::Json | OutputFormat::JsonLines if !is_batch => {
+                    println!("{}", error::error_to_json(&e))
+                }
+                _ => eprintln!("Error: {:?}", e),
+            }
+            let code = error::exit_code_for(&e);
+            logfile::record_exit(&e, code);
+            ExitCode::from(code)
+        }
+    }
+}
+
+/// The settings `AppConfig` takes from the command line.
+fn cli_overrides(cli: &Cli) -> CliOverrides {
+    CliOverrides {
+        country: cli.country.clone(),
+        currency: cli.currency.clone(),
+        strict_currency: cli.strict_currency,
+        no_cache: cli.no_cache,
+        offline: cli.offline,
+        offline_stale_ok: cli.offline_stale_ok,
+        cache_shard: cli.cache_shard,
+        delay: cli.delay,
+        product_delay: cli.product_delay,
+        no_jitter: cli.no_jitter,
+        fail_fast: cli.fail_fast,
+        max_rpm: cli.max_rpm,
+        timeout: cli.timeout,
+        cf_session_retries: cli.cf_session_retries,
+        server_error_retries: cli.server_error_retries,
+        wait_strategy: cli.wait_strategy,
+        prefer_strategy: cli.prefer_strategy,
+        only_strategy: cli.only_strategy,
+        no_enrich_dom: cli.no_enrich_dom,
+        debug: cli.debug,
+        headless: cli.headless,
+        quiet: cli.quiet,
+        format: cli.format,
+        sanitize: cli.sanitize,
+        explain: cli.explain,
+        compact_nutrition: cli.compact_nutrition,
+        round_prices: cli.round_prices,
+        user_agent: cli.user_agent.clone(),
+        headers: cli.headers.clone(),
+        set_selectors: cli.set_selectors.clone(),
+        override_stealth_headers: cli.override_stealth_headers,
+        dump_html: cli.dump_html.clone(),
+        diagnostics_dir: cli.diagnostics_dir.clone(),
+        reuse_tabs: cli.reuse_tabs,
+        browser: cli.browser,
+        no_download: cli.no_download,
+    }
+}
 
+async fn run(cli: Cli) -> Result<()> {
     let filter = if cli.debug {
         "iherb_cli=debug"
+    } else if cli.quiet {
+        "iherb_cli=error"
     } else {
         "iherb_cli=warn"
     };
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(filter)),
+    let stderr_filter = tracing_subscriber::EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(filter));
+    let log_file = match &cli.log_file {
+        Some(path) => {
+            let filter = if cli.debug {
+                "iherb_cli=debug"
+            } else {
+                "iherb_cli=info"
+            };
+            let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new(filter));
+            let max_bytes = cli.log_max_size.map(|mb| mb * 1024 * 1024);
+            Some(
+                logfile::layer(path, max_bytes, filter)
+                    .with_context(|| format!("Failed to open log file {}", path.display()))?,
+            )
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer()
+                .with_target(false)
+                .with_filter(stderr_filter),
+        )
+        // The tail for --diagnostics-dir bundles records debug lines regardless
+        .with(
+            cli.diagnostics_dir
+                .is_some()
+                .then(diagnostics::log_tail_layer),
         )
-        .with_target(false)
+        .with(log_file)
         .init();
 
-    let config = AppConfig::load(
-        cli.country,
-        cli.currency,
-        cli.no_cache,
-        cli.delay,
-        cli.debug,
-    )?;
+    let config = AppConfig::load(cli_overrides(&cli))?;
+    scraper::selectors::set_overrides(&config.selector_overrides);
 
     ctrlc::set_handler(|| {
         eprintln!("\nInterrupted.");
@@ -49,200 +159,2526 @@ async fn main() -> Result<()> {
     .context("Failed to set Ctrl+C handler")?;
 
     let mut browser_session: Option<BrowserSession> = None;
+    let keep_browser_open = cli.keep_browser_open;
 
-    match cli.command {
+    let result = match cli.command {
         Commands::Search {
             query,
+            query_file,
+            similar_to,
             limit,
+            page,
             sort,
+            value_weights,
             category,
+            no_envelope,
+            replay,
+            bestsellers_only,
+            min_rating,
+            bayesian_rating,
+            rating_prior,
+            min_price,
+            max_price,
+            min_servings,
+            max_price_per_serving,
+            enrich,
+            enrich_limit,
+        } => {
+            let price = scraper::search::PriceRange {
+                min: min_price,
+                max: max_price,
+            };
+            if price.min.zip(price.max).is_some_and(|(min, max)| min > max) {
+                anyhow::bail!("--min-price must not be greater than --max-price");
+            }
+            if let Some(path) = replay {
+                let query = query.unwrap_or_default();
+                return replay_search(&config, &path, &query, limit, price, no_envelope);
+            }
+            if sort == SortOrder::Value && !enrich {
+                anyhow::bail!("--sort value needs --enrich to read each result's servings");
+            }
+            let options = SearchOptions {
+                limit: match page {
+                    Some(_) => scraper::search::RESULTS_PER_PAGE,
+                    None => limit,
+                },
+                page: page.map(|p| p as usize),
+                sort,
+                value_weights,
+                category: category.as_deref(),
+                price,
+                no_envelope,
+                bestsellers_only,
+                min_rating,
+                rating_prior: bayesian_rating.then_some(rating_prior),
+                min_servings,
+                max_price_per_serving,
+                enrich,
+                enrich_limit,
+                exclude_product: None,
+            };
+            match (query_file, similar_to) {
+                (Some(path), _) => {
+                    let queries = read_query_file(&path)?;
+                    cmd_search_batch(&config, &mut browser_session, &queries, &options).await
+                }
+                (None, Some(id_or_url)) => {
+                    cmd_search_similar(
+                        &config,
+                        &mut browser_session,
+                        &id_or_url,
+                        query.as_deref(),
+                        &options,
+                    )
+                    .await
+                }
+                (None, None) => {
+                    let query = query.unwrap_or_default();
+                    cmd_search(&config, &mut browser_session, &query, &options).await
+                }
+            }
+        }
+        Commands::Product {
+            id_or_url,
+            search,
+            pick,
+            section,
+            nutrient,
+            output_dir,
+            min_servings,
+            include_qa,
+            timeout_per_product,
+            replay,
         } => {
-            cmd_search(
+            let view = ProductView {
+                section,
+                nutrient: nutrient.as_deref(),
+            };
+            if let Some(path) = replay {
+                return replay_product(&config, &path, &id_or_url, view);
+            }
+            let id_or_url = match search {
+                Some(term) => {
+                    let pick = pick.unwrap_or(1) as usize;
+                    vec![search_match(&config, &mut browser_session, &term, pick).await?]
+                }
+                None => id_or_url,
+            };
+            cmd_product(
                 &config,
                 &mut browser_session,
-                &query,
-                limit,
-                sort,
-                category.as_deref(),
+                &id_or_url,
+                view,
+                output_dir.as_deref(),
+                min_servings,
+                ProductFetch {
+                    include_qa,
+                    timeout: timeout_per_product.map(Duration::from_secs),
+                },
             )
-            .await?;
+            .await
         }
-        Commands::Product { id_or_url, section } => {
-            cmd_product(&config, &mut browser_session, &id_or_url, section).await?;
+        Commands::Brand { name, limit, sort } => {
+            cmd_brand(&config, &mut browser_session, &name, limit, sort).await
         }
-    }
+        Commands::Categories { parent } => {
+            cmd_categories(&config, &mut browser_session, parent.as_deref()).await
+        }
+        Commands::Compare {
+            ids,
+            countries,
+            max_concurrent_browsers,
+            ..
+        } if !countries.is_empty() => {
+            cmd_compare_countries(&config, &ids, &countries, max_concurrent_browsers).await
+        }
+        Commands::Compare {
+            ids,
+            min_servings,
+            nutrient,
+            ..
+        } => {
+            cmd_compare(
+                &config,
+                &mut browser_session,
+                &ids,
+                min_servings,
+                nutrient.as_deref(),
+            )
+            .await
+        }
+        Commands::Summary { id_or_url, related } => {
+            cmd_summary(&config, &mut browser_session, &id_or_url, related).await
+        }
+        Commands::Doctor => cmd_doctor(&config, &mut browser_session).await,
+        Commands::ListCache => cmd_list_cache(&config),
+        Commands::Version { check } => cmd_version(&config, check).await,
+    };
 
+    // Close the browser even when the command failed, so cookies are persisted
     if let Some(session) = browser_session.take() {
+        if keep_browser_open {
+            wait_for_enter().await;
+        }
         if let Err(e) = session.close().await {
             tracing::warn!("Failed to close browser: {}", e);
         }
     }
 
+    result
+}
+
+/// `--keep-browser-open`: block until Enter is pressed (or stdin closes), leaving the
+/// headed browser up for inspection.
+async fn wait_for_enter() {
+    eprint!("Press Enter to close the browser...");
+    let _ = std::io::stderr().flush();
+    let _ = tokio::task::spawn_blocking(|| {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line)
+    })
+    .await;
+}
+
+/// Parse a saved search page, as with `--replay`, skipping the browser and cache.
+fn replay_search(
+    config: &AppConfig,
+    path: &Path,
+    query: &str,
+    limit: usize,
+    price: scraper::search::PriceRange,
+    no_envelope: bool,
+) -> Result<()> {
+    let html = read_replay_file(path)?;
+    let mut result = scraper::search::parse_search_from_html(
+        &html,
+        query,
+        &config.base_url(),
+        config.fallback_currency(),
+    )?;
+    result.products.retain(|p| price.contains(p.price));
+    result.products.truncate(limit);
+    sanitize_listing(config, &mut result);
+
+    let fetched_at = SystemTime::now();
+    let meta = (!no_envelope).then(|| response_meta(0, false, fetched_at));
+    print_search(config, &result, fetched_at, meta);
+    Ok(())
+}
+
+/// Parse a saved product page, as with `--replay`, skipping the browser and cache.
+fn replay_product(
+    config: &AppConfig,
+    path: &Path,
+    ids: &[String],
+    view: ProductView<'_>,
+) -> Result<()> {
+    let [id_or_url] = ids else {
+        anyhow::bail!("--replay takes exactly one product ID, the one the page belongs to");
+    };
+    let product_id = parse_product_identifier(id_or_url)?;
+    if !config.extract_order.iter().any(|s| s.reads_html()) {
+        anyhow::bail!(
+            "--replay can only extract with json-ld or dom; the other strategies need a live page"
+        );
+    }
+    let html = read_replay_file(path)?;
+    let mut product = scraper::product::extract_product_from_html(
+        &html,
+        &product_id,
+        &config.base_url(),
+        config.fallback_currency(),
+        &config.extract_order,
+        config.enrich_dom,
+    )?;
+    sanitize_names(config, &mut product.name, &mut product.brand);
+    explain_product(config, &mut product);
+    print_product(config, &product, view, SystemTime::now());
     Ok(())
 }
 
+fn read_replay_file(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read replay file {}", path.display()))
+}
+
+/// Search settings shared by single and `--query-file` searches.
+struct SearchOptions<'a> {
+    limit: usize,
+    /// Fetch only this listing page, with `limit` set to a full page.
+    page: Option<usize>,
+    sort: SortOrder,
+    /// Score weights for `SortOrder::Value`.
+    value_weights: ValueWeights,
+    category: Option<&'a str>,
+    /// `--min-price` / `--max-price`, applied by iHerb and checked again locally.
+    price: scraper::search::PriceRange,
+    no_envelope: bool,
+    /// Keep only results with a best-seller or rank badge.
+    bestsellers_only: bool,
+    /// Drop results rated lower, or unrated; compared to the adjusted rating when set.
+    min_rating: Option<f64>,
+    /// With `--bayesian-rating`, the prior to adjust ratings by review count with.
+    rating_prior: Option<cli::RatingPrior>,
+    /// Drop results with fewer servings; each result's product page is fetched to find out.
+    min_servings: Option<u32>,
+    /// Drop results costing more per serving; also needs each result's product page.
+    max_price_per_serving: Option<f64>,
+    /// Fetch every result's product page and print full details, also needed by the two
+    /// filters above and the value sort.
+    enrich: bool,
+    /// Fetch at most this many product pages when enriching.
+    enrich_limit: Option<usize>,
+    /// Leave this product out of the results, for `--similar-to`.
+    exclude_product: Option<&'a str>,
+}
+
+impl SearchOptions<'_> {
+    /// Whether live results are printed page by page as they are scraped. Filters,
+    /// and leaving out the `--similar-to` product, need the whole result set first,
+    /// so they turn streaming off.
+    fn streams(&self, config: &AppConfig) -> bool {
+        config.format == OutputFormat::JsonLines
+            && !self.bestsellers_only
+            && self.min_rating.is_none()
+            && self.rating_prior.is_none()
+            && !self.price.is_set()
+            && !self.enrich
+            && self.exclude_product.is_none()
+    }
+
+    /// Whether listing filters may drop results, so more pages than `limit` needs
+    /// may have to be fetched. The price range is left to iHerb's own filter.
+    fn drops_listed(&self) -> bool {
+        self.bestsellers_only || self.min_rating.is_some()
+    }
+
+    /// Whether `product` passes `--bestsellers-only`, `--min-rating` (compared to its
+    /// adjusted rating with `--bayesian-rating`) and the price range.
+    fn keeps(&self, product: &model::ProductSummary) -> bool {
+        let rating = match self.rating_prior {
+            Some(prior) => scraper::search::bayesian_rating(product, prior),
+            None => product.rating,
+        };
+        self.price.contains(product.price)
+            && (!self.bestsellers_only || product.rank.is_some())
+            && self
+                .min_rating
+                .is_none_or(|min| rating.is_some_and(|r| r >= min))
+    }
+
+    /// Adjust ratings with `--bayesian-rating` (reordering them for `--sort rating`)
+    /// and drop the products `keeps` rejects. Runs on every result fetched, before
+    /// they are cut to `limit`.
+    fn filter_listing(&self, products: &mut Vec<model::ProductSummary>) {
+        if let Some(prior) = self.rating_prior {
+            scraper::search::apply_bayesian_rating(products, prior);
+            if self.sort == SortOrder::Rating {
+                scraper::search::sort_products(products, SortOrder::Rating);
+            }
+        }
+        products.retain(|p| self.keeps(p));
+    }
+}
+
 async fn cmd_search(
     config: &AppConfig,
     browser_session: &mut Option<BrowserSession>,
     query: &str,
-    limit: usize,
-    sort: SortOrder,
-    category: Option<&str>,
+    options: &SearchOptions<'_>,
 ) -> Result<()> {
-    if query.trim().is_empty() {
-        anyhow::bail!("Search query cannot be empty");
+    if let Err(e) = cli::validate_query(query) {
+        anyhow::bail!("Invalid search: {}", e);
     }
-    if limit == 0 {
+    if options.limit == 0 {
         anyhow::bail!("Limit must be at least 1");
     }
 
-    let cache = Cache::new(config.cache_dir.clone(), config.no_cache);
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+    let category =
+        resolve_search_category(config, browser_session, &cache, options.category).await?;
+
+    let (fetched, pages_fetched) = search_query(
+        config,
+        browser_session,
+        &cache,
+        query,
+        options,
+        category.as_deref(),
+    )
+    .await?;
 
-    if let Some(hit) = cache.get_search::<model::SearchResult>(query, sort, category) {
-        let mut result = hit.data;
-        result.products.truncate(limit);
-        print!("{}", output::format_search_results(&result));
-        println!("\n- **Data from:** {}", output::format_cached_at(hit.cached_at));
-        return Ok(());
+    // Live json-lines results were already streamed page by page
+    if fetched.from_cache || !options.streams(config) {
+        let meta = (!options.no_envelope)
+            .then(|| response_meta(pages_fetched, fetched.from_cache, fetched.fetched_at));
+        print_search_output(config, &fetched.data, fetched.fetched_at, meta);
     }
+    Ok(())
+}
 
-    let session = get_or_launch_browser(config, browser_session).await?;
-    let page = session.new_page().await?;
-    let navigator = Navigator::new(config.delay_ms);
+/// `search --similar-to`: look up the product's category breadcrumb, then search
+/// that category (with the innermost category name as the search term, unless one
+/// is given) best rated first, leaving the product itself out. A heuristic, not
+/// iHerb's own recommendations.
+async fn cmd_search_similar(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    id_or_url: &str,
+    query: Option<&str>,
+    options: &SearchOptions<'_>,
+) -> Result<()> {
+    let product = fetch_product(config, browser_session, id_or_url)
+        .await
+        .with_context(|| format!("Failed to fetch {}", id_or_url))?
+        .data;
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+    let (query, category) =
+        similar_search_target(config, browser_session, &cache, &product, query).await?;
 
-    let base_url = config.base_url();
-    let total_pages = scraper::search::pages_needed(limit);
-    let mut all_products = Vec::new();
-    let mut total_results = None;
+    let options = SearchOptions {
+        sort: SortOrder::Rating,
+        category: category.as_deref(),
+        exclude_product: Some(&product.product_id),
+        ..*options
+    };
+    cmd_search(config, browser_session, &query, &options).await
+}
 
-    for page_num in 1..=total_pages {
-        if all_products.len() >= limit {
-            break;
+/// The search term and category to find products like `product` with: its innermost
+/// breadcrumb entry that is a known category, searched for by name unless `query`
+/// is given.
+async fn similar_search_target(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    cache: &Cache,
+    product: &model::ProductDetail,
+    query: Option<&str>,
+) -> Result<(String, Option<String>)> {
+    let breadcrumb = product.category_breadcrumb.as_deref().unwrap_or_default();
+    let category = if breadcrumb.is_empty() {
+        None
+    } else {
+        let tree = load_category_tree(config, browser_session, cache).await?;
+        let map = scraper::categories::category_map(&tree);
+        breadcrumb
+            .iter()
+            .rev()
+            .find_map(|name| map.get(&scraper::helpers::slugify(name)).cloned())
+    };
+    let query = match (query, breadcrumb.last()) {
+        (Some(query), _) => query.to_string(),
+        (None, Some(name)) => name.clone(),
+        (None, None) => anyhow::bail!(
+            "Could not find the category of {}; give a search term along with --similar-to",
+            product.product_id
+        ),
+    };
+    tracing::info!(
+        "Searching \"{}\" in category {} for products similar to {}",
+        query,
+        category.as_deref().unwrap_or("(any)"),
+        product.product_id
+    );
+    Ok((query, category))
+}
+
+/// `summary`: the product with its `related` best-rated neighbours (as found by
+/// `search --similar-to`) in one brief. The product is required; when the related
+/// search fails the brief goes out without it.
+async fn cmd_summary(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    id_or_url: &str,
+    related: usize,
+) -> Result<()> {
+    let fetched = fetch_product(config, browser_session, id_or_url)
+        .await
+        .with_context(|| format!("Failed to fetch {}", id_or_url))?;
+    let product = fetched.data;
+    let related = if related == 0 {
+        Vec::new()
+    } else {
+        match related_products(config, browser_session, &product, related).await {
+            Ok(products) => products,
+            Err(e) => {
+                tracing::warn!("Leaving out related products: {:#}", e);
+                Vec::new()
+            }
         }
+    };
 
-        let url = scraper::search::build_search_url(&base_url, query, sort, category, page_num);
-        let html = navigator
-            .navigate_with_retry(&page, &url, 2)
-            .await
-            .context("Failed to navigate to search page")?;
+    match config.format {
+        OutputFormat::Json | OutputFormat::JsonLines => {
+            let brief = model::ProductBrief {
+                product,
+                related,
+                top_reviews: None,
+                top_reviews_note: model::TOP_REVIEWS_NOTE,
+            };
+            match config.format {
+                OutputFormat::Json => println!("{}", output::format_json(&brief)),
+                _ => println!("{}", output::format_json_line(&brief)),
+            }
+        }
+        OutputFormat::Markdown => print!(
+            "{}{}\n- **Data from:** {}\n",
+            storefront_note(config, product.effective_country.as_deref()).unwrap_or_default(),
+            output::format_summary(
+                &product,
+                &related,
+                config.compact_nutrition,
+                config.round_prices
+            ),
+            output::format_cached_at(fetched.fetched_at)
+        ),
+    }
+    Ok(())
+}
 
-        let page_result =
-            scraper::search::extract_search(&page, &html, query, &base_url, &config.currency)
-                .await
-                .context("Failed to extract search results")?;
+/// Up to `limit` best-rated products from `product`'s category, leaving it out.
+async fn related_products(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    product: &model::ProductDetail,
+    limit: usize,
+) -> Result<Vec<model::ProductSummary>> {
+    let breadcrumb = product.category_breadcrumb.as_deref().unwrap_or_default();
+    if breadcrumb.is_empty() {
+        anyhow::bail!("Could not find the category of {}", product.product_id);
+    }
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+    let (query, category) =
+        similar_search_target(config, browser_session, &cache, product, None).await?;
+    let category =
+        resolve_search_category(config, browser_session, &cache, category.as_deref()).await?;
+    let options = SearchOptions {
+        limit,
+        page: None,
+        sort: SortOrder::Rating,
+        value_weights: ValueWeights::default(),
+        category: category.as_deref(),
+        price: scraper::search::PriceRange::default(),
+        no_envelope: true,
+        bestsellers_only: false,
+        min_rating: None,
+        rating_prior: None,
+        min_servings: None,
+        max_price_per_serving: None,
+        enrich: false,
+        enrich_limit: None,
+        exclude_product: Some(&product.product_id),
+    };
+    // The listing is part of the brief, never streamed on its own
+    let listing_config = AppConfig {
+        format: OutputFormat::Markdown,
+        ..config.clone()
+    };
+    let (fetched, _) = search_listing(
+        &listing_config,
+        browser_session,
+        &cache,
+        &query,
+        &options,
+        category.as_deref(),
+    )
+    .await?;
+    let mut products = fetched.data.products;
+    products.retain(|p| p.product_id != product.product_id);
+    products.truncate(limit);
+    Ok(products)
+}
 
-        if page_result.products.is_empty() {
-            break;
+/// `product --search`: the ID of the `pick`th result (1-based) of a relevance search
+/// for `term`.
+async fn search_match(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    term: &str,
+    pick: usize,
+) -> Result<String> {
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+    let options = SearchOptions {
+        limit: pick,
+        page: None,
+        sort: SortOrder::Relevance,
+        value_weights: ValueWeights::default(),
+        category: None,
+        price: scraper::search::PriceRange::default(),
+        no_envelope: true,
+        bestsellers_only: false,
+        min_rating: None,
+        rating_prior: None,
+        min_servings: None,
+        max_price_per_serving: None,
+        enrich: false,
+        enrich_limit: None,
+        exclude_product: None,
+    };
+    // Only the product is printed, never the listing
+    let listing_config = AppConfig {
+        format: OutputFormat::Markdown,
+        ..config.clone()
+    };
+    let (fetched, _) = search_listing(
+        &listing_config,
+        browser_session,
+        &cache,
+        term,
+        &options,
+        None,
+    )
+    .await?;
+    let products = fetched.data.products;
+    let Some(product) = products.get(pick - 1) else {
+        let found = match products.len() {
+            0 => "no results".to_string(),
+            1 => "only 1 result".to_string(),
+            n => format!("only {} results", n),
+        };
+        return Err(IherbError::ProductNotFound(format!(
+            "searching for \"{}\" found {}",
+            term, found
+        ))
+        .into());
+    };
+    if !config.quiet {
+        eprintln!(
+            "Result {} for \"{}\": {} ({})",
+            pick, term, product.name, product.product_id
+        );
+    }
+    Ok(product.product_id.clone())
+}
+
+/// Run every query from `--query-file` in one browser session. JSON output is a
+/// single object keyed by query; a failing query does not stop the rest.
+async fn cmd_search_batch(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    queries: &[String],
+    options: &SearchOptions<'_>,
+) -> Result<()> {
+    if options.limit == 0 {
+        anyhow::bail!("Limit must be at least 1");
+    }
+
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+    let category =
+        resolve_search_category(config, browser_session, &cache, options.category).await?;
+
+    let mut batch = BatchRunner::new(config, queries.len());
+    let mut results = serde_json::Map::new();
+    let mut succeeded = 0;
+
+    for query in queries {
+        batch.pace().await;
+        let outcome = search_query(
+            config,
+            browser_session,
+            &cache,
+            query,
+            options,
+            category.as_deref(),
+        )
+        .await;
+        match outcome {
+            Ok((fetched, pages_fetched)) => {
+                batch.record_success(fetched.from_cache);
+                match config.format {
+                    OutputFormat::Json => {
+                        let value = if options.no_envelope {
+                            serde_json::to_value(&fetched.data)?
+                        } else {
+                            serde_json::to_value(model::Envelope {
+                                meta: response_meta(
+                                    pages_fetched,
+                                    fetched.from_cache,
+                                    fetched.fetched_at,
+                                ),
+                                result: &fetched.data,
+                            })?
+                        };
+                        results.insert(query.clone(), value);
+                    }
+                    OutputFormat::Markdown => {
+                        if succeeded > 0 {
+                            println!("\n---\n");
+                        }
+                        print_search_output(config, &fetched.data, fetched.fetched_at, None);
+                    }
+                    OutputFormat::JsonLines if fetched.from_cache || !options.streams(config) => {
+                        print_search_output(config, &fetched.data, fetched.fetched_at, None)
+                    }
+                    OutputFormat::JsonLines => {}
+                }
+                succeeded += 1;
+            }
+            Err(e) => batch.record_failure(query, e)?,
         }
+    }
 
-        if total_results.is_none() {
-            total_results = page_result.total_results;
+    if config.format == OutputFormat::Json {
+        println!("{}", output::format_json(&results));
+    }
+
+    if !config.quiet {
+        eprintln!("\nSearched {} of {} queries.", succeeded, queries.len());
+    }
+    batch.finish()
+}
+
+/// Resolve a `--category` name to iHerb's numeric id; ids pass through unchanged.
+async fn resolve_search_category(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    cache: &Cache,
+    category: Option<&str>,
+) -> Result<Option<String>> {
+    Ok(match category {
+        Some(cat) if scraper::categories::needs_lookup(cat) => {
+            let tree = load_category_tree(config, browser_session, cache).await?;
+            let map = scraper::categories::category_map(&tree);
+            Some(scraper::categories::resolve_category(cat, &map)?)
         }
+        other => other.map(str::to_string),
+    })
+}
 
-        all_products.extend(page_result.products);
+/// A search result as printed: the listing's summaries, or with `--enrich` each
+/// product's full details.
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum SearchOutput {
+    Summaries(model::SearchResult),
+    Details(model::SearchResult<model::ProductDetail>),
+}
 
-        if page_num < total_pages {
-            navigator.rate_limit_delay().await;
+/// Results for one search, from the cache when possible; with `--enrich` upgraded
+/// to product details, filtered by `--min-servings` and `--max-price-per-serving`
+/// and, with `--sort value`, scored.
+/// Returns them with the number of listing pages navigated. When `options.streams`,
+/// live results are printed as each page is scraped.
+async fn search_query(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    cache: &Cache,
+    query: &str,
+    options: &SearchOptions<'_>,
+    category: Option<&str>,
+) -> Result<(Fetched<SearchOutput>, u32)> {
+    let (mut fetched, pages_fetched) =
+        search_listing(config, browser_session, cache, query, options, category).await?;
+    if let Some(excluded) = options.exclude_product {
+        fetched.data.products.retain(|p| p.product_id != excluded);
+        fetched.data.products.truncate(options.limit);
+    }
+    let data = if options.enrich {
+        SearchOutput::Details(enrich_results(config, browser_session, fetched.data, options).await)
+    } else {
+        SearchOutput::Summaries(fetched.data)
+    };
+    let fetched = Fetched {
+        data,
+        fetched_at: fetched.fetched_at,
+        from_cache: fetched.from_cache,
+    };
+    Ok((fetched, pages_fetched))
+}
+
+/// A search's listing, `--bayesian-rating` applied and filtered by
+/// `--bestsellers-only` and `--min-rating` before being cut to `limit`. With either
+/// filter, pages are fetched until `limit` products pass, up to `MAX_FILTERED_PAGES`;
+/// a cached listing with too few is fetched again.
+async fn search_listing(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    cache: &Cache,
+    query: &str,
+    options: &SearchOptions<'_>,
+    category: Option<&str>,
+) -> Result<(Fetched<model::SearchResult>, u32)> {
+    let SearchOptions {
+        limit,
+        page,
+        sort,
+        price,
+        ..
+    } = *options;
+    // One spare result makes up for the product `--similar-to` leaves out
+    let limit = limit + usize::from(options.exclude_product.is_some());
+    let keep = |p: &model::ProductSummary| options.keeps(p);
+    // Filtered results may be spread over more pages than `limit` alone needs
+    let max_pages = if page.is_none() && options.drops_listed() {
+        scraper::search::pages_needed(limit).max(MAX_FILTERED_PAGES)
+    } else {
+        scraper::search::pages_needed(limit)
+    };
+    if let Some(hit) = cache.get_search::<model::SearchResult>(query, sort, category, price, page) {
+        let mut result = hit.data;
+        let cached = result.products.len();
+        relabel_listing_currency(config, &mut result);
+        sanitize_listing(config, &mut result);
+        // Entries cached before a sort was verified may be in server order
+        scraper::search::sort_products(&mut result.products, sort);
+        options.filter_listing(&mut result.products);
+        let more_listed = result
+            .total_results
+            .is_none_or(|total| cached < total as usize)
+            && cached < max_pages * scraper::search::RESULTS_PER_PAGE;
+        let enough = result.products.len() >= limit || !options.drops_listed();
+        if enough || !more_listed || config.offline {
+            result.products.truncate(limit);
+            let fetched = Fetched {
+                data: result,
+                fetched_at: hit.cached_at,
+                from_cache: true,
+            };
+            return Ok((fetched, 0));
         }
+        tracing::debug!("Too few cached results pass the filters, fetching more pages");
     }
 
+    if config.offline {
+        return Err(IherbError::Offline(format!("search \"{}\"", query)).into());
+    }
+
+    let base_url = config.base_url();
+    let streaming = options.streams(config);
+
+    // A multi-page search saves its products after each page, so a run that is
+    // interrupted picks up after the last saved page instead of starting over
+    let resumable = page.is_none() && max_pages > 1;
+    let mut partial = if resumable {
+        cache
+            .get_search_partial::<model::PartialListing>(query, sort, category, price)
+            .map(|hit| hit.data)
+            .unwrap_or_default()
+    } else {
+        model::PartialListing::default()
+    };
+    let resumed_pages = partial.pages_fetched;
+    let resumed_products = partial.products.len();
+    let resumed_kept = partial.products.iter().filter(|p| keep(p)).count();
+    if resumed_pages > 0 {
+        tracing::info!(
+            "Resuming search \"{}\" after page {} ({} products saved)",
+            query,
+            resumed_pages,
+            resumed_products
+        );
+        if streaming {
+            stream_products(config, &partial.products[..limit.min(resumed_products)]);
+        }
+    }
+
+    let (listing, pages_fetched) = if resumed_kept >= limit || resumed_pages >= max_pages {
+        let listing = model::SearchResult {
+            query: query.to_string(),
+            total_results: None,
+            products: Vec::new(),
+            effective_country: None,
+        };
+        (listing, 0)
+    } else {
+        fetch_listing(
+            config,
+            browser_session,
+            "search",
+            query,
+            ListingLimit {
+                limit: limit - resumed_kept,
+                max_pages: max_pages - resumed_pages,
+                keep: &keep,
+            },
+            // With --page, the single page fetched is that page
+            |page_num| {
+                let page_num = page.unwrap_or(resumed_pages + page_num);
+                scraper::search::build_search_url(&base_url, query, sort, category, price, page_num)
+            },
+            async |products, fetched| {
+                if streaming {
+                    stream_products(config, products)
+                }
+                if resumable {
+                    partial.pages_fetched += 1;
+                    partial.products.truncate(resumed_products);
+                    partial.products.extend_from_slice(fetched);
+                    let saved = cache
+                        .set_search_partial(query, sort, category, price, &partial)
+                        .await;
+                    if let Err(e) = saved {
+                        tracing::debug!("Failed to save search progress: {}", e);
+                    }
+                }
+            },
+        )
+        .await?
+    };
+    let mut all_products = partial.products;
+    all_products.truncate(resumed_products);
+    all_products.extend(listing.products);
+    // iHerb's price facet may round differently from the prices shown on the cards
+    all_products.retain(|p| price.contains(p.price));
+
     if all_products.is_empty() {
-        anyhow::bail!("No search results found for: {}", query);
+        match page {
+            Some(page) => anyhow::bail!("No search results on page {} for: {}", page, query),
+            None => anyhow::bail!("No search results found for: {}", query),
+        }
+    }
+
+    if !scraper::search::is_in_sort_order(&all_products, sort) {
+        if streaming {
+            tracing::warn!(
+                "iHerb did not honor sort order '{}'; streamed results are in server order",
+                sort.as_cache_key()
+            );
+        } else {
+            tracing::warn!(
+                "iHerb did not honor sort order '{}', sorting results locally",
+                sort.as_cache_key()
+            );
+        }
+        scraper::search::sort_products(&mut all_products, sort);
     }
 
     // Cache the full result set before truncating
     let full_result = model::SearchResult {
-        query: query.to_string(),
-        total_results,
         products: all_products,
+        ..listing
     };
 
-    if let Err(e) = cache.set_search(query, sort, category, &full_result) {
+    if let Err(e) = cache
+        .set_search(query, sort, category, price, page, &full_result)
+        .await
+    {
         tracing::debug!("Failed to cache search results: {}", e);
     }
+    if resumable {
+        cache.remove_search_partial(query, sort, category, price);
+    }
 
     let mut result = full_result;
+    options.filter_listing(&mut result.products);
     result.products.truncate(limit);
+    sanitize_listing(config, &mut result);
 
-    print!("{}", output::format_search_results(&result));
-    println!("\n- **Data from:** {}", output::format_cached_at(SystemTime::now()));
-    Ok(())
+    let fetched = Fetched {
+        data: result,
+        fetched_at: SystemTime::now(),
+        from_cache: false,
+    };
+    Ok((fetched, pages_fetched))
 }
 
-async fn cmd_product(
+/// Fetch the product page of each result (up to `--enrich-limit`) and keep the details
+/// of those passing `--min-servings` and `--max-price-per-serving`, ordered by
+/// `--sort value` when given. Results whose product page can't be fetched, or beyond
+/// the enrich limit, are dropped.
+async fn enrich_results(
     config: &AppConfig,
     browser_session: &mut Option<BrowserSession>,
-    id_or_url: &str,
-    section: Option<Section>,
+    result: model::SearchResult,
+    options: &SearchOptions<'_>,
+) -> model::SearchResult<model::ProductDetail> {
+    let navigator = Navigator::new(config);
+    let mut kept = Vec::new();
+    let mut details = HashMap::new();
+    let mut prices_per_serving = HashMap::new();
+    let mut fetched_live = false;
+
+    let mut candidates = result.products;
+    if let Some(enrich_limit) = options.enrich_limit {
+        if candidates.len() > enrich_limit {
+            tracing::info!(
+                "Checking only the first {} of {} results (--enrich-limit)",
+                enrich_limit,
+                candidates.len()
+            );
+            candidates.truncate(enrich_limit);
+        }
+    }
+    if !candidates.is_empty() {
+        tracing::warn!(
+            "--enrich loads up to {} product pages one by one (cached ones are reused); this can take several minutes",
+            candidates.len()
+        );
+    }
+
+    for summary in candidates {
+        if fetched_live {
+            navigator.rate_limit_delay().await;
+        }
+        match fetch_product(config, browser_session, &summary.product_id).await {
+            Ok(fetched) => {
+                fetched_live = !fetched.from_cache;
+                if meets_min_servings(&fetched.data, options.min_servings)
+                    && meets_max_price_per_serving(&fetched.data, options.max_price_per_serving)
+                {
+                    if let Some(pps) = output::price_per_serving(&fetched.data) {
+                        prices_per_serving.insert(summary.product_id.clone(), pps);
+                    }
+                    details.insert(summary.product_id.clone(), fetched.data);
+                    kept.push(summary);
+                }
+            }
+            Err(e) => {
+                fetched_live = true;
+                tracing::warn!(
+                    "Could not load product page for {}, dropping it: {:#}",
+                    summary.product_id,
+                    e
+                );
+            }
+        }
+    }
+    if options.sort == SortOrder::Value {
+        scraper::search::sort_by_value(&mut kept, &prices_per_serving, options.value_weights);
+    }
+    model::SearchResult {
+        query: result.query,
+        total_results: result.total_results,
+        products: kept
+            .iter()
+            .filter_map(|summary| details.remove(&summary.product_id))
+            .collect(),
+        effective_country: result.effective_country,
+    }
+}
+
+/// Whether a product costs at most `max` per serving. Products with an unknown
+/// price per serving only pass when there is no maximum.
+fn meets_max_price_per_serving(product: &model::ProductDetail, max: Option<f64>) -> bool {
+    match max {
+        Some(max) => output::price_per_serving(product).is_some_and(|price| price <= max),
+        None => true,
+    }
+}
+
+/// Whether a product has at least `min` servings per container. Products with an
+/// unknown servings count only pass when there is no minimum.
+fn meets_min_servings(product: &model::ProductDetail, min: Option<u32>) -> bool {
+    match min {
+        Some(min) => output::servings_per_container(product)
+            .is_some_and(|servings| servings >= f64::from(min)),
+        None => true,
+    }
+}
+
+/// Queries from a `--query-file`: one per line, skipping blank lines and `#` comments.
+fn read_query_file(path: &Path) -> Result<Vec<String>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read query file {}", path.display()))?;
+    let mut queries = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        // Reject bad lines up front rather than after the browser has started
+        if let Err(e) = cli::validate_query(line) {
+            anyhow::bail!("{} line {}: {}", path.display(), number + 1, e);
+        }
+        queries.push(line.to_string());
+    }
+    if queries.is_empty() {
+        anyhow::bail!("Query file {} contains no queries", path.display());
+    }
+    Ok(queries)
+}
+
+async fn cmd_brand(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    name: &str,
+    limit: usize,
+    sort: SortOrder,
 ) -> Result<()> {
-    let product_id = parse_product_identifier(id_or_url)?;
-    let cache = Cache::new(config.cache_dir.clone(), config.no_cache);
+    let slug = scraper::helpers::slugify(name);
+    if slug.is_empty() {
+        anyhow::bail!("Brand name cannot be empty");
+    }
+    if sort == SortOrder::Value {
+        anyhow::bail!("--sort value is only available for search, with --enrich");
+    }
+    if limit == 0 {
+        anyhow::bail!("Limit must be at least 1");
+    }
+
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
 
-    if let Some(hit) = cache.get_product::<model::ProductDetail>(&product_id) {
-        print!("{}", output::format_product_detail(&hit.data, section));
-        println!("\n- **Data from:** {}", output::format_cached_at(hit.cached_at));
+    if let Some(hit) = cache.get_brand::<model::SearchResult>(&slug, sort) {
+        let mut result = hit.data;
+        relabel_listing_currency(config, &mut result);
+        sanitize_listing(config, &mut result);
+        result.products.truncate(limit);
+        print_brand(config, &result, hit.cached_at);
         return Ok(());
     }
 
-    let session = get_or_launch_browser(config, browser_session).await?;
-    let page = session.new_page().await?;
-    let navigator = Navigator::new(config.delay_ms);
+    if config.offline {
+        return Err(IherbError::Offline(format!("brand \"{}\"", name)).into());
+    }
 
     let base_url = config.base_url();
-    let url = format!("{}/pr/item/{}", base_url, product_id);
-
-    let html = navigator
-        .navigate_with_retry(&page, &url, 2)
-        .await
-        .context("Failed to navigate to product page")?;
+    let streaming = config.format == OutputFormat::JsonLines;
+    let (mut result, _) = fetch_listing(
+        config,
+        browser_session,
+        "brand",
+        name,
+        ListingLimit {
+            limit,
+            max_pages: scraper::search::pages_needed(limit),
+            keep: &|_| true,
+        },
+        |page_num| scraper::search::build_brand_url(&base_url, name, sort, page_num),
+        async |products, _| stream_products(config, products),
+    )
+    .await?;
 
-    if scraper::helpers::is_not_found_page(&html) {
-        anyhow::bail!("Product not found: {}", product_id);
+    if result.products.is_empty() {
+        anyhow::bail!("No products found for brand: {}", name);
     }
 
-    let product =
-        scraper::product::extract_product(&page, &html, &product_id, &base_url, &config.currency)
-            .await
-            .context("Failed to extract product data")?;
+    if !scraper::search::is_in_sort_order(&result.products, sort) {
+        tracing::warn!(
+            "iHerb did not honor sort order '{}', sorting results locally",
+            sort.as_cache_key()
+        );
+        scraper::search::sort_products(&mut result.products, sort);
+    }
 
-    // Validate the extracted product to catch nonexistent product pages that slip
-    // through extraction (e.g., iHerb returns a page that doesn't trigger 404 detection
-    // but has no real product data).
-    if product.name.is_empty()
-        || product.name == "Unknown Product"
-        || (product.price == 0.0 && product.rating.is_none() && product.review_count.is_none())
-    {
-        anyhow::bail!("Product not found: {}", product_id);
+    if let Err(e) = cache.set_brand(&slug, sort, &result).await {
+        tracing::debug!("Failed to cache brand results: {}", e);
     }
 
-    if let Err(e) = cache.set_product(&product_id, &product) {
-        tracing::debug!("Failed to cache product data: {}", e);
+    if streaming {
+        return Ok(());
     }
 
-    print!("{}", output::format_product_detail(&product, section));
-    println!("\n- **Data from:** {}", output::format_cached_at(SystemTime::now()));
+    result.products.truncate(limit);
+    sanitize_listing(config, &mut result);
+    print_brand(config, &result, SystemTime::now());
     Ok(())
 }
 
+async fn cmd_categories(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    parent: Option<&str>,
+) -> Result<()> {
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+    let tree = load_category_tree(config, browser_session, &cache).await?;
+
+    let categories = match parent {
+        Some(id) => match scraper::categories::find_category(&tree, id) {
+            Some(category) => &category.children,
+            None => {
+                return Err(IherbError::InvalidCategory(format!(
+                    "No category with id '{}'. Run `iherb-cli categories` to list valid ids",
+                    id
+                ))
+                .into())
+            }
+        },
+        None => &tree,
+    };
+
+    match config.format {
+        OutputFormat::Json => println!("{}", output::format_json(categories)),
+        OutputFormat::JsonLines => {
+            for category in categories {
+                println!("{}", output::format_json_line(category));
+            }
+        }
+        OutputFormat::Markdown => print!("{}", output::format_category_tree(categories)),
+    }
+    Ok(())
+}
+
+/// Load the category tree from the cache, scraping iHerb's category page on first use.
+async fn load_category_tree(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    cache: &Cache,
+) -> Result<Vec<model::Category>> {
+    if let Some(hit) = cache.get_categories() {
+        return Ok(hit.data);
+    }
+
+    if config.offline {
+        return Err(IherbError::Offline("the category list".to_string()).into());
+    }
+
+    let session = get_or_launch_browser(config, browser_session).await?;
+    let page = session.new_page().await?;
+    let navigator = Navigator::new(config);
+
+    let base_url = config.base_url();
+    let url = scraper::categories::build_categories_url(&base_url);
+    let nav = navigator
+        .navigate_with_retry(&page, &url, None, 2)
+        .await
+        .context("Failed to navigate to category list")?;
+    scraper::helpers::dump_html(&nav.html, "categories", config.dump_html_dir.as_deref());
+
+    let tree = scraper::categories::parse_categories_from_html(&nav.html, &base_url);
+    if tree.is_empty() {
+        anyhow::bail!("Could not read iHerb's category list. Pass a numeric category id instead");
+    }
+
+    if let Err(e) = cache.set_categories(&tree).await {
+        tracing::debug!("Failed to cache category list: {}", e);
+    }
+    Ok(tree)
+}
+
+/// How much of a listing `fetch_listing` collects.
+struct ListingLimit<'a> {
+    /// Products `keep` accepts to collect.
+    limit: usize,
+    /// Pages to fetch at most.
+    max_pages: usize,
+    keep: &'a dyn Fn(&model::ProductSummary) -> bool,
+}
+
+/// Page through a product listing (search results or a brand page) until `limit`
+/// products that `keep` accepts are collected, `max_pages` pages are fetched or the
+/// listing runs out. `on_page` sees each page's new products, up to `limit` in total,
+/// as soon as the page is parsed, along with every product fetched so far.
+///
+/// Returns the listing along with the number of pages navigated.
+async fn fetch_listing(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    kind: &str,
+    label: &str,
+    wanted: ListingLimit<'_>,
+    build_url: impl Fn(usize) -> String,
+    mut on_page: impl AsyncFnMut(&[model::ProductSummary], &[model::ProductSummary]),
+) -> Result<(model::SearchResult, u32)> {
+    let ListingLimit {
+        limit,
+        max_pages,
+        keep,
+    } = wanted;
+    let session = get_or_launch_browser(config, browser_session).await?;
+    let mut page = session.new_page().await?;
+    let navigator = Navigator::new(config);
+
+    let base_url = config.base_url();
+    let mut all_products: Vec<model::ProductSummary> = Vec::new();
+    let mut kept = 0;
+    let mut total_results = None;
+    let mut effective_country = None;
+    let mut pages_fetched = 0;
+
+    for page_num in 1..=max_pages {
+        let listed_all = total_results.is_some_and(|total| all_products.len() >= total as usize);
+        if kept >= limit || listed_all {
+            break;
+        }
+        if page_num > 1 {
+            navigator.rate_limit_delay().await;
+        }
+
+        let url = build_url(page_num);
+        let page_label = format!("{}_{}_p{}", kind, label, page_num);
+        let mut attempt = 0;
+        let page_result = loop {
+            let nav = match navigator
+                .navigate_with_retry(&page, &url, Some(SEARCH_READY_SELECTOR), 2)
+                .await
+            {
+                Err(e @ IherbError::CloudflareBlocked(_)) => {
+                    match fresh_page_after_block(config, browser_session, e).await {
+                        Ok(fresh) => {
+                            page = fresh;
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                other => other,
+            };
+            let nav = match nav {
+                Ok(nav) => nav,
+                Err(e) => {
+                    let status = e.http_status();
+                    let e = anyhow::Error::from(e).context("Failed to navigate to listing page");
+                    return Err(failed_page(config, &page, &page_label, status, e).await);
+                }
+            };
+            scraper::helpers::dump_html(&nav.html, &page_label, config.dump_html_dir.as_deref());
+            if effective_country.is_none() {
+                effective_country = nav.effective_country;
+            }
+            if page_num == 1 && attempt == 0 {
+                if let Some(false) = scraper::search::sort_indicator_matches(&nav.html, &url) {
+                    tracing::warn!(
+                        "iHerb's sort control does not match the requested sort ({}); the sort parameter may be outdated",
+                        url
+                    );
+                }
+            }
+
+            let extracted = scraper::search::extract_search(
+                &page,
+                &nav.html,
+                label,
+                &base_url,
+                config.fallback_currency(),
+            )
+            .await;
+            let page_result = match extracted {
+                Ok(page_result) => page_result,
+                Err(e) => {
+                    let e = anyhow::Error::from(e).context("Failed to extract product listing");
+                    return Err(failed_page(config, &page, &page_label, nav.status, e).await);
+                }
+            };
+
+            // An empty first page, or an empty page while more results are known to
+            // exist, usually means the product grid had not hydrated yet.
+            let more_expected = page_num == 1
+                || total_results.is_some_and(|total| (all_products.len() as u32) < total);
+            if page_result.products.is_empty() && more_expected && attempt < EXTRACTION_RETRIES {
+                attempt += 1;
+                tracing::warn!(
+                    "Listing page {} returned no products, re-navigating (attempt {}/{})",
+                    page_num,
+                    attempt,
+                    EXTRACTION_RETRIES
+                );
+                tokio::time::sleep(HYDRATION_WAIT).await;
+                continue;
+            }
+            break page_result;
+        };
+        pages_fetched += 1;
+
+        if page_result.products.is_empty() {
+            // An empty first page fails the command; it may be selector drift, so
+            // keep a record of what the page showed
+            if page_num == 1 {
+                let e = anyhow::anyhow!("No products on listing page {}", url);
+                failed_page(config, &page, &page_label, None, e).await;
+            }
+            break;
+        }
+
+        if total_results.is_none() {
+            total_results = page_result.total_results;
+        }
+
+        let start = all_products.len();
+        let shown = limit.saturating_sub(start).min(page_result.products.len());
+        kept += page_result.products.iter().filter(|p| keep(p)).count();
+        all_products.extend(page_result.products);
+        on_page(&all_products[start..start + shown], &all_products).await;
+    }
+
+    let result = model::SearchResult {
+        query: label.to_string(),
+        total_results,
+        products: all_products,
+        effective_country,
+    };
+    Ok((result, pages_fetched))
+}
+
+/// Label cached prices whose currency was a fallback with the current fallback, so
+/// `--strict-currency` applies to cached data as it does to fresh fetches.
+fn relabel_defaulted_currency(
+    config: &AppConfig,
+    currency: &mut String,
+    source: Option<CurrencySource>,
+) {
+    if source == Some(CurrencySource::Defaulted) {
+        *currency = config.fallback_currency().to_string();
+    }
+}
+
+fn relabel_listing_currency(config: &AppConfig, result: &mut model::SearchResult) {
+    for product in &mut result.products {
+        relabel_defaulted_currency(config, &mut product.currency, product.currency_source);
+    }
+}
+
+/// With `--sanitize`, strip emoji and non-printable characters from a product's name
+/// and brand. Applied to output only; the cache keeps the raw text.
+fn sanitize_names(config: &AppConfig, name: &mut String, brand: &mut String) {
+    if config.sanitize {
+        *name = output::sanitize_text(name);
+        *brand = output::sanitize_text(brand);
+    }
+}
+
+/// Drop a product's field provenance from the output unless `--explain` is given.
+fn explain_product(config: &AppConfig, product: &mut model::ProductDetail) {
+    if !config.explain {
+        product.provenance = None;
+    }
+}
+
+fn sanitize_listing(config: &AppConfig, result: &mut model::SearchResult) {
+    for product in &mut result.products {
+        sanitize_names(config, &mut product.name, &mut product.brand);
+    }
+}
+
+/// A fetched item along with where it came from.
+struct Fetched<T> {
+    data: T,
+    fetched_at: SystemTime,
+    from_cache: bool,
+}
+
+/// How a product is shown: `--section` and `--nutrient`.
+#[derive(Clone, Copy)]
+struct ProductView<'a> {
+    section: Option<Section>,
+    /// Only show nutrition rows whose name contains this, ignoring case.
+    nutrient: Option<&'a str>,
+}
+
+async fn cmd_product(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    ids: &[String],
+    view: ProductView<'_>,
+    output_dir: Option<&Path>,
+    min_servings: Option<u32>,
+    fetch: ProductFetch,
+) -> Result<()> {
+    if let ([id_or_url], None) = (ids, output_dir) {
+        let fetched = fetch_product_retrying(config, browser_session, id_or_url, fetch).await?;
+        if !meets_min_servings(&fetched.data, min_servings) {
+            print_servings_skip(config, id_or_url, min_servings);
+            return Ok(());
+        }
+        print_product(config, &fetched.data, view, fetched.fetched_at);
+        return Ok(());
+    }
+
+    if let Some(dir) = output_dir {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create output directory {}", dir.display()))?;
+    }
+
+    let mut batch = BatchRunner::new(config, ids.len());
+    let mut products = Vec::new();
+    let mut skipped = 0;
+    let mut timed_out = Vec::new();
+
+    for id_or_url in ids {
+        batch.pace().await;
+        let result = fetch_product_retrying(config, browser_session, id_or_url, fetch).await;
+        if result.as_ref().is_err_and(is_timeout) {
+            timed_out.push(id_or_url.as_str());
+        }
+        match result {
+            Ok(fetched) => {
+                batch.record_success(fetched.from_cache);
+                if !meets_min_servings(&fetched.data, min_servings) {
+                    print_servings_skip(config, id_or_url, min_servings);
+                    skipped += 1;
+                    continue;
+                }
+                if let Some(dir) = output_dir {
+                    match write_product_file(config, dir, &fetched, view) {
+                        Ok(path) => {
+                            println!("{}", path.display());
+                            products.push(fetched.data);
+                        }
+                        Err(e) => batch.record_failure(id_or_url, e)?,
+                    }
+                    continue;
+                }
+                match config.format {
+                    OutputFormat::Markdown => {
+                        if !products.is_empty() {
+                            println!("\n---\n");
+                        }
+                        print_product(config, &fetched.data, view, fetched.fetched_at);
+                    }
+                    OutputFormat::JsonLines => {
+                        print_product(config, &fetched.data, view, fetched.fetched_at)
+                    }
+                    OutputFormat::Json => {}
+                }
+                products.push(fetched.data);
+            }
+            Err(e) => batch.record_failure(id_or_url, e)?,
+        }
+    }
+
+    if config.format == OutputFormat::Json && output_dir.is_none() {
+        println!("{}", output::format_json(&products));
+    }
+
+    match output_dir {
+        _ if config.quiet => {}
+        Some(dir) => eprintln!(
+            "\nWrote {} of {} products to {}.",
+            products.len(),
+            ids.len(),
+            dir.display()
+        ),
+        None => eprintln!("\nFetched {} of {} products.", products.len(), ids.len()),
+    }
+    if skipped > 0 && !config.quiet {
+        eprintln!("Skipped {} below --min-servings.", skipped);
+    }
+    if !timed_out.is_empty() && !config.quiet {
+        eprintln!("Timed out: {}.", timed_out.join(", "));
+    }
+    batch.finish()
+}
+
+async fn cmd_compare(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    ids: &[String],
+    min_servings: Option<u32>,
+    nutrient: Option<&str>,
+) -> Result<()> {
+    if ids.len() < 2 {
+        anyhow::bail!("compare needs two or more products, or one product with --countries");
+    }
+    let navigator = Navigator::new(config);
+    let mut products = Vec::new();
+    let mut fetched_live = false;
+
+    for id_or_url in ids {
+        if fetched_live {
+            navigator.product_delay().await;
+        }
+        let fetched = fetch_product(config, browser_session, id_or_url)
+            .await
+            .with_context(|| format!("Failed to fetch {}", id_or_url))?;
+        fetched_live = !fetched.from_cache;
+        if !meets_min_servings(&fetched.data, min_servings) {
+            print_servings_skip(config, id_or_url, min_servings);
+            continue;
+        }
+        products.push(fetched.data);
+    }
+    if products.len() < 2 {
+        anyhow::bail!(
+            "Fewer than two products have at least {} servings per container",
+            min_servings.unwrap_or_default()
+        );
+    }
+
+    // Prices from different storefronts are not comparable side by side
+    let mut currencies: Vec<&str> = products.iter().map(|p| p.currency.as_str()).collect();
+    currencies.sort_unstable();
+    currencies.dedup();
+    if currencies.len() > 1 {
+        anyhow::bail!(
+            "Cannot compare prices in different currencies ({}); fetch all products from the same storefront with --country",
+            currencies.join(", ")
+        );
+    }
+
+    match config.format {
+        OutputFormat::Json => println!("{}", output::format_json(&products)),
+        OutputFormat::JsonLines => {
+            for product in &products {
+                println!("{}", output::format_json_line(product));
+            }
+        }
+        OutputFormat::Markdown => match nutrient {
+            Some(nutrient) => print!(
+                "{}",
+                output::format_nutrient_comparison(&products, nutrient)
+            ),
+            None => print!(
+                "{}",
+                output::format_comparison(&products, config.round_prices)
+            ),
+        },
+    }
+    Ok(())
+}
+
+/// `compare --countries`: fetch one product from several storefronts at once, each in
+/// its own browser with its own profile, with at most `max_browsers` running at a
+/// time. Results are reported in the order the storefronts were given.
+async fn cmd_compare_countries(
+    config: &AppConfig,
+    ids: &[String],
+    countries: &[String],
+    max_browsers: u32,
+) -> Result<()> {
+    let [id_or_url] = ids else {
+        anyhow::bail!("--countries compares exactly one product across storefronts");
+    };
+    if config.offline {
+        anyhow::bail!(
+            "--countries fetches every storefront live; the cache doesn't tell storefronts apart"
+        );
+    }
+    for country in countries {
+        AppConfig::validate_country(country)?;
+    }
+
+    // Resolve (and if needed download) Chrome once, rather than from every browser at once
+    browser::resolve::resolve_chrome(
+        config.browser_path.as_ref(),
+        &config.data_dir,
+        config.browser,
+        config.no_download,
+        config.quiet,
+    )
+    .await
+    .context("Failed to resolve Chrome browser")?;
+
+    let fetches = countries.iter().map(|country| async move {
+        let config = config.for_country(country);
+        let mut session = None;
+        let fetched = fetch_product(&config, &mut session, id_or_url).await;
+        if let Some(session) = session {
+            if let Err(e) = session.close().await {
+                tracing::warn!("Failed to close the {} browser: {}", country, e);
+            }
+        }
+        fetched
+    });
+    let results: Vec<_> = futures::stream::iter(fetches)
+        .buffered(max_browsers as usize)
+        .collect()
+        .await;
+
+    let mut batch = BatchRunner::new(config, countries.len());
+    let mut products = Vec::new();
+    for (country, result) in countries.iter().zip(results) {
+        match result {
+            Ok(fetched) => {
+                batch.record_success(fetched.from_cache);
+                products.push((country.as_str(), fetched.data));
+            }
+            Err(e) => batch.record_failure(country, e)?,
+        }
+    }
+
+    match config.format {
+        OutputFormat::Json => {
+            let mut by_country = serde_json::Map::new();
+            for (country, product) in &products {
+                by_country.insert(country.to_string(), serde_json::to_value(product)?);
+            }
+            println!("{}", output::format_json(&by_country));
+        }
+        OutputFormat::JsonLines => {
+            for (_, product) in &products {
+                println!("{}", output::format_json_line(product));
+            }
+        }
+        OutputFormat::Markdown => print!(
+            "{}",
+            output::format_storefront_comparison(&products, config.round_prices)
+        ),
+    }
+    batch.finish()
+}
+
+/// Run each setup step in turn, reporting every outcome, and fail with the first
+/// critical error.
+async fn cmd_doctor(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+) -> Result<()> {
+    let mut checks = vec![
+        model::DoctorCheck {
+            name: "Cache dir".to_string(),
+            ok: true,
+            detail: config.cache_dir.display().to_string(),
+        },
+        model::DoctorCheck {
+            name: "Data dir".to_string(),
+            ok: true,
+            detail: config.data_dir.display().to_string(),
+        },
+    ];
+    let failure = doctor_checks(config, browser_session, &mut checks)
+        .await
+        .err();
+
+    match config.format {
+        OutputFormat::Json => println!("{}", output::format_json(&checks)),
+        OutputFormat::JsonLines => {
+            for check in &checks {
+                println!("{}", output::format_json_line(check));
+            }
+        }
+        OutputFormat::Markdown => print!("{}", output::format_doctor(&checks)),
+    }
+
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+fn cmd_list_cache(config: &AppConfig) -> Result<()> {
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+    let entries = cache.list()?;
+    match config.format {
+        OutputFormat::Json => println!("{}", output::format_json(&entries)),
+        OutputFormat::JsonLines => {
+            for entry in &entries {
+                println!("{}", output::format_json_line(entry));
+            }
+        }
+        OutputFormat::Markdown => print!("{}", output::format_cache_entries(&entries)),
+    }
+    Ok(())
+}
+
+/// Print the version and platform; with `check`, also the browser that would be used
+/// and whether Chrome for Testing has a newer stable release. Never downloads Chrome.
+async fn cmd_version(config: &AppConfig, check: bool) -> Result<()> {
+    let mut report = model::VersionReport {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        os: std::env::consts::OS.to_string(),
+        arch: std::env::consts::ARCH.to_string(),
+        browser_path: None,
+        browser_version: None,
+        browser_error: None,
+        latest_chrome: None,
+        chrome_update_available: None,
+    };
+
+    if check {
+        let resolved = browser::resolve::resolve_chrome(
+            config.browser_path.as_ref(),
+            &config.data_dir,
+            config.browser,
+            true,
+            config.quiet,
+        )
+        .await;
+        match resolved {
+            Ok(path) => {
+                match browser::resolve::probe_version(&path).await {
+                    Ok(version) => report.browser_version = Some(version),
+                    Err(e) => report.browser_error = Some(e.to_string()),
+                }
+                report.browser_path = Some(path.display().to_string());
+            }
+            // With downloads ruled out, resolving only fails when nothing is installed
+            Err(_) => {
+                report.browser_error = Some("no installed or downloaded browser found".to_string())
+            }
+        }
+
+        if !config.offline {
+            match browser::download::latest_stable_version().await {
+                Ok(latest) => {
+                    report.chrome_update_available = report
+                        .browser_version
+                        .as_deref()
+                        .and_then(version_numbers)
+                        .zip(version_numbers(&latest))
+                        .map(|(installed, latest)| installed < latest);
+                    report.latest_chrome = Some(latest);
+                }
+                Err(e) => tracing::warn!("Could not look up the latest Chrome version: {}", e),
+            }
+        }
+    }
+
+    match config.format {
+        OutputFormat::Json => println!("{}", output::format_json(&report)),
+        OutputFormat::JsonLines => println!("{}", output::format_json_line(&report)),
+        OutputFormat::Markdown => print!("{}", output::format_version_report(&report)),
+    }
+    Ok(())
+}
+
+/// The dotted version number in a browser's `--version` output, e.g. `[141, 0, 7390, 54]`
+/// from "Google Chrome 141.0.7390.54".
+fn version_numbers(text: &str) -> Option<Vec<u32>> {
+    text.split_whitespace()
+        .find(|word| word.contains('.') && word.starts_with(|c: char| c.is_ascii_digit()))
+        .and_then(|word| word.split('.').map(|part| part.parse().ok()).collect())
+}
+
+/// The critical `doctor` steps. Each records its result; the first failure stops the run.
+async fn doctor_checks(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    checks: &mut Vec<model::DoctorCheck>,
+) -> Result<()> {
+    let mut record = |name: &str, ok: bool, detail: String| {
+        checks.push(model::DoctorCheck {
+            name: name.to_string(),
+            ok,
+            detail,
+        });
+    };
+
+    let resolved = browser::resolve::resolve_chrome(
+        config.browser_path.as_ref(),
+        &config.data_dir,
+        config.browser,
+        config.no_download,
+        config.quiet,
+    )
+    .await;
+    let chrome_path = match resolved {
+        Ok(path) => {
+            record("Chrome", true, path.display().to_string());
+            path
+        }
+        Err(e) => {
+            record("Chrome", false, e.to_string());
+            return Err(e.into());
+        }
+    };
+
+    let session = match BrowserSession::launch(chrome_path, config).await {
+        Ok(session) => browser_session.insert(session),
+        Err(e) => {
+            record("Browser launch", false, e.to_string());
+            return Err(e.into());
+        }
+    };
+    let version = session
+        .version()
+        .await
+        .unwrap_or_else(|_| "version unknown".to_string());
+    record("Browser launch", true, version);
+
+    let page = session.new_page().await?;
+    let navigator = Navigator::new(config);
+    let url = config.base_url();
+    let nav = match navigator.navigate(&page, &url, None).await {
+        Ok(nav) => nav,
+        Err(e) => {
+            record("iHerb reachable", false, e.to_string());
+            return Err(e.into());
+        }
+    };
+    let status = nav
+        .status
+        .map(|s| format!("HTTP {}", s))
+        .unwrap_or_else(|| "loaded".to_string());
+    record("iHerb reachable", true, format!("{} ({})", url, status));
+
+    let challenge = if nav.challenged {
+        "challenge shown and passed"
+    } else {
+        "no challenge"
+    };
+    record("Cloudflare", true, challenge.to_string());
+
+    let storefront = match nav.effective_country {
+        Some(ref country) if *country != config.country => {
+            format!("requested `{}`, served `{}`", config.country, country)
+        }
+        Some(country) => country,
+        None => "unknown".to_string(),
+    };
+    record("Storefront", true, storefront);
+    Ok(())
+}
+
+/// Write one product to `{dir}/{product_id}.{ext}` in the configured format.
+fn write_product_file(
+    config: &AppConfig,
+    dir: &Path,
+    fetched: &Fetched<model::ProductDetail>,
+    view: ProductView<'_>,
+) -> Result<PathBuf> {
+    let extension = match config.format {
+        OutputFormat::Markdown => "md",
+        OutputFormat::Json | OutputFormat::JsonLines => "json",
+    };
+    let path = dir.join(format!("{}.{}", fetched.data.product_id, extension));
+    let content = render_product(config, &fetched.data, view, fetched.fetched_at);
+    std::fs::write(&path, content)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(path)
+}
+
+/// Fetch a single product, from the cache when possible, otherwise via the browser.
+async fn fetch_product(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    id_or_url: &str,
+) -> Result<Fetched<model::ProductDetail>> {
+    let (product_id, id_or_url) = match parse_product_identifier(id_or_url) {
+        Ok(product_id) => (product_id, id_or_url.to_string()),
+        Err(e) => match resolve_share_url(config, id_or_url).await {
+            Some(resolved) => resolved,
+            None => return Err(e),
+        },
+    };
+    let id_or_url = id_or_url.as_str();
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+
+    // Products cached before provenance was recorded can't be explained; fetch them again
+    let hit = cache
+        .get_product::<model::ProductDetail>(&product_id)
+        .filter(|hit| !config.explain || config.offline || hit.data.provenance.is_some());
+    if let Some(mut hit) = hit {
+        relabel_defaulted_currency(config, &mut hit.data.currency, hit.data.currency_source);
+        sanitize_names(config, &mut hit.data.name, &mut hit.data.brand);
+        explain_product(config, &mut hit.data);
+        return Ok(Fetched {
+            data: hit.data,
+            fetched_at: hit.cached_at,
+            from_cache: true,
+        });
+    }
+
+    if config.offline {
+        return Err(IherbError::Offline(format!("product {}", product_id)).into());
+    }
+
+    let session = get_or_launch_browser(config, browser_session).await?;
+    let mut page = session.new_page().await?;
+    let navigator = Navigator::new(config);
+
+    let base_url = config.base_url();
+    let mut urls = product_url_candidates(&base_url, id_or_url, &product_id);
+    let mut tried = Vec::new();
+
+    let label = format!("product_{}", product_id);
+    let product = 'urls: loop {
+        let Some(url) = urls.pop_front() else {
+            return Err(IherbError::ProductNotFound(product_id).into());
+        };
+        tried.push(url.clone());
+
+        let mut attempt = 0;
+        loop {
+            let nav = match navigator
+                .navigate_with_retry(&page, &url, Some(PRODUCT_READY_SELECTOR), 2)
+                .await
+            {
+                Err(e @ IherbError::CloudflareBlocked(_)) => {
+                    match fresh_page_after_block(config, browser_session, e).await {
+                        Ok(fresh) => {
+                            page = fresh;
+                            continue;
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                other => other,
+            };
+            let nav = match nav {
+                Ok(nav) => nav,
+                Err(e) => {
+                    let status = e.http_status();
+                    let e = anyhow::Error::from(e).context("Failed to navigate to product page");
+                    return Err(failed_page(config, &page, &label, status, e).await);
+                }
+            };
+            scraper::helpers::dump_html(&nav.html, &label, config.dump_html_dir.as_deref());
+
+            // A page that names its canonical URL is worth trying before other guesses
+            if let Some(canonical) = canonical_product_url(&nav.html, &base_url) {
+                if !tried.contains(&canonical) && !urls.contains(&canonical) {
+                    urls.push_front(canonical);
+                }
+            }
+
+            // Trust the HTTP status when Chrome reported one; the page text is
+            // localized on non-English storefronts.
+            let not_found = match nav.status {
+                Some(status) => status == 404 || status == 410,
+                None => scraper::helpers::is_not_found_page(
+                    &nav.html,
+                    nav.effective_country.as_deref().unwrap_or(&config.country),
+                ),
+            };
+            if not_found {
+                tracing::info!("{} not found, trying the next URL form", url);
+                continue 'urls;
+            }
+
+            let extracted = scraper::product::extract_product(
+                &page,
+                &nav.html,
+                &product_id,
+                &base_url,
+                config.fallback_currency(),
+                &config.extract_order,
+                config.enrich_dom,
+            )
+            .await;
+
+            // A discontinued listing never hydrates into a product, so report it
+            // instead of retrying
+            if !matches!(&extracted, Ok(product) if is_real_product(product)) {
+                if let Some(alternatives) =
+                    scraper::product::detect_discontinued(&nav.html, &product_id)
+                {
+                    return Err(IherbError::Discontinued {
+                        id: product_id,
+                        alternatives,
+                    }
+                    .into());
+                }
+            }
+
+            // A page that loaded but yields no usable product has often not hydrated
+            // yet, so re-navigate a couple of times before declaring it not found.
+            match extracted {
+                Ok(mut product) if is_real_product(&product) => {
+                    product.effective_country = nav.effective_country;
+                    break 'urls product;
+                }
+                Ok(_) | Err(IherbError::ProductNotFound(_)) if attempt < EXTRACTION_RETRIES => {
+                    attempt += 1;
+                    tracing::warn!(
+                        "Product {} page had no product data, re-navigating (attempt {}/{})",
+                        product_id,
+                        attempt,
+                        EXTRACTION_RETRIES
+                    );
+                    tokio::time::sleep(HYDRATION_WAIT).await;
+                }
+                // The last candidate loaded but had no product: likely selector drift
+                Ok(_) | Err(IherbError::ProductNotFound(_)) if urls.is_empty() => {
+                    let e = IherbError::ProductNotFound(product_id.clone()).into();
+                    return Err(failed_page(config, &page, &label, nav.status, e).await);
+                }
+                Ok(_) | Err(IherbError::ProductNotFound(_)) => continue 'urls,
+                Err(e) => {
+                    let e = anyhow::Error::from(e).context("Failed to extract product data");
+                    return Err(failed_page(config, &page, &label, nav.status, e).await);
+                }
+            }
+        }
+    };
+
+    // A forced extraction order may give worse data than the default; keep it out of the cache
+    if config.extract_order != cli::ExtractStrategy::ALL {
+        tracing::debug!("Not caching {}: non-default extraction order", product_id);
+    } else if !config.enrich_dom {
+        // Nor a product missing its DOM-only fields, which a later full fetch would be served
+        tracing::debug!("Not caching {}: DOM enrichment skipped", product_id);
+    } else if let Err(e) = cache.set_product(&product_id, &product).await {
+        tracing::debug!("Failed to cache product data: {}", e);
+    }
+
+    let mut product = product;
+    sanitize_names(config, &mut product.name, &mut product.brand);
+    explain_product(config, &mut product);
+    Ok(Fetched {
+        data: product,
+        fetched_at: SystemTime::now(),
+        from_cache: false,
+    })
+}
+
+/// How `product` fetches each product.
+#[derive(Clone, Copy)]
+struct ProductFetch {
+    /// Also load the customer Q&A.
+    include_qa: bool,
+    /// `--timeout-per-product`: abandon a product taking longer than this.
+    timeout: Option<Duration>,
+}
+
+/// `fetch_product_with_qa`, abandoned with a timeout error once `fetch.timeout`
+/// runs out.
+async fn fetch_product_timed(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    id_or_url: &str,
+    fetch: ProductFetch,
+) -> Result<Fetched<model::ProductDetail>> {
+    let fetching = fetch_product_with_qa(config, browser_session, id_or_url, fetch.include_qa);
+    let Some(limit) = fetch.timeout else {
+        return fetching.await;
+    };
+    match tokio::time::timeout(limit, fetching).await {
+        Ok(result) => result,
+        Err(elapsed) => Err(anyhow::Error::new(elapsed).context(format!(
+            "Gave up after {}s (--timeout-per-product)",
+            limit.as_secs()
+        ))),
+    }
+}
+
+/// `fetch_product_timed`, retried once on the relaunched browser when the browser
+/// crashed mid-fetch. A product that timed out is not retried.
+async fn fetch_product_retrying(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    id_or_url: &str,
+    fetch: ProductFetch,
+) -> Result<Fetched<model::ProductDetail>> {
+    match fetch_product_timed(config, browser_session, id_or_url, fetch).await {
+        Err(e) if !is_timeout(&e) && browser_crashed(browser_session).await => {
+            tracing::warn!("Browser crashed while fetching {}: {:#}", id_or_url, e);
+            fetch_product_timed(config, browser_session, id_or_url, fetch).await
+        }
+        other => other,
+    }
+}
+
+fn is_timeout(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<tokio::time::error::Elapsed>()
+        .is_some()
+}
+
+/// `fetch_product`, plus the customer Q&A with `--include-qa`. The Q&A is stored in
+/// the product's cache entry, so it is loaded once; without the flag it is left out.
+/// A Q&A page that fails to load only costs a warning.
+async fn fetch_product_with_qa(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    id_or_url: &str,
+    include_qa: bool,
+) -> Result<Fetched<model::ProductDetail>> {
+    let mut fetched = fetch_product(config, browser_session, id_or_url).await?;
+    if !include_qa {
+        fetched.data.qa = None;
+        return Ok(fetched);
+    }
+    if fetched.data.qa.is_some() {
+        return Ok(fetched);
+    }
+    if config.offline {
+        tracing::warn!(
+            "No cached Q&A for product {} in offline mode",
+            fetched.data.product_id
+        );
+        return Ok(fetched);
+    }
+
+    let product_id = fetched.data.product_id.clone();
+    let qa = match fetch_qa(config, browser_session, &product_id).await {
+        Ok(qa) => qa,
+        Err(e) => {
+            tracing::warn!("Failed to load Q&A for product {}: {:#}", product_id, e);
+            return Ok(fetched);
+        }
+    };
+
+    // Add the Q&A to the raw cache entry, not the relabeled and sanitized copy
+    let cache = Cache::new(
+        config.cache_dir.clone(),
+        config.no_cache,
+        config.offline_stale_ok,
+        config.cache_shard,
+    );
+    if let Some(mut hit) = cache.get_product::<model::ProductDetail>(&product_id) {
+        hit.data.qa = Some(qa.clone());
+        if let Err(e) = cache.set_product(&product_id, &hit.data).await {
+            tracing::debug!("Failed to cache product Q&A: {}", e);
+        }
+    }
+    fetched.data.qa = Some(qa);
+    Ok(fetched)
+}
+
+/// Load a product's customer questions page and parse its answered questions.
+async fn fetch_qa(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    product_id: &str,
+) -> Result<Vec<model::QaEntry>> {
+    let session = get_or_launch_browser(config, browser_session).await?;
+    let page = session.new_page().await?;
+    let navigator = Navigator::new(config);
+
+    navigator.rate_limit_delay().await;
+    let url = scraper::qa::build_qa_url(&config.base_url(), product_id);
+    let nav = navigator
+        .navigate_with_retry(&page, &url, None, 2)
+        .await
+        .context("Failed to navigate to the Q&A page")?;
+    scraper::helpers::dump_html(
+        &nav.html,
+        &format!("qa_{}", product_id),
+        config.dump_html_dir.as_deref(),
+    );
+    Ok(scraper::qa::parse_qa_from_html(&nav.html))
+}
+
+/// URLs to try for a product, in order: the `/pr/item/{id}` shortcut, the URL the
+/// user gave (which may carry the slug iHerb expects), and the `/pr/p/{id}` form.
+fn product_url_candidates(base_url: &str, id_or_url: &str, product_id: &str) -> VecDeque<String> {
+    let mut urls = VecDeque::from([format!("{}/pr/item/{}", base_url, product_id)]);
+    if id_or_url.starts_with("http") && id_or_url.contains("/pr/") {
+        urls.push_back(id_or_url.to_string());
+    }
+    let short = format!("{}/pr/p/{}", base_url, product_id);
+    if !urls.contains(&short) {
+        urls.push_back(short);
+    }
+    urls
+}
+
+/// The canonical product URL named by the page's JSON-LD, made absolute.
+fn canonical_product_url(html: &str, base_url: &str) -> Option<String> {
+    let json_ld = scraper::extract::extract_json_ld(html)?;
+    let url = json_ld.get("url")?.as_str()?;
+    if url.starts_with("http") {
+        Some(url.to_string())
+    } else {
+        Some(format!("{}{}", base_url, url))
+    }
+}
+
+/// In `--format json-lines`, print products as soon as they are scraped.
+fn stream_products(config: &AppConfig, products: &[model::ProductSummary]) {
+    if config.format != OutputFormat::JsonLines {
+        return;
+    }
+    let mut stdout = std::io::stdout().lock();
+    for product in products {
+        let line = if config.sanitize {
+            let mut product = product.clone();
+            sanitize_names(config, &mut product.name, &mut product.brand);
+            output::format_json_line(&product)
+        } else {
+            output::format_json_line(product)
+        };
+        let _ = writeln!(stdout, "{}", line);
+    }
+    let _ = stdout.flush();
+}
+
+/// Print a search's summaries, or with `--enrich` its product details.
+fn print_search_output(
+    config: &AppConfig,
+    output: &SearchOutput,
+    fetched_at: SystemTime,
+    meta: Option<model::ResponseMeta>,
+) {
+    let result = match output {
+        SearchOutput::Summaries(result) => return print_search(config, result, fetched_at, meta),
+        SearchOutput::Details(result) => result,
+    };
+    match config.format {
+        OutputFormat::Json => match meta {
+            Some(meta) => println!("{}", output::format_json(&model::Envelope { meta, result })),
+            None => println!("{}", output::format_json(result)),
+        },
+        OutputFormat::JsonLines => {
+            let mut stdout = std::io::stdout().lock();
+            for product in &result.products {
+                let _ = writeln!(stdout, "{}", output::format_json_line(product));
+            }
+            let _ = stdout.flush();
+        }
+        OutputFormat::Markdown => {
+            print_storefront_note(config, result.effective_country.as_deref());
+            print!(
+                "{}",
+                output::format_enriched_search_results(
+                    result,
+                    config.compact_nutrition,
+                    config.round_prices,
+                )
+            );
+            println!(
+                "\n- **Data from:** {}",
+                output::format_cached_at(fetched_at)
+            );
+        }
+    }
+}
+
+/// `meta` wraps `--format json` output in an envelope; `None` prints the bare result.
+fn print_search(
+    config: &AppConfig,
+    result: &model::SearchResult,
+    fetched_at: SystemTime,
+    meta: Option<model::ResponseMeta>,
+) {
+    match config.format {
+        OutputFormat::Json => match meta {
+            Some(meta) => println!("{}", output::format_json(&model::Envelope { meta, result })),
+            None => println!("{}", output::format_json(result)),
+        },
+        OutputFormat::JsonLines => stream_products(config, &result.products),
+        OutputFormat::Markdown => {
+            print_storefront_note(config, result.effective_country.as_deref());
+            print!(
+                "{}",
+                output::format_search_results(result, config.round_prices)
+            );
+            println!(
+                "\n- **Data from:** {}",
+                output::format_cached_at(fetched_at)
+            );
+        }
+    }
+}
+
+fn response_meta(
+    pages_fetched: u32,
+    from_cache: bool,
+    fetched_at: SystemTime,
+) -> model::ResponseMeta {
+    model::ResponseMeta {
+        pages_fetched,
+        from_cache,
+        fetched_at: fetched_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs(),
+    }
+}
+
+fn print_brand(config: &AppConfig, result: &model::SearchResult, fetched_at: SystemTime) {
+    match config.format {
+        OutputFormat::Json => println!("{}", output::format_json(result)),
+        OutputFormat::JsonLines => stream_products(config, &result.products),
+        OutputFormat::Markdown => {
+            print_storefront_note(config, result.effective_country.as_deref());
+            print!(
+                "{}",
+                output::format_brand_results(result, config.round_prices)
+            );
+            println!(
+                "\n- **Data from:** {}",
+                output::format_cached_at(fetched_at)
+            );
+        }
+    }
+}
+
+fn print_product(
+    config: &AppConfig,
+    product: &model::ProductDetail,
+    view: ProductView<'_>,
+    fetched_at: SystemTime,
+) {
+    print!("{}", render_product(config, product, view, fetched_at));
+}
+
+/// A product rendered in the configured output format, as printed or written to a file.
+fn render_product(
+    config: &AppConfig,
+    product: &model::ProductDetail,
+    view: ProductView<'_>,
+    fetched_at: SystemTime,
+) -> String {
+    match config.format {
+        OutputFormat::Json => format!("{}\n", output::format_json(product)),
+        OutputFormat::JsonLines => format!("{}\n", output::format_json_line(product)),
+        OutputFormat::Markdown => format!(
+            "{}{}\n- **Data from:** {}\n",
+            storefront_note(config, product.effective_country.as_deref()).unwrap_or_default(),
+            output::format_product_detail(
+                product,
+                view.section,
+                config.compact_nutrition,
+                view.nutrient,
+                config.round_prices,
+            ),
+            output::format_cached_at(fetched_at)
+        ),
+    }
+}
+
+/// Warn in the output when iHerb served a different storefront than requested.
+fn print_storefront_note(config: &AppConfig, effective_country: Option<&str>) {
+    if let Some(note) = storefront_note(config, effective_country) {
+        print!("{}", note);
+    }
+}
+
+fn storefront_note(config: &AppConfig, effective_country: Option<&str>) -> Option<String> {
+    let actual = effective_country.filter(|c| *c != config.country)?;
+    Some(format!(
+        "> **Note:** iHerb served the `{}` storefront instead of the requested `{}`; prices and availability reflect `{}`.\n\n",
+        actual, config.country, actual
+    ))
+}
+
+/// Validate an extracted product to catch nonexistent product pages that slip
+/// through extraction (e.g., iHerb returns a page that doesn't trigger 404 detection
+/// but has no real product data).
+fn is_real_product(product: &model::ProductDetail) -> bool {
+    !(product.name.is_empty()
+        || product.name == "Unknown Product"
+        || (product.price == 0.0 && product.rating.is_none() && product.review_count.is_none()))
+}
+
 async fn get_or_launch_browser<'a>(
     config: &AppConfig,
     session: &'a mut Option<BrowserSession>,
 ) -> Result<&'a BrowserSession> {
     if session.is_none() {
-        let chrome_path =
-            browser::resolve::resolve_chrome(config.browser_path.as_ref(), &config.data_dir)
-                .await
-                .context("Failed to resolve Chrome browser")?;
+        let chrome_path = browser::resolve::resolve_chrome(
+            config.browser_path.as_ref(),
+            &config.data_dir,
+            config.browser,
+            config.no_download,
+            config.quiet,
+        )
+        .await
+        .context("Failed to resolve Chrome browser")?;
 
         let launched = BrowserSession::launch(chrome_path, config)
             .await
             .context("Failed to launch browser")?;
 
         *session = Some(launched);
+    } else if let Some(existing) = session.as_mut() {
+        if !existing.is_alive().await {
+            existing
+                .relaunch(config)
+                .await
+                .context("Failed to relaunch browser")?;
+        }
     }
     Ok(session.as_ref().unwrap())
 }
 
+/// With `--diagnostics-dir`, save a bundle describing the page `error` happened on
+/// before handing the error back.
+async fn failed_page(
+    config: &AppConfig,
+    page: &chromiumoxide::Page,
+    label: &str,
+    status: Option<u16>,
+    error: anyhow::Error,
+) -> anyhow::Error {
+    let Some(ref dir) = config.diagnostics_dir else {
+        return error;
+    };
+    match diagnostics::write_bundle(dir, label, page, status, &error).await {
+        Ok(bundle) if !config.quiet => eprintln!("Wrote diagnostics to {}", bundle.display()),
+        Ok(_) => {}
+        Err(e) => tracing::warn!("Failed to write diagnostics to {}: {}", dir.display(), e),
+    }
+    error
+}
+
+/// After Cloudflare blocked every navigation retry, restart the browser with a fresh
+/// profile and return a tab on it, up to `--cf-session-retries` times per run.
+/// Otherwise hand back the block error.
+async fn fresh_page_after_block(
+    config: &AppConfig,
+    browser_session: &mut Option<BrowserSession>,
+    blocked: IherbError,
+) -> Result<PooledPage, IherbError> {
+    let Some(session) = browser_session.as_mut() else {
+        return Err(blocked);
+    };
+    if session.cf_restarts() >= config.cf_session_retries {
+        return Err(blocked);
+    }
+    session.restart(config).await?;
+    session.new_page().await
+}
+
+async fn browser_crashed(session: &Option<BrowserSession>) -> bool {
+    match session {
+        Some(session) => !session.is_alive().await,
+        None => false,
+    }
+}
+
+fn print_servings_skip(config: &AppConfig, id_or_url: &str, min_servings: Option<u32>) {
+    if config.quiet {
+        return;
+    }
+    eprintln!(
+        "Skipping {}: fewer than {} servings per container (or unknown)",
+        id_or_url,
+        min_servings.unwrap_or_default()
+    );
+}
+
+/// Follow the redirects of an iHerb share or QR link (e.g. `/r/...`) that carries no
+/// product ID, and return the ID and URL of the product page it lands on. Uses a
+/// plain HTTP request rather than the browser; `None` when offline, not an iHerb
+/// URL, or the link does not lead to a product.
+async fn resolve_share_url(config: &AppConfig, input: &str) -> Option<(String, String)> {
+    let url = url::Url::parse(input).ok()?;
+    if config.offline || !scraper::helpers::is_iherb_host(url.host_str()?) {
+        return None;
+    }
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.timeout_secs))
+        .user_agent(match config.user_agent {
+            Some(ref user_agent) => user_agent.as_str(),
+            None => browser::session::pick_user_agent(),
+        })
+        .build()
+        .ok()?;
+    // Cloudflare may answer the product page itself with a challenge, but the
+    // final URL after the redirects is all that's needed
+    let response = match client.get(url).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::debug!("Could not follow {}: {}", input, e);
+            return None;
+        }
+    };
+    let mut resolved = response.url().clone();
+    resolved.set_query(None);
+    resolved.set_fragment(None);
+    let resolved = resolved.to_string();
+    let product_id = parse_product_identifier(&resolved).ok()?;
+    tracing::info!("Resolved {} to {}", input, resolved);
+    Some((product_id, resolved))
+}
+
 fn parse_product_identifier(input: &str) -> Result<String> {
     if input.chars().all(|c| c.is_ascii_digit()) && !input.is_empty() {
         return Ok(input.to_string());
@@ -263,3 +2699,113 @@ fn parse_product_identifier(input: &str) -> Result<String> {
         input
     );
 }
+
+/// Checks against iherb.com itself, so they need a network connection and a Chrome
+/// iherb-cli can launch: `cargo test --features live-tests`.
+#[cfg(all(test, feature = "live-tests"))]
+mod live_tests {
+    use super::*;
+
+    /// How iHerb's sort control names each sort, in lowercase fragments, and how its
+    /// results must then be ordered. Written from the site's labels, not from
+    /// `SortOrder::as_url_param`, so a wrong `sr=` value fails here.
+    const EXPECTED_SORTS: &[(SortOrder, &[&str])] = &[
+        (SortOrder::Relevance, &["relevan"]),
+        (SortOrder::PriceAsc, &["low to high"]),
+        (SortOrder::PriceDesc, &["high to low"]),
+        (SortOrder::Rating, &["rating", "rated"]),
+        (SortOrder::BestSelling, &["best sell", "bestsell"]),
+        (SortOrder::Newest, &["new"]),
+        (SortOrder::DiscountDesc, &["discount", "sale"]),
+    ];
+
+    /// Text of the sort option the page shows as active, lowercased.
+    fn active_sort_label(html: &str) -> Option<String> {
+        let doc = ::scraper::Html::parse_document(html);
+        let sel = ::scraper::Selector::parse(
+            "select[name='sr'] option[selected], select#sort-by option[selected], \
+             select.sort-select option[selected], .sort-by a.selected, .sort-by a.active, \
+             .sort-by li.active a, a[aria-current][href*='sr=']",
+        )
+        .unwrap();
+        let el = doc.select(&sel).next()?;
+        let text = el.text().collect::<Vec<_>>().join(" ").to_lowercase();
+        Some(text.split_whitespace().collect::<Vec<_>>().join(" "))
+    }
+
+    fn discount(product: &model::ProductSummary) -> f64 {
+        match product.original_price {
+            Some(list) if list > product.price => (list - product.price) / list,
+            _ => 0.0,
+        }
+    }
+
+    /// Whether `values` never goes up (`descending`) or never goes down.
+    fn monotonic(values: &[f64], descending: bool) -> bool {
+        values.windows(2).all(|pair| match descending {
+            true => pair[0] >= pair[1],
+            false => pair[0] <= pair[1],
+        })
+    }
+
+    #[tokio::test]
+    async fn iherb_honors_each_sort_order() {
+        let cli = Cli::parse_from(["iherb-cli", "--no-cache", "search", "vitamin c"]);
+        let config = AppConfig::load(cli_overrides(&cli)).unwrap();
+        let mut browser_session = None;
+        let session = get_or_launch_browser(&config, &mut browser_session)
+            .await
+            .unwrap();
+        let page = session.new_page().await.unwrap();
+        let navigator = Navigator::new(&config);
+        let base_url = config.base_url();
+
+        for (sort, labels) in EXPECTED_SORTS {
+            let url = scraper::search::build_search_url(
+                &base_url,
+                "vitamin c",
+                *sort,
+                None,
+                scraper::search::PriceRange::default(),
+                1,
+            );
+            let nav = navigator
+                .navigate_with_retry(&page, &url, Some(SEARCH_READY_SELECTOR), 2)
+                .await
+                .unwrap();
+
+            let label = active_sort_label(&nav.html)
+                .unwrap_or_else(|| panic!("{:?}: no active sort on {}", sort, url));
+            assert!(
+                labels.iter().any(|expected| label.contains(expected)),
+                "{:?}: {} shows \"{}\" as the active sort",
+                sort,
+                url,
+                label
+            );
+
+            let products = scraper::search::parse_search_from_html(
+                &nav.html,
+                "vitamin c",
+                &base_url,
+                config.fallback_currency(),
+            )
+            .unwrap()
+            .products;
+            assert!(!products.is_empty(), "{:?}: no results on {}", sort, url);
+            let prices: Vec<f64> = products.iter().map(|p| p.price).collect();
+            let ratings: Vec<f64> = products.iter().map(|p| p.rating.unwrap_or(0.0)).collect();
+            let discounts: Vec<f64> = products.iter().map(discount).collect();
+            let ordered = match sort {
+                SortOrder::PriceAsc => monotonic(&prices, false),
+                SortOrder::PriceDesc => monotonic(&prices, true),
+                SortOrder::Rating => monotonic(&ratings, true),
+                SortOrder::DiscountDesc => monotonic(&discounts, true),
+                _ => true,
+            };
+            assert!(ordered, "{:?}: {} is not in that order", sort, url);
+
+            navigator.rate_limit_delay().await;
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ProductSummary {
@@ -7,8 +8,15 @@ pub struct ProductSummary {
     pub price: f64,
     pub original_price: Option<f64>,
     pub currency: String,
+    /// Whether `currency` was read from the page or is the configured fallback.
+    pub currency_source: Option<CurrencySource>,
     pub rating: Option<f64>,
     pub review_count: Option<u32>,
+    /// `rating` smoothed toward a prior by review count, with `--bayesian-rating`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adjusted_rating: Option<f64>,
+    /// Popularity badge, e.g. "Best Seller" or "#3 in Vitamin C".
+    pub rank: Option<String>,
     pub product_url: String,
     pub product_id: String,
     pub in_stock: bool,
@@ -18,24 +26,99 @@ pub struct ProductSummary {
 pub struct ProductDetail {
     pub name: String,
     pub brand: String,
+    /// The brand's iHerb page, e.g. `https://www.iherb.com/c/now-foods`.
+    pub brand_url: Option<String>,
     pub price: f64,
     pub original_price: Option<f64>,
     pub currency: String,
+    /// Whether `currency` was read from the page or is the configured fallback.
+    pub currency_source: Option<CurrencySource>,
     pub rating: Option<f64>,
     pub review_count: Option<u32>,
+    /// Popularity badge, e.g. "Best Seller" or "#3 in Vitamin C".
+    pub rank: Option<String>,
     pub product_url: String,
     pub product_id: String,
     pub in_stock: bool,
+    /// Stock-status message shown when unavailable, e.g. an expected restock date.
+    pub availability_note: Option<String>,
     pub description: Option<String>,
+    /// Bullet-point highlights from the overview, e.g. "Non-GMO" or "Third-party tested".
+    #[serde(default)]
+    pub highlights: Vec<String>,
+    /// Full-resolution gallery images (front, back, label), in gallery order.
+    #[serde(default)]
+    pub image_urls: Vec<String>,
     pub product_code: Option<String>,
     pub upc: Option<String>,
     pub ingredients: Option<String>,
     pub supplement_facts: Option<SupplementFacts>,
     pub suggested_use: Option<String>,
     pub warnings: Option<String>,
+    /// Shelf-life guidance from the specs or overview text, e.g. "Best By: 11/2027" or
+    /// "Use within 90 days of opening."
+    pub expiration_note: Option<String>,
     pub shipping_weight: Option<String>,
+    /// `shipping_weight` parsed into a number and unit, preferring the metric value.
+    pub shipping_weight_quantity: Option<Quantity>,
+    /// Units sold together for `price` in a multi-pack or bundle listing, e.g. 2 for "Pack of 2".
+    pub pack_size: Option<u32>,
+    /// Warehouse or fulfillment origin, e.g. "iHerb US warehouse"; varies by storefront.
+    pub ships_from: Option<String>,
+    /// Delivery estimate as shown for the storefront, e.g. "3-5 business days".
+    pub estimated_delivery: Option<String>,
+    /// Other sizes/flavors of this product, including the current one.
+    #[serde(default)]
+    pub variations: Vec<Variation>,
     pub category_breadcrumb: Option<Vec<String>>,
     pub review_distribution: Option<ReviewDistribution>,
+    /// Customer questions with their top answer; only loaded with `--include-qa`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub qa: Option<Vec<QaEntry>>,
+    /// Storefront country iHerb actually served, which may differ from the requested one.
+    pub effective_country: Option<String>,
+    /// Where each major field's value was read from, by field name. Cached, but only
+    /// printed with `--explain`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<BTreeMap<String, FieldSource>>,
+}
+
+/// The extraction step a product field's value came from, for `--explain`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FieldSource {
+    JsonLd,
+    JsGlobals,
+    NextData,
+    Dom,
+    /// Not on the page; taken from the configuration, e.g. the `--currency` fallback.
+    ConfigFallback,
+}
+
+/// Where a price's currency code came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CurrencySource {
+    /// Read from the page: structured data, a meta tag or the price's symbol.
+    Detected,
+    /// Not found on the page, so the `--currency` fallback (or "UNKNOWN" with
+    /// `--strict-currency`) was used.
+    Defaulted,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Quantity {
+    pub value: f64,
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variation {
+    /// Option label as shown on the page, e.g. "120 Veggie Capsules" or "Chocolate".
+    pub label: String,
+    pub product_id: String,
+    pub price: Option<f64>,
+    pub in_stock: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -49,7 +132,21 @@ pub struct SupplementFacts {
 pub struct Nutrient {
     pub name: String,
     pub amount: String,
+    /// `amount` parsed into a number and unit, e.g. "500 mg" or "100-200 mcg".
+    pub amount_quantity: Option<Amount>,
     pub daily_value: Option<String>,
+    /// Nesting depth on the label: 0 for top-level rows, 1+ for indented sub-nutrients.
+    #[serde(default)]
+    pub indent_level: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Amount {
+    pub value: f64,
+    /// Upper bound when the label gives a range; `value` is then the lower bound.
+    pub max_value: Option<f64>,
+    /// Normalized unit: "mg", "mcg", "g", "IU", "kcal" or "cal".
+    pub unit: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -61,9 +158,105 @@ pub struct ReviewDistribution {
     pub one_star: Option<f64>,
 }
 
+/// A customer question from a product's Q&A section and its top answer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QaEntry {
+    pub question: String,
+    pub answer: String,
+}
+
+/// A page of listing results. `search --enrich` replaces the summaries with each
+/// product's full details.
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SearchResult {
+pub struct SearchResult<P = ProductSummary> {
     pub query: String,
     pub total_results: Option<u32>,
+    pub products: Vec<P>,
+    /// Storefront country iHerb actually served, which may differ from the requested one.
+    pub effective_country: Option<String>,
+}
+
+/// The pages of a multi-page search fetched so far, saved after each page so an
+/// interrupted search can resume.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PartialListing {
+    pub pages_fetched: usize,
     pub products: Vec<ProductSummary>,
 }
+
+/// Details about how a result was obtained, for `--format json` consumers.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseMeta {
+    /// Listing pages navigated for this result; 0 when served from the cache.
+    pub pages_fetched: u32,
+    pub from_cache: bool,
+    /// Unix timestamp (seconds) when the data was scraped.
+    pub fetched_at: u64,
+}
+
+/// `summary` output: a product with the related products listed next to it.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProductBrief {
+    pub product: ProductDetail,
+    pub related: Vec<ProductSummary>,
+    /// Always `null`: review text is not scraped, only the rating breakdown in
+    /// `product.review_distribution`.
+    pub top_reviews: Option<Vec<String>>,
+    /// Why `top_reviews` is empty.
+    pub top_reviews_note: &'static str,
+}
+
+/// Why `summary` has no top reviews, in its JSON and Markdown output.
+pub const TOP_REVIEWS_NOTE: &str =
+    "Top reviews are not included: iherb-cli does not read review text, only the rating breakdown.";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Envelope<T> {
+    pub meta: ResponseMeta,
+    pub result: T,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Category {
+    pub id: String,
+    pub name: String,
+    pub children: Vec<Category>,
+}
+
+/// One file in the cache, as listed by `list-cache`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// "product", "search", "brand" or "categories".
+    pub kind: String,
+    /// Product ID, or the query or brand slug of a listing.
+    pub key: String,
+    /// Unix timestamp (seconds) when the entry was written.
+    pub cached_at: u64,
+    /// Whether the entry is younger than the cache TTL and is served without `--offline-stale-ok`.
+    pub fresh: bool,
+}
+
+/// Environment reported by `version`; the browser and Chrome fields need `--check`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionReport {
+    pub version: String,
+    pub os: String,
+    pub arch: String,
+    pub browser_path: Option<String>,
+    /// What the browser printed for `--version`, e.g. "Google Chrome 141.0.7390.54".
+    pub browser_version: Option<String>,
+    /// Why no browser could be resolved or probed.
+    pub browser_error: Option<String>,
+    /// Current Chrome for Testing stable release.
+    pub latest_chrome: Option<String>,
+    /// Whether `latest_chrome` is newer than the browser in use.
+    pub chrome_update_available: Option<bool>,
+}
+
+/// Outcome of one `doctor` check.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DoctorCheck {
+    pub name: String,
+    pub ok: bool,
+    pub detail: String,
+}
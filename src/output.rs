@@ -1,8 +1,97 @@
-use crate::cli::Section;
-use crate::model::{ProductDetail, SearchResult};
+use crate::cli::{PriceRounding, Section};
+use crate::model::{
+    CacheEntry, Category, DoctorCheck, Nutrient, ProductDetail, ProductSummary, SearchResult,
+    VersionReport, TOP_REVIEWS_NOTE,
+};
+use serde::Serialize;
 use std::time::SystemTime;
 
-pub fn format_search_results(result: &SearchResult) -> String {
+/// Whether `c` is dropped by `--sanitize`: control characters, emoji and their
+/// joiners and variation selectors, other invisible formatting characters, and
+/// private-use code points.
+fn is_unsafe_char(c: char) -> bool {
+    c.is_control()
+        || matches!(
+            c,
+            '\u{200B}'..='\u{200F}'
+                | '\u{2060}'..='\u{206F}'
+                | '\u{FEFF}'
+                | '\u{FE00}'..='\u{FE0F}'
+                | '\u{2600}'..='\u{27BF}'
+                | '\u{2B00}'..='\u{2BFF}'
+                | '\u{E000}'..='\u{F8FF}'
+                | '\u{1F000}'..='\u{1FAFF}'
+                | '\u{E0000}'..='\u{E007F}'
+        )
+}
+
+/// Text with `--sanitize`'s unsafe characters removed and runs of whitespace
+/// collapsed, e.g. "Vitamin C 🍊 Gummies" becomes "Vitamin C Gummies".
+pub fn sanitize_text(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_whitespace() { ' ' } else { c })
+        .filter(|&c| !is_unsafe_char(c))
+        .collect::<String>()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Serialize a value as pretty-printed JSON for `--format json`.
+pub fn format_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|e| {
+        serde_json::json!({ "error": { "kind": "json", "message": e.to_string() } }).to_string()
+    })
+}
+
+/// Serialize a value as a single line of compact JSON for `--format json-lines`.
+pub fn format_json_line<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).unwrap_or_else(|e| {
+        serde_json::json!({ "error": { "kind": "json", "message": e.to_string() } }).to_string()
+    })
+}
+
+pub fn format_search_results(result: &SearchResult, rounding: PriceRounding) -> String {
+    let title = format!("Search results for \"{}\"", result.query);
+    format_product_list(&title, result, rounding)
+}
+
+pub fn format_brand_results(result: &SearchResult, rounding: PriceRounding) -> String {
+    format_product_list(&format!("Products by {}", result.query), result, rounding)
+}
+
+/// `search --enrich` results: each product's full details, one after another.
+pub fn format_enriched_search_results(
+    result: &SearchResult<ProductDetail>,
+    compact_nutrition: bool,
+    rounding: PriceRounding,
+) -> String {
+    let total_str = match result.total_results {
+        Some(total) => format!("{}+", format_number(total)),
+        None => "?".to_string(),
+    };
+    let mut out = format!(
+        "## Search results for \"{}\" (showing {} of {})\n\n",
+        result.query,
+        result.products.len(),
+        total_str
+    );
+    for (i, product) in result.products.iter().enumerate() {
+        if i > 0 {
+            out.push_str("\n---\n\n");
+        }
+        out.push_str(&format_product_detail(
+            product,
+            None,
+            compact_nutrition,
+            None,
+            rounding,
+        ));
+    }
+    out
+}
+
+fn format_product_list(title: &str, result: &SearchResult, rounding: PriceRounding) -> String {
     let mut out = String::new();
 
     let total_str = match result.total_results {
@@ -11,8 +100,8 @@ pub fn format_search_results(result: &SearchResult) -> String {
     };
     let showing = result.products.len();
     out.push_str(&format!(
-        "## Search results for \"{}\" (showing {} of {})\n\n",
-        result.query, showing, total_str
+        "## {} (showing {} of {})\n\n",
+        title, showing, total_str
     ));
 
     for (i, product) in result.products.iter().enumerate() {
@@ -23,6 +112,7 @@ pub fn format_search_results(result: &SearchResult) -> String {
             product.price,
             product.original_price.as_ref(),
             &product.currency,
+            rounding,
         );
         out.push_str(&format!("- **Price:** {}\n", price_str));
 
@@ -33,6 +123,12 @@ pub fn format_search_results(result: &SearchResult) -> String {
                 format_number(count)
             ));
         }
+        if let Some(adjusted) = product.adjusted_rating {
+            out.push_str(&format!("- **Adjusted Rating:** {:.2}/5\n", adjusted));
+        }
+        if let Some(ref rank) = product.rank {
+            out.push_str(&format!("- **Rank:** {}\n", rank));
+        }
 
         out.push_str(&format!("- **ID:** {}\n", product.product_id));
         out.push_str(&format!("- **URL:** {}\n", product.product_url));
@@ -45,7 +141,14 @@ pub fn format_search_results(result: &SearchResult) -> String {
     out
 }
 
-pub fn format_product_detail(product: &ProductDetail, section: Option<Section>) -> String {
+/// `nutrient` limits the nutrition table to rows whose name contains it.
+pub fn format_product_detail(
+    product: &ProductDetail,
+    section: Option<Section>,
+    compact_nutrition: bool,
+    nutrient: Option<&str>,
+    rounding: PriceRounding,
+) -> String {
     let mut out = String::new();
 
     let sections: &[Section] = match section {
@@ -59,15 +162,15 @@ pub fn format_product_detail(product: &ProductDetail, section: Option<Section>)
 
     for sec in sections {
         match sec {
-            Section::Overview => format_overview(product, &mut out),
+            Section::Overview => format_overview(product, rounding, &mut out),
             Section::Description => format_description(product, &mut out),
-            Section::Nutrition => format_nutrition(product, &mut out),
+            Section::Nutrition => format_nutrition(product, compact_nutrition, nutrient, &mut out),
             Section::Ingredients => {
                 // When explicitly requesting ingredients, show supplement facts
                 // first (active ingredients) then other ingredients — matching
                 // how supplement labels read and what users expect from "what's in it?"
                 if section.is_some() {
-                    format_nutrition(product, &mut out);
+                    format_nutrition(product, compact_nutrition, nutrient, &mut out);
                 }
                 format_ingredients(product, &mut out);
             }
@@ -76,6 +179,10 @@ pub fn format_product_detail(product: &ProductDetail, section: Option<Section>)
             Section::Reviews => format_reviews(product, &mut out),
         }
     }
+    if section.is_none() {
+        format_images(product, &mut out);
+        format_qa(product, &mut out);
+    }
 
     if out.is_empty() {
         if let Some(sec) = section {
@@ -86,16 +193,27 @@ pub fn format_product_detail(product: &ProductDetail, section: Option<Section>)
     out
 }
 
-fn format_overview(product: &ProductDetail, out: &mut String) {
+fn format_overview(product: &ProductDetail, rounding: PriceRounding, out: &mut String) {
     out.push_str("## Overview\n");
-    out.push_str(&format!("- **Brand:** {}\n", product.brand));
+    match product.brand_url {
+        Some(ref url) => out.push_str(&format!("- **Brand:** [{}]({})\n", product.brand, url)),
+        None => out.push_str(&format!("- **Brand:** {}\n", product.brand)),
+    }
 
     let price_str = format_price(
         product.price,
         product.original_price.as_ref(),
         &product.currency,
+        rounding,
     );
     out.push_str(&format!("- **Price:** {}\n", price_str));
+    if let Some(pack_size) = product.pack_size {
+        out.push_str(&format!(
+            "- **Pack:** {} units, {} per unit\n",
+            pack_size,
+            format_price(unit_price(product), None, &product.currency, rounding)
+        ));
+    }
 
     if let (Some(rating), Some(count)) = (product.rating, product.review_count) {
         out.push_str(&format!(
@@ -104,13 +222,24 @@ fn format_overview(product: &ProductDetail, out: &mut String) {
             format_number(count)
         ));
     }
+    if let Some(ref rank) = product.rank {
+        out.push_str(&format!("- **Rank:** {}\n", rank));
+    }
+    if let Some(ref breadcrumb) = product.category_breadcrumb {
+        out.push_str(&format!("- **Category:** {}\n", breadcrumb.join(" > ")));
+    }
 
     let stock_str = if product.in_stock {
         "In Stock"
     } else {
         "Out of Stock"
     };
-    out.push_str(&format!("- **Availability:** {}\n", stock_str));
+    match product.availability_note {
+        Some(ref note) if !product.in_stock => {
+            out.push_str(&format!("- **Availability:** {} ({})\n", stock_str, note));
+        }
+        _ => out.push_str(&format!("- **Availability:** {}\n", stock_str)),
+    }
 
     if let Some(ref code) = product.product_code {
         out.push_str(&format!("- **Product Code:** {}\n", code));
@@ -118,6 +247,44 @@ fn format_overview(product: &ProductDetail, out: &mut String) {
     if let Some(ref weight) = product.shipping_weight {
         out.push_str(&format!("- **Shipping Weight:** {}\n", weight));
     }
+    if let Some(ref note) = product.expiration_note {
+        out.push_str(&format!("- **Expiration:** {}\n", note));
+    }
+    if let Some(ref origin) = product.ships_from {
+        out.push_str(&format!("- **Ships From:** {}\n", origin));
+    }
+    if let Some(ref delivery) = product.estimated_delivery {
+        out.push_str(&format!("- **Estimated Delivery:** {}\n", delivery));
+    }
+    if !product.highlights.is_empty() {
+        out.push_str("- **Highlights:**\n");
+        for highlight in &product.highlights {
+            out.push_str(&format!("  - {}\n", highlight));
+        }
+    }
+    if !product.variations.is_empty() {
+        out.push_str("- **Variations:**\n");
+        for variation in &product.variations {
+            let price = variation
+                .price
+                .map(|p| format!(" — {}", format_price(p, None, &product.currency, rounding)))
+                .unwrap_or_default();
+            let stock = if variation.in_stock {
+                ""
+            } else {
+                " (out of stock)"
+            };
+            let current = if variation.product_id == product.product_id {
+                " *(this product)*"
+            } else {
+                ""
+            };
+            out.push_str(&format!(
+                "  - {}{}{} — ID {}{}\n",
+                variation.label, price, stock, variation.product_id, current
+            ));
+        }
+    }
     out.push('\n');
 }
 
@@ -129,7 +296,12 @@ fn format_description(product: &ProductDetail, out: &mut String) {
     }
 }
 
-fn format_nutrition(product: &ProductDetail, out: &mut String) {
+fn format_nutrition(
+    product: &ProductDetail,
+    compact: bool,
+    nutrient_filter: Option<&str>,
+    out: &mut String,
+) {
     let facts = match product.supplement_facts {
         Some(ref f) => f,
         None => return,
@@ -139,10 +311,17 @@ fn format_nutrition(product: &ProductDetail, out: &mut String) {
         out.push_str("| Nutrient | Amount | % Daily Value |\n");
         out.push_str("|---|---|---|\n");
         for nutrient in &facts.nutrients {
+            if compact && is_padding_nutrient(nutrient) {
+                continue;
+            }
+            if nutrient_filter.is_some_and(|filter| !nutrient_matches(nutrient, filter)) {
+                continue;
+            }
             let dv = nutrient.daily_value.as_deref().unwrap_or("");
+            let indent = "— ".repeat(nutrient.indent_level as usize);
             out.push_str(&format!(
-                "| {} | {} | {} |\n",
-                nutrient.name, nutrient.amount, dv
+                "| {}{} | {} | {} |\n",
+                indent, nutrient.name, nutrient.amount, dv
             ));
         }
         out.push('\n');
@@ -156,6 +335,26 @@ fn format_nutrition(product: &ProductDetail, out: &mut String) {
     out.push('\n');
 }
 
+/// Whether a nutrient's name contains `filter`, ignoring case.
+fn nutrient_matches(nutrient: &Nutrient, filter: &str) -> bool {
+    nutrient
+        .name
+        .to_lowercase()
+        .contains(&filter.trim().to_lowercase())
+}
+
+/// Whether a nutrient row only pads the label: its amount is zero ("0g", "0 mg") or
+/// just a footnote mark ("†", "*").
+fn is_padding_nutrient(nutrient: &Nutrient) -> bool {
+    if let Some(ref amount) = nutrient.amount_quantity {
+        return amount.value == 0.0 && amount.max_value.unwrap_or(0.0) == 0.0;
+    }
+    nutrient
+        .amount
+        .chars()
+        .all(|c| c.is_whitespace() || matches!(c, '†' | '‡' | '*'))
+}
+
 fn format_ingredients(product: &ProductDetail, out: &mut String) {
     if let Some(ref ingredients) = product.ingredients {
         out.push_str("## Other Ingredients\n");
@@ -208,7 +407,368 @@ fn format_reviews(product: &ProductDetail, out: &mut String) {
     out.push('\n');
 }
 
-fn format_price(price: f64, original: Option<&f64>, currency: &str) -> String {
+fn format_images(product: &ProductDetail, out: &mut String) {
+    if product.image_urls.is_empty() {
+        return;
+    }
+    out.push_str("## Images\n");
+    for url in &product.image_urls {
+        out.push_str(&format!("- {}\n", url));
+    }
+    out.push('\n');
+}
+
+fn format_qa(product: &ProductDetail, out: &mut String) {
+    let Some(ref qa) = product.qa else {
+        return;
+    };
+    out.push_str("## Questions & Answers\n");
+    if qa.is_empty() {
+        out.push_str("No answered questions yet.\n");
+    }
+    for entry in qa {
+        out.push_str(&format!("- **Q:** {}\n", entry.question));
+        out.push_str(&format!("  **A:** {}\n", entry.answer));
+    }
+    out.push('\n');
+}
+
+/// Render the category tree as a nested list with each category's id.
+pub fn format_category_tree(categories: &[Category]) -> String {
+    let mut out = String::new();
+    if categories.is_empty() {
+        out.push_str("No subcategories found.\n");
+    }
+    push_categories(&mut out, categories, 0);
+    out
+}
+
+fn push_categories(out: &mut String, categories: &[Category], depth: usize) {
+    for category in categories {
+        out.push_str(&format!(
+            "{}- {} (`{}`)\n",
+            "  ".repeat(depth),
+            category.name,
+            category.id
+        ));
+        push_categories(out, &category.children, depth + 1);
+    }
+}
+
+/// Render `doctor` results as a checklist.
+pub fn format_doctor(checks: &[DoctorCheck]) -> String {
+    let mut out = String::from("## iherb-cli doctor\n\n");
+    for check in checks {
+        let mark = if check.ok { "OK" } else { "FAIL" };
+        out.push_str(&format!(
+            "- **{}** [{}]: {}\n",
+            check.name, mark, check.detail
+        ));
+    }
+    out
+}
+
+/// `list-cache` output: a table of cached entries, oldest first.
+pub fn format_cache_entries(entries: &[CacheEntry]) -> String {
+    if entries.is_empty() {
+        return "The cache is empty.\n".to_string();
+    }
+    let mut out = String::from("## Cached entries\n\n");
+    out.push_str("| Type | Key | Cached | Status |\n|---|---|---|---|\n");
+    for entry in entries {
+        let cached_at = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(entry.cached_at);
+        out.push_str(&format!(
+            "| {} | {} | {} | {} |\n",
+            entry.kind,
+            escape_cell(&entry.key),
+            format_cached_at(cached_at),
+            if entry.fresh { "valid" } else { "expired" }
+        ));
+    }
+    let expired = entries.iter().filter(|e| !e.fresh).count();
+    out.push_str(&format!(
+        "\n{} entries, {} expired.\n",
+        entries.len(),
+        expired
+    ));
+    out
+}
+
+/// `version` output: one line per fact, for pasting into bug reports.
+pub fn format_version_report(report: &VersionReport) -> String {
+    let mut out = format!("## iherb-cli {}\n\n", report.version);
+    out.push_str(&format!("- **Platform:** {} {}\n", report.os, report.arch));
+    if let Some(ref path) = report.browser_path {
+        out.push_str(&format!("- **Browser:** {}\n", path));
+    }
+    if let Some(ref version) = report.browser_version {
+        out.push_str(&format!("- **Browser version:** {}\n", version));
+    }
+    if let Some(ref error) = report.browser_error {
+        out.push_str(&format!("- **Browser problem:** {}\n", error));
+    }
+    if let Some(ref latest) = report.latest_chrome {
+        let status = match report.chrome_update_available {
+            Some(true) => " (newer than the browser in use)",
+            Some(false) => " (browser is up to date)",
+            None => "",
+        };
+        out.push_str(&format!(
+            "- **Latest Chrome for Testing:** {}{}\n",
+            latest, status
+        ));
+    }
+    out
+}
+
+/// Render products side by side as a Markdown table, one column per product.
+pub fn format_comparison(products: &[ProductDetail], rounding: PriceRounding) -> String {
+    let mut out = String::new();
+    out.push_str("## Product comparison\n\n");
+
+    let mut header = String::from("| |");
+    let mut divider = String::from("|---|");
+    for product in products {
+        header.push_str(&format!(
+            " {} ({}) |",
+            escape_cell(&product.name),
+            product.product_id
+        ));
+        divider.push_str("---|");
+    }
+    out.push_str(&header);
+    out.push('\n');
+    out.push_str(&divider);
+    out.push('\n');
+
+    push_comparison_row(&mut out, "Brand", products, |p| escape_cell(&p.brand));
+    push_comparison_row(&mut out, "Price", products, |p| {
+        format_price(p.price, p.original_price.as_ref(), &p.currency, rounding)
+    });
+    if products.iter().any(|p| p.pack_size.is_some()) {
+        push_comparison_row(&mut out, "Unit Price", products, |p| {
+            format_price(unit_price(p), None, &p.currency, rounding)
+        });
+    }
+    push_comparison_row(&mut out, "Price / Serving", products, |p| {
+        price_per_serving(p)
+            .map(|pps| format_price(pps, None, &p.currency, PriceRounding::None))
+            .unwrap_or_default()
+    });
+    push_comparison_row(&mut out, "Rating", products, |p| {
+        p.rating.map(|r| format!("{:.1}/5", r)).unwrap_or_default()
+    });
+    push_comparison_row(&mut out, "Reviews", products, |p| {
+        p.review_count.map(format_number).unwrap_or_default()
+    });
+    push_comparison_row(&mut out, "Serving Size", products, |p| {
+        p.supplement_facts
+            .as_ref()
+            .and_then(|f| f.serving_size.as_deref())
+            .map(escape_cell)
+            .unwrap_or_default()
+    });
+
+    // Nutrients in first-seen order across all products, matched by name
+    let mut nutrient_names: Vec<&str> = Vec::new();
+    for product in products {
+        if let Some(ref facts) = product.supplement_facts {
+            for nutrient in &facts.nutrients {
+                if !nutrient_names
+                    .iter()
+                    .any(|n| n.eq_ignore_ascii_case(&nutrient.name))
+                {
+                    nutrient_names.push(&nutrient.name);
+                }
+            }
+        }
+    }
+    for name in nutrient_names {
+        push_comparison_row(&mut out, &escape_cell(name), products, |p| {
+            p.supplement_facts
+                .as_ref()
+                .and_then(|f| {
+                    f.nutrients
+                        .iter()
+                        .find(|n| n.name.eq_ignore_ascii_case(name))
+                })
+                .map(|n| escape_cell(&n.amount))
+                .unwrap_or_default()
+        });
+    }
+
+    out
+}
+
+/// `summary`: one brief with the product's overview, supplement facts, review
+/// breakdown and related products. Sections the product has no data for are left out.
+pub fn format_summary(
+    product: &ProductDetail,
+    related: &[ProductSummary],
+    compact_nutrition: bool,
+    rounding: PriceRounding,
+) -> String {
+    let mut out = format!("# {}\n\n", product.name);
+    format_overview(product, rounding, &mut out);
+    format_nutrition(product, compact_nutrition, None, &mut out);
+    format_reviews(product, &mut out);
+    out.push_str(&format!("_{}_\n\n", TOP_REVIEWS_NOTE));
+    if !related.is_empty() {
+        out.push_str("## Related Products\n");
+        for p in related {
+            let price = format_price(p.price, p.original_price.as_ref(), &p.currency, rounding);
+            let rating = p
+                .rating
+                .map(|r| format!(", {:.1}/5", r))
+                .unwrap_or_default();
+            out.push_str(&format!(
+                "- [{}]({}) ({}): {}{}\n",
+                p.name, p.product_url, p.product_id, price, rating
+            ));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// One product as sold on several storefronts, one row per storefront. A storefront
+/// that served another country's page is marked with the country it served.
+pub fn format_storefront_comparison(
+    products: &[(&str, ProductDetail)],
+    rounding: PriceRounding,
+) -> String {
+    let mut out = match products.first() {
+        Some((_, product)) => format!(
+            "## {} ({}) across storefronts\n\n",
+            product.name, product.product_id
+        ),
+        None => return "No storefront returned the product.\n".to_string(),
+    };
+    out.push_str("| Storefront | Price | Price / Serving | Rating | Reviews | Availability |\n");
+    out.push_str("|---|---|---|---|---|---|\n");
+    for (country, product) in products {
+        let storefront = match product.effective_country.as_deref() {
+            Some(served) if served != *country => format!("{} (served {})", country, served),
+            _ => country.to_string(),
+        };
+        let price = format_price(
+            product.price,
+            product.original_price.as_ref(),
+            &product.currency,
+            rounding,
+        );
+        let pps = price_per_serving(product)
+            .map(|pps| format_price(pps, None, &product.currency, PriceRounding::None))
+            .unwrap_or_default();
+        let rating = product.rating.map(|r| format!("{:.1}/5", r));
+        let reviews = product.review_count.map(format_number);
+        let availability = match (product.in_stock, product.availability_note.as_deref()) {
+            (true, _) => "In Stock".to_string(),
+            (false, Some(note)) => escape_cell(note),
+            (false, None) => "Out of Stock".to_string(),
+        };
+        out.push_str(&format!(
+            "| {} | {} | {} | {} | {} | {} |\n",
+            storefront,
+            price,
+            pps,
+            rating.unwrap_or_default(),
+            reviews.unwrap_or_default(),
+            availability
+        ));
+    }
+    out
+}
+
+/// `compare --nutrient`: one row per product with the amount of each nutrient whose
+/// name contains `filter`. Products without a match get an empty row.
+pub fn format_nutrient_comparison(products: &[ProductDetail], filter: &str) -> String {
+    let mut out = format!("## Comparison: {}\n\n", filter.trim());
+    out.push_str("| Product | Nutrient | Amount | % Daily Value | Price / Serving |\n");
+    out.push_str("|---|---|---|---|---|\n");
+    for product in products {
+        let product_cell = format!("{} ({})", escape_cell(&product.name), product.product_id);
+        let pps = price_per_serving(product)
+            .map(|pps| format_price(pps, None, &product.currency, PriceRounding::None))
+            .unwrap_or_default();
+        let matches: Vec<&Nutrient> = product
+            .supplement_facts
+            .iter()
+            .flat_map(|f| &f.nutrients)
+            .filter(|n| nutrient_matches(n, filter))
+            .collect();
+        if matches.is_empty() {
+            out.push_str(&format!("| {} | — | | | {} |\n", product_cell, pps));
+        }
+        for nutrient in matches {
+            out.push_str(&format!(
+                "| {} | {} | {} | {} | {} |\n",
+                product_cell,
+                escape_cell(&nutrient.name),
+                escape_cell(&nutrient.amount),
+                escape_cell(nutrient.daily_value.as_deref().unwrap_or("")),
+                pps
+            ));
+        }
+    }
+    out
+}
+
+fn push_comparison_row(
+    out: &mut String,
+    label: &str,
+    products: &[ProductDetail],
+    cell: impl Fn(&ProductDetail) -> String,
+) {
+    out.push_str(&format!("| **{}** |", label));
+    for product in products {
+        out.push_str(&format!(" {} |", cell(product)));
+    }
+    out.push('\n');
+}
+
+/// Price divided by the number of servings, when the label states a servings count.
+pub fn price_per_serving(product: &ProductDetail) -> Option<f64> {
+    let count = servings_per_container(product)?;
+    // The label's servings are per container, so bundles are priced per unit
+    let price = unit_price(product);
+    if count > 0.0 && price > 0.0 {
+        Some(price / count)
+    } else {
+        None
+    }
+}
+
+/// Price of a single unit: the listing price divided by the pack size for multi-packs.
+fn unit_price(product: &ProductDetail) -> f64 {
+    match product.pack_size {
+        Some(pack_size) if pack_size > 1 => product.price / f64::from(pack_size),
+        _ => product.price,
+    }
+}
+
+/// Number of servings per container, when the label states one.
+pub fn servings_per_container(product: &ProductDetail) -> Option<f64> {
+    let servings = product
+        .supplement_facts
+        .as_ref()?
+        .servings_per_container
+        .as_deref()?;
+    crate::scraper::helpers::parse_servings(servings)
+}
+
+fn escape_cell(text: &str) -> String {
+    text.replace('|', "\\|")
+}
+
+/// Format a price with its currency symbol. With `--round-prices` it is shown as a
+/// whole number; the discount is still computed from the exact prices.
+fn format_price(
+    price: f64,
+    original: Option<&f64>,
+    currency: &str,
+    rounding: PriceRounding,
+) -> String {
     let symbol = match currency {
         "USD" => "$",
         "CHF" => "CHF ",
@@ -216,16 +776,24 @@ fn format_price(price: f64, original: Option<&f64>, currency: &str) -> String {
         "GBP" => "£",
         _ => currency,
     };
+    let decimals = if rounding == PriceRounding::None {
+        2
+    } else {
+        0
+    };
+    let amount = |p: f64| format!("{}{:.*}", symbol, decimals, rounding.apply(p));
 
     match original {
         Some(orig) if *orig > price => {
             let discount = ((*orig - price) / *orig * 100.0).round() as u32;
             format!(
-                "{}{:.2} ~~{}{:.2}~~ ({}% off)",
-                symbol, price, symbol, orig, discount
+                "{} ~~{}~~ ({}% off)",
+                amount(price),
+                amount(*orig),
+                discount
             )
         }
-        _ => format!("{}{:.2}", symbol, price),
+        _ => amount(price),
     }
 }
 
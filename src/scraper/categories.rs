@@ -0,0 +1,176 @@
+use crate::error::IherbError;
+use crate::model::Category;
+use scraper::{ElementRef, Html, Selector};
+use std::collections::BTreeMap;
+
+use super::helpers::slugify;
+
+/// Maximum edit distance for a category name to be suggested as a close match.
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+/// Category slug (e.g. "vitamins") to iHerb's numeric category id.
+pub type CategoryMap = BTreeMap<String, String>;
+
+/// URL of the page listing the category tree.
+pub fn build_categories_url(base_url: &str) -> String {
+    format!("{}/categories", base_url)
+}
+
+/// Whether the category needs the map to resolve, i.e. it isn't already a numeric id.
+pub fn needs_lookup(category: &str) -> bool {
+    !category.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Flatten a category tree into a slug to id map for name lookups.
+pub fn category_map(tree: &[Category]) -> CategoryMap {
+    let mut map = CategoryMap::new();
+    let mut stack: Vec<&Category> = tree.iter().collect();
+    while let Some(category) = stack.pop() {
+        map.entry(slugify(&category.name))
+            .or_insert_with(|| category.id.clone());
+        stack.extend(&category.children);
+    }
+    map
+}
+
+/// Find the category with the given id anywhere in the tree.
+pub fn find_category<'a>(tree: &'a [Category], id: &str) -> Option<&'a Category> {
+    tree.iter().find_map(|category| {
+        if category.id == id {
+            Some(category)
+        } else {
+            find_category(&category.children, id)
+        }
+    })
+}
+
+/// Resolve a `--category` value to a numeric id. Numeric ids pass through unchanged;
+/// names are matched by slug. Unknown names list close matches in the error.
+pub fn resolve_category(category: &str, map: &CategoryMap) -> Result<String, IherbError> {
+    if !needs_lookup(category) {
+        return Ok(category.to_string());
+    }
+
+    let slug = slugify(category);
+    if let Some(cid) = map.get(&slug) {
+        return Ok(cid.clone());
+    }
+
+    let mut suggestions: Vec<(usize, &str)> = map
+        .keys()
+        .filter_map(|known| {
+            let distance = edit_distance(&slug, known);
+            let close = distance <= MAX_SUGGESTION_DISTANCE
+                || known.contains(slug.as_str())
+                || slug.contains(known.as_str());
+            close.then_some((distance, known.as_str()))
+        })
+        .collect();
+    suggestions.sort();
+
+    let hint = if suggestions.is_empty() {
+        "Run `iherb-cli categories` to list valid categories".to_string()
+    } else {
+        let names: Vec<&str> = suggestions.iter().take(5).map(|(_, name)| *name).collect();
+        format!("Did you mean: {}?", names.join(", "))
+    };
+    Err(IherbError::InvalidCategory(format!(
+        "Unknown category '{}'. {}",
+        category, hint
+    )))
+}
+
+/// Parse the category tree from the categories page. Links carry the id either as a
+/// `cids` query parameter or a data attribute; nesting follows the page's list items.
+pub fn parse_categories_from_html(html: &str, base_url: &str) -> Vec<Category> {
+    let doc = Html::parse_document(html);
+    let tree = collect_categories(doc.root_element(), base_url);
+    tracing::debug!("Parsed {} top-level categories", tree.len());
+    tree
+}
+
+/// Walk the children of `el`, turning each list item or link with a category id into
+/// a node. List items own the categories nested beneath them.
+fn collect_categories(el: ElementRef, base_url: &str) -> Vec<Category> {
+    let mut categories = Vec::new();
+    for child in el.children().filter_map(ElementRef::wrap) {
+        match child.value().name() {
+            "a" => categories.extend(parse_category_link(child, base_url)),
+            "li" => match own_category_link(child, base_url) {
+                Some(mut category) => {
+                    let mut children = collect_categories(child, base_url);
+                    children.retain(|c| c.id != category.id);
+                    category.children = children;
+                    categories.push(category);
+                }
+                None => categories.extend(collect_categories(child, base_url)),
+            },
+            _ => categories.extend(collect_categories(child, base_url)),
+        }
+    }
+    categories
+}
+
+/// The first category link of a list item that isn't inside a nested list.
+fn own_category_link(li: ElementRef, base_url: &str) -> Option<Category> {
+    let link_sel = Selector::parse("a[href]").ok()?;
+    li.select(&link_sel)
+        .filter(|link| {
+            !link
+                .ancestors()
+                .take_while(|a| a.id() != li.id())
+                .filter_map(ElementRef::wrap)
+                .any(|a| matches!(a.value().name(), "ul" | "ol" | "li"))
+        })
+        .find_map(|link| parse_category_link(link, base_url))
+}
+
+fn parse_category_link(link: ElementRef, base_url: &str) -> Option<Category> {
+    let attrs = link.value();
+    let href = attrs.attr("href")?;
+    let url = url::Url::parse(base_url)
+        .and_then(|base| base.join(href))
+        .ok()?;
+
+    let id = url
+        .query_pairs()
+        .find(|(key, _)| key == "cids")
+        .map(|(_, value)| value.into_owned())
+        .or_else(|| {
+            attrs
+                .attr("data-category-id")
+                .or_else(|| attrs.attr("data-ga-category-id"))
+                .map(|s| s.to_string())
+        })
+        .filter(|id| !id.is_empty() && !needs_lookup(id))?;
+
+    let name = link.text().collect::<String>().trim().to_string();
+    let name = if name.is_empty() {
+        url.path()
+            .strip_prefix("/c/")?
+            .trim_end_matches('/')
+            .to_string()
+    } else {
+        name
+    };
+
+    Some(Category {
+        id,
+        name,
+        children: Vec::new(),
+    })
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
@@ -21,17 +21,12 @@ pub fn extract_json_ld(html: &str) -> Option<serde_json::Value> {
     for el in doc.select(&sel) {
         let text: String = el.text().collect();
         if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&text) {
-            if parsed.get("@type").and_then(|v| v.as_str()) == Some("Product") {
+            if let Some(product) = find_json_ld_node(&parsed, PRODUCT_TYPES) {
                 tracing::debug!("Found JSON-LD Product data");
-                return Some(parsed);
-            }
-            if let Some(arr) = parsed.as_array() {
-                for item in arr {
-                    if item.get("@type").and_then(|v| v.as_str()) == Some("Product") {
-                        tracing::debug!("Found JSON-LD Product data in array");
-                        return Some(item.clone());
-                    }
+                if is_json_ld_type(product, "ProductGroup") {
+                    return Some(flatten_product_group(product));
                 }
+                return Some(product.clone());
             }
         }
     }
@@ -39,6 +34,109 @@ pub fn extract_json_ld(html: &str) -> Option<serde_json::Value> {
     None
 }
 
+/// Category names from the page's JSON-LD `BreadcrumbList`, outermost first,
+/// leaving out the leading home link.
+pub fn extract_json_ld_breadcrumb(html: &str) -> Option<Vec<String>> {
+    let doc = scraper::Html::parse_document(html);
+    let sel = scraper::Selector::parse(r#"script[type="application/ld+json"]"#).ok()?;
+
+    for el in doc.select(&sel) {
+        let text: String = el.text().collect();
+        let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&text) else {
+            continue;
+        };
+        let Some(list) = find_json_ld_node(&parsed, &["BreadcrumbList"]) else {
+            continue;
+        };
+        let mut items: Vec<&serde_json::Value> = list
+            .get("itemListElement")
+            .and_then(|v| v.as_array())
+            .map(|items| items.iter().collect())
+            .unwrap_or_default();
+        items.sort_by_key(|item| item.get("position").and_then(|p| p.as_u64()));
+        let names: Vec<String> = items
+            .iter()
+            .filter_map(|item| {
+                item.get("name")
+                    .or_else(|| item.get("item").and_then(|i| i.get("name")))
+                    .and_then(|n| n.as_str())
+            })
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case("home"))
+            .collect();
+        if !names.is_empty() {
+            return Some(names);
+        }
+    }
+    None
+}
+
+/// JSON-LD `@type`s accepted as product data.
+const PRODUCT_TYPES: &[&str] = &["Product", "IndividualProduct", "ProductGroup"];
+
+/// Find the first node of one of `types` in a JSON-LD value, descending into
+/// top-level arrays and `@graph` containers.
+fn find_json_ld_node<'a>(
+    value: &'a serde_json::Value,
+    types: &[&str],
+) -> Option<&'a serde_json::Value> {
+    if let Some(arr) = value.as_array() {
+        return arr.iter().find_map(|v| find_json_ld_node(v, types));
+    }
+    if types.iter().any(|t| is_json_ld_type(value, t)) {
+        return Some(value);
+    }
+    value
+        .get("@graph")
+        .and_then(|graph| find_json_ld_node(graph, types))
+}
+
+/// Check whether a JSON-LD node's `@type` is (or, for array types, contains) `type_name`.
+pub fn is_json_ld_type(value: &serde_json::Value, type_name: &str) -> bool {
+    match value.get("@type") {
+        Some(serde_json::Value::String(t)) => t == type_name,
+        Some(serde_json::Value::Array(types)) => {
+            types.iter().any(|t| t.as_str() == Some(type_name))
+        }
+        _ => false,
+    }
+}
+
+/// Turn a `ProductGroup` into a single Product-shaped node. A group with its own
+/// `offers` is used as is; otherwise the default variant (the one whose `url` is the
+/// group's, else the first with offers) supplies the variant fields, and the group
+/// fills in whatever the variant lacks (brand, rating, description). `hasVariant` is
+/// kept so the other variants can be listed.
+fn flatten_product_group(group: &serde_json::Value) -> serde_json::Value {
+    if group.get("offers").is_some() {
+        return group.clone();
+    }
+    let variants = match group.get("hasVariant") {
+        Some(serde_json::Value::Array(variants)) => variants.as_slice(),
+        Some(variant) => std::slice::from_ref(variant),
+        None => &[],
+    };
+    let group_url = group.get("url").and_then(|v| v.as_str());
+    let variant = variants
+        .iter()
+        .find(|v| group_url.is_some() && v.get("url").and_then(|u| u.as_str()) == group_url)
+        .or_else(|| variants.iter().find(|v| v.get("offers").is_some()));
+
+    let (Some(serde_json::Value::Object(variant)), serde_json::Value::Object(group_fields)) =
+        (variant, group)
+    else {
+        return group.clone();
+    };
+    tracing::debug!("Using default variant of JSON-LD ProductGroup");
+    let mut merged = variant.clone();
+    for (key, value) in group_fields {
+        if key != "@type" {
+            merged.entry(key.clone()).or_insert_with(|| value.clone());
+        }
+    }
+    serde_json::Value::Object(merged)
+}
+
 /// Extract JS globals (window.PRODUCT_DETAILS, window.IHR_DL) from the page via JS evaluation.
 pub async fn extract_js_globals(page: &Page) -> Result<Option<serde_json::Value>, IherbError> {
     let script = r#"
@@ -84,3 +182,61 @@ async fn evaluate_and_parse_json(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page_with_json_ld(json: &str) -> String {
+        format!(
+            r#"<html><head><script type="application/ld+json">{}</script></head><body></body></html>"#,
+            json
+        )
+    }
+
+    #[test]
+    fn finds_product_inside_graph() {
+        let html = page_with_json_ld(
+            r#"{
+                "@context": "https://schema.org",
+                "@graph": [
+                    {"@type": "Organization", "name": "iHerb"},
+                    {"@type": "BreadcrumbList", "itemListElement": [
+                        {"@type": "ListItem", "position": 1, "name": "Home"},
+                        {"@type": "ListItem", "position": 2, "name": "Supplements"}
+                    ]},
+                    {"@type": "Product", "name": "Omega-3 Fish Oil", "sku": "CGN-01234",
+                     "offers": {"@type": "Offer", "price": "12.50", "priceCurrency": "USD"}}
+                ]
+            }"#,
+        );
+        let product = extract_json_ld(&html).expect("Product node in @graph");
+        assert_eq!(product["name"], "Omega-3 Fish Oil");
+        assert_eq!(product["sku"], "CGN-01234");
+        assert_eq!(
+            extract_json_ld_breadcrumb(&html),
+            Some(vec!["Supplements".to_string()])
+        );
+    }
+
+    #[test]
+    fn finds_product_with_array_type_inside_graph() {
+        let graph: serde_json::Value = serde_json::from_str(
+            r#"{"@graph": [
+                {"@type": "WebPage", "name": "Omega-3"},
+                {"@type": ["Product", "IndividualProduct"], "name": "Omega-3 Fish Oil"}
+            ]}"#,
+        )
+        .unwrap();
+        let product = find_json_ld_node(&graph, PRODUCT_TYPES).expect("Product node");
+        assert_eq!(product["name"], "Omega-3 Fish Oil");
+    }
+
+    #[test]
+    fn graph_without_product_yields_none() {
+        let html = page_with_json_ld(
+            r#"{"@context": "https://schema.org", "@graph": [{"@type": "Organization", "name": "iHerb"}]}"#,
+        );
+        assert!(extract_json_ld(&html).is_none());
+    }
+}
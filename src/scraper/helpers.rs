@@ -1,12 +1,53 @@
-use scraper::{Html, Selector};
+use crate::model::{Amount, CurrencySource, Quantity};
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use std::path::Path;
+use std::sync::OnceLock;
+
+fn weight_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?i)(\d+(?:[.,]\d+)?)\s*(kg|g|lbs?|oz)\b").expect("valid weight regex")
+    })
+}
+
+fn amount_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"(?i)(\d[\d,]*(?:\.\d+)?)\s*(?:(?:-|–|to)\s*(\d[\d,]*(?:\.\d+)?))?\s*(mcg|µg|μg|mg|kcal|cal|iu|g)\b",
+        )
+        .expect("valid amount regex")
+    })
+}
+
+fn expiration_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?i)\b(best (by|before)|use by|use within|expir\w*|shelf[- ]life)\b")
+            .expect("valid expiration regex")
+    })
+}
+
+/// The first sentence of `text` about shelf life or expiration, e.g. "Refrigerate
+/// after opening and use within 60 days." Only what the text says; dates printed on
+/// the label image are not read.
+pub fn expiration_sentence(text: &str) -> Option<String> {
+    text.split_inclusive(['.', '!', '?'])
+        .map(|sentence| sentence.split_whitespace().collect::<Vec<_>>().join(" "))
+        .find(|sentence| expiration_re().is_match(sentence))
+}
 
 /// Parse a price string by extracting digits, periods, and commas, then
 /// determine the decimal separator based on position and context.
-/// Handles both US format (1,234.56) and European format (1.234,56).
+/// Handles US format (1,234.56), European format (1.234,56), Indian grouping
+/// (1,23,456.78), dot-grouped amounts (Rp 1.234.567) and currency words ending in
+/// a period (Rs. 1,299).
 pub fn parse_price_str(s: &str) -> Option<f64> {
-    // Keep only digits, periods, and commas
+    // Keep only digits, periods, and commas, from the first digit on
     let cleaned: String = s
         .chars()
+        .skip_while(|c| !c.is_ascii_digit())
         .filter(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
         .collect();
 
@@ -39,14 +80,156 @@ pub fn parse_price_str(s: &str) -> Option<f64> {
             // Otherwise treat comma as decimal (e.g. "23,99")
             cleaned.replacen(',', ".", 1)
         }
+    } else if cleaned.matches('.').count() > 1 {
+        // Several dots can only be thousands separators (e.g. "1.234.567")
+        cleaned.replace('.', "")
     } else {
-        // Only dots or no separator at all: parse normally
+        // One dot or no separator at all: parse normally
         cleaned
     };
 
     normalized.parse().ok()
 }
 
+/// Parse a weight string like "0.45 kg (0.99 lb)" or "0.99 lb (0.45 kg)" into a
+/// quantity, preferring the metric value when both are given. Returns `None` when
+/// no number with a recognized unit is present.
+pub fn parse_weight(s: &str) -> Option<Quantity> {
+    let quantities: Vec<Quantity> = weight_re()
+        .captures_iter(s)
+        .filter_map(|caps| {
+            let value = caps[1].replace(',', ".").parse::<f64>().ok()?;
+            let unit = match caps[2].to_lowercase().as_str() {
+                "lbs" => "lb".to_string(),
+                other => other.to_string(),
+            };
+            Some(Quantity { value, unit })
+        })
+        .collect();
+
+    quantities
+        .iter()
+        .find(|q| q.unit == "kg" || q.unit == "g")
+        .or_else(|| quantities.first())
+        .cloned()
+}
+
+/// Parse a supplement-facts amount like "500 mg", "1,000 IU" or "100-200 mcg" into
+/// a number and normalized unit. Returns `None` for amounts without a dosage unit.
+pub fn parse_amount(s: &str) -> Option<Amount> {
+    let caps = amount_re().captures(s)?;
+    let value = parse_price_str(&caps[1])?;
+    let max_value = caps.get(2).and_then(|m| parse_price_str(m.as_str()));
+    let unit = match caps[3].to_lowercase().as_str() {
+        "µg" | "μg" => "mcg".to_string(),
+        "iu" => "IU".to_string(),
+        other => other.to_string(),
+    };
+    Some(Amount {
+        value,
+        max_value,
+        unit,
+    })
+}
+
+/// Parse a servings count like "120" or "About 60" from a supplement-facts label.
+pub fn parse_servings(s: &str) -> Option<f64> {
+    s.split(|c: char| !c.is_ascii_digit() && c != '.')
+        .find(|s| !s.is_empty())?
+        .parse()
+        .ok()
+}
+
+fn pack_size_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?i)\b(?:pack|bundle|set|case) of (\d+)\b|\b(\d+)[ -]?(?:pack|bottles)\b")
+            .expect("valid pack size regex")
+    })
+}
+
+/// Number of units in a multi-pack listing, e.g. 2 for "..., 60 Capsules, Pack of 2"
+/// or "2-Pack". Single units and implausible counts give `None`.
+pub fn parse_pack_size(s: &str) -> Option<u32> {
+    let caps = pack_size_re().captures(s)?;
+    let count: u32 = caps.get(1).or_else(|| caps.get(2))?.as_str().parse().ok()?;
+    (2..=48).contains(&count).then_some(count)
+}
+
+fn rating_re() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(r"(?i)(\d(?:[.,]\d+)?)\s*(?:/|out of)\s*5\b").expect("valid rating regex")
+    })
+}
+
+/// Star-rating elements; the rating is in their `title` or `aria-label`, or else
+/// drawn as the width of the filled stars.
+pub const STAR_RATING_SELECTOR: &str = "a.stars, [aria-label*='out of 5'], .stars[style*='width']";
+
+/// Rating out of 5 from a star element's label, e.g. "4.8/5 - 42,328 Reviews",
+/// "Rated 4.8 out of 5" or "4,8/5". Values outside 0–5 are rejected.
+pub fn parse_rating_text(text: &str) -> Option<f64> {
+    let number = match text.split('/').next().map(str::trim) {
+        Some(lead) if lead.len() < text.len() && lead.parse::<f64>().is_ok() => lead.to_string(),
+        _ => rating_re().captures(text)?[1].replace(',', "."),
+    };
+    let rating: f64 = number.parse().ok()?;
+    (0.0..=5.0).contains(&rating).then_some(rating)
+}
+
+/// Rating of a star element, from its `title` or else its `aria-label`, or else
+/// its filled width, e.g. `width: 96%` for 4.8.
+pub fn star_element_rating(el: &scraper::node::Element) -> Option<f64> {
+    ["title", "aria-label"]
+        .iter()
+        .filter_map(|attr| el.attr(attr))
+        .find_map(parse_rating_text)
+        .or_else(|| {
+            let pct = parse_width_percent(el.attr("style")?)?;
+            let rating = (pct / 20.0 * 10.0).round() / 10.0;
+            (0.0..=5.0).contains(&rating).then_some(rating)
+        })
+}
+
+/// Parse a percentage value from a CSS width style like "width: 84%;".
+pub fn parse_width_percent(style: &str) -> Option<f64> {
+    style
+        .split(';')
+        .filter_map(|prop| {
+            let prop = prop.trim();
+            if prop.starts_with("width") {
+                prop.split(':')
+                    .nth(1)
+                    .and_then(|v| v.trim().strip_suffix('%'))
+                    .and_then(|v| v.trim().parse::<f64>().ok())
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+/// Elements iHerb uses for best-seller and category-rank badges.
+const RANK_BADGE_SELECTOR: &str = ".best-seller-rank, .bestseller-rank, .product-rank, \
+    .badge-best-seller, .best-seller, [data-testid='best-seller-badge'], .product-flag, \
+    .product-badge";
+
+/// Best-seller or category-rank badge text within `scope`, e.g. "Best Seller" or
+/// "#3 in Vitamin C". Other badges (e.g. "New", "Trial Pricing") are ignored.
+pub fn extract_rank_badge(scope: &ElementRef) -> Option<String> {
+    let sel = Selector::parse(RANK_BADGE_SELECTOR).ok()?;
+    scope.select(&sel).find_map(|el| {
+        let text = el.text().collect::<Vec<_>>().join(" ");
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        let lower = text.to_lowercase();
+        let is_rank = lower.contains("best seller")
+            || lower.contains("bestseller")
+            || (text.starts_with('#') && text[1..].starts_with(|c: char| c.is_ascii_digit()));
+        is_rank.then_some(text)
+    })
+}
+
 /// Extract text from a document by trying comma-separated CSS selectors.
 pub fn extract_text(doc: &Html, selectors: &str) -> Option<String> {
     for sel_str in selectors.split(',') {
@@ -97,21 +280,127 @@ pub fn parse_review_count(text: &str) -> Option<u32> {
         .ok()
 }
 
-/// Dump HTML to /tmp for debugging when debug level is enabled.
-pub fn debug_dump_html(html: &str, label: &str) {
-    if tracing::enabled!(tracing::Level::DEBUG) {
-        let safe_label = label.replace(' ', "_");
-        let dump_path = format!("/tmp/iherb_{}.html", safe_label);
-        let _ = std::fs::write(&dump_path, html);
-        tracing::debug!("Dumped HTML to {}", dump_path);
+/// Dump HTML for debugging. Always writes when `dump_dir` is set (via `--dump-html`),
+/// otherwise only when debug logging is enabled, into the system temp directory.
+pub fn dump_html(html: &str, label: &str, dump_dir: Option<&Path>) {
+    let dir = match dump_dir {
+        Some(dir) => dir.to_path_buf(),
+        None if tracing::enabled!(tracing::Level::DEBUG) => std::env::temp_dir(),
+        None => return,
+    };
+    let dump_path = dir.join(format!("iherb_{}.html", file_safe_label(label)));
+    if let Err(e) = std::fs::create_dir_all(&dir).and_then(|_| std::fs::write(&dump_path, html)) {
+        tracing::warn!("Failed to dump HTML to {}: {}", dump_path.display(), e);
+        return;
     }
+    tracing::debug!("Dumped HTML to {}", dump_path.display());
+}
+
+/// `label` with everything but letters, digits and dashes replaced by underscores,
+/// for use in a file name.
+pub fn file_safe_label(label: &str) -> String {
+    label
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Turn a display name into iHerb's URL slug ("NOW Foods" -> "now-foods").
+pub fn slugify(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| part.to_ascii_lowercase())
+        .collect::<Vec<_>>()
+        .join("-")
 }
 
-/// Check if HTML indicates a 404/not-found page.
-pub fn is_not_found_page(html: &str) -> bool {
+/// Check if HTML indicates a 404/not-found page. English markers are always checked,
+/// plus the localized text of the given storefront. Prefer the HTTP status when known.
+pub fn is_not_found_page(html: &str, country: &str) -> bool {
     html.contains("Page Not Found")
         || html.contains("<title>404</title>")
         || html.contains("404 Not Found")
+        || localized_not_found_markers(country)
+            .iter()
+            .any(|marker| html.contains(marker))
+}
+
+/// Localized "page not found" text per storefront country code.
+fn localized_not_found_markers(country: &str) -> &'static [&'static str] {
+    match country {
+        "de" | "at" => &["Seite nicht gefunden"],
+        "ch" => &[
+            "Seite nicht gefunden",
+            "Page introuvable",
+            "Pagina non trovata",
+        ],
+        "fr" => &["Page introuvable", "Page non trouvée"],
+        "be" => &["Page introuvable", "Pagina niet gevonden"],
+        "nl" => &["Pagina niet gevonden"],
+        "it" => &["Pagina non trovata"],
+        "es" | "mx" | "cl" | "co" | "ar" => &["Página no encontrada"],
+        "pt" | "br" => &["Página não encontrada"],
+        "se" => &["Sidan kunde inte hittas"],
+        "no" => &["Siden ble ikke funnet"],
+        "dk" => &["Siden blev ikke fundet"],
+        "fi" => &["Sivua ei löytynyt"],
+        "pl" => &["Nie znaleziono strony"],
+        "cz" => &["Stránka nenalezena"],
+        "gr" => &["Η σελίδα δεν βρέθηκε"],
+        "ru" => &["Страница не найдена"],
+        "tr" => &["Sayfa bulunamadı"],
+        "sa" | "ae" | "kw" | "eg" => &["الصفحة غير موجودة"],
+        "il" => &["הדף לא נמצא"],
+        "jp" => &["ページが見つかりません"],
+        "kr" => &["페이지를 찾을 수 없습니다"],
+        "cn" => &["页面未找到", "找不到页面"],
+        "tw" | "hk" => &["找不到頁面", "頁面未找到"],
+        "th" => &["ไม่พบหน้า"],
+        "vn" => &["Không tìm thấy trang"],
+        "id" => &["Halaman tidak ditemukan"],
+        "my" => &["Halaman tidak dijumpai"],
+        _ => &[],
+    }
+}
+
+/// Whether `host` is iHerb's, `iherb.com` or one of its subdomains. Lookalikes
+/// such as `notiherb.com` or `iherb.com.example` are not.
+pub fn is_iherb_host(host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    host == "iherb.com" || host.ends_with(".iherb.com")
+}
+
+/// Extract the storefront country code from an iHerb URL.
+/// `www.iherb.com` is the US storefront; other storefronts use a country subdomain.
+pub fn country_from_url(url: &str) -> Option<String> {
+    let parsed = url::Url::parse(url).ok()?;
+    let host = parsed.host_str()?;
+    if !is_iherb_host(host) {
+        return None;
+    }
+    let subdomain = host.strip_suffix("iherb.com")?.trim_end_matches('.');
+    match subdomain {
+        "" | "www" => Some("us".to_string()),
+        sub if !sub.contains('.') => Some(sub.to_lowercase()),
+        _ => None,
+    }
+}
+
+/// A currency code found on the page, or else `fallback`, along with which it was.
+pub fn currency_or_fallback(detected: Option<String>, fallback: &str) -> (String, CurrencySource) {
+    match detected {
+        Some(code) => (code, CurrencySource::Detected),
+        None => {
+            tracing::debug!("No currency found on the page, using {}", fallback);
+            (fallback.to_string(), CurrencySource::Defaulted)
+        }
+    }
 }
 
 /// Detect the actual currency from HTML via meta tags or price text.
@@ -141,25 +430,205 @@ pub fn detect_currency_from_html(doc: &Html) -> Option<String> {
     None
 }
 
+/// Symbols and words storefronts put before a price, with their currency. A prefix
+/// comes before the shorter ones it starts with ("Rs." before "Rs" before "R").
+const CURRENCY_PREFIXES: &[(&str, &str)] = &[
+    ("$", "USD"),
+    ("US$", "USD"),
+    ("€", "EUR"),
+    ("£", "GBP"),
+    ("CHF", "CHF"),
+    ("CA$", "CAD"),
+    ("C$", "CAD"),
+    ("AU$", "AUD"),
+    ("A$", "AUD"),
+    ("NZ$", "NZD"),
+    ("HK$", "HKD"),
+    ("NT$", "TWD"),
+    ("S$", "SGD"),
+    ("MX$", "MXN"),
+    ("R$", "BRL"),
+    ("¥", "JPY"),
+    ("₩", "KRW"),
+    ("₹", "INR"),
+    ("Rs.", "INR"),
+    ("Rs", "INR"),
+    ("RM", "MYR"),
+    ("Rp", "IDR"),
+    ("R", "ZAR"),
+    ("₱", "PHP"),
+    ("฿", "THB"),
+    ("₫", "VND"),
+    ("₺", "TRY"),
+    ("₽", "RUB"),
+    ("₪", "ILS"),
+    ("₦", "NGN"),
+    ("KSh", "KES"),
+    ("E£", "EGP"),
+    ("zł", "PLN"),
+    ("Kč", "CZK"),
+];
+
+/// The currency of a price from its prefix: a known symbol or word, or an ISO code
+/// (e.g. "INR 1,299"). The amount must follow the prefix, so a word that merely
+/// starts like one ("Rupees") is not mistaken for it.
 fn detect_currency_from_text(text: &str) -> Option<String> {
     let text = text.trim();
-    if text.starts_with('$') {
-        Some("USD".to_string())
-    } else if text.starts_with('€') {
-        Some("EUR".to_string())
-    } else if text.starts_with('£') {
-        Some("GBP".to_string())
-    } else if text.starts_with("CHF") {
-        Some("CHF".to_string())
-    } else if text.starts_with("CA$") || text.starts_with("C$") {
-        Some("CAD".to_string())
-    } else if text.starts_with("A$") || text.starts_with("AU$") {
-        Some("AUD".to_string())
-    } else if text.starts_with("¥") {
-        Some("JPY".to_string())
-    } else if text.starts_with("₩") {
-        Some("KRW".to_string())
-    } else {
-        None
+    let amount_follows = |rest: &str| rest.trim_start().starts_with(|c: char| c.is_ascii_digit());
+    let known = CURRENCY_PREFIXES
+        .iter()
+        .find(|(prefix, _)| text.strip_prefix(prefix).is_some_and(amount_follows));
+    if let Some((_, code)) = known {
+        return Some(code.to_string());
+    }
+    let code_len = text.chars().take_while(|c| c.is_ascii_uppercase()).count();
+    (code_len == 3 && amount_follows(&text[3..])).then(|| text[..3].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_indian_grouped_price() {
+        assert_eq!(parse_price_str("₹1,23,456.00"), Some(123456.0));
+        assert_eq!(
+            detect_currency_from_text("₹1,23,456.00").as_deref(),
+            Some("INR")
+        );
+    }
+
+    #[test]
+    fn parses_brazilian_price() {
+        assert_eq!(parse_price_str("R$ 1.234,56"), Some(1234.56));
+        assert_eq!(
+            detect_currency_from_text("R$ 1.234,56").as_deref(),
+            Some("BRL")
+        );
+    }
+
+    #[test]
+    fn iherb_hosts() {
+        assert!(is_iherb_host("iherb.com"));
+        assert!(is_iherb_host("www.iherb.com"));
+        assert!(is_iherb_host("kr.iherb.com"));
+        assert!(is_iherb_host("WWW.IHERB.COM"));
+    }
+
+    #[test]
+    fn lookalike_hosts_are_not_iherb() {
+        assert!(!is_iherb_host("notiherb.com"));
+        assert!(!is_iherb_host("evil-iherb.com"));
+        assert!(!is_iherb_host("iherb.com.example.net"));
+        assert!(!is_iherb_host("iherb.co"));
+    }
+
+    #[test]
+    fn parses_dot_grouped_price() {
+        assert_eq!(parse_price_str("Rp 1.234.567"), Some(1234567.0));
+        assert_eq!(
+            detect_currency_from_text("Rp 1.234.567").as_deref(),
+            Some("IDR")
+        );
+    }
+
+    #[test]
+    fn parses_price_after_currency_word_ending_in_period() {
+        assert_eq!(parse_price_str("Rs. 1,299"), Some(1299.0));
+        assert_eq!(
+            detect_currency_from_text("Rs. 1,299").as_deref(),
+            Some("INR")
+        );
+    }
+
+    #[test]
+    fn parses_price_after_iso_code() {
+        assert_eq!(parse_price_str("INR 1,299"), Some(1299.0));
+        assert_eq!(
+            detect_currency_from_text("INR 1,299").as_deref(),
+            Some("INR")
+        );
+        assert_eq!(
+            detect_currency_from_text("PLN 49,99").as_deref(),
+            Some("PLN")
+        );
+    }
+
+    #[test]
+    fn parses_european_prices() {
+        assert_eq!(parse_price_str("€1.234,56"), Some(1234.56));
+        assert_eq!(parse_price_str("23,99 €"), Some(23.99));
+        assert_eq!(parse_price_str("1,000"), Some(1000.0));
+    }
+
+    #[test]
+    fn longer_currency_prefixes_win_over_shorter_ones() {
+        for (text, currency) in [
+            ("R 249,99", "ZAR"),
+            ("R$ 49,90", "BRL"),
+            ("Rs 1,299", "INR"),
+            ("Rs. 1,299", "INR"),
+            ("RM 59.90", "MYR"),
+            ("Rp 150.000", "IDR"),
+        ] {
+            assert_eq!(
+                detect_currency_from_text(text).as_deref(),
+                Some(currency),
+                "{}",
+                text
+            );
+        }
+        assert_eq!(detect_currency_from_text("Rupees 100"), None);
+    }
+
+    #[test]
+    fn country_from_storefront_urls() {
+        for (url, country) in [
+            ("https://www.iherb.com/pr/p/61864", Some("us")),
+            ("https://iherb.com/pr/p/61864", Some("us")),
+            ("https://kr.iherb.com/pr/p/61864", Some("kr")),
+            ("https://DE.iherb.com/pr/p/61864", Some("de")),
+            ("https://notiherb.com/pr/p/61864", None),
+            ("https://iherb.com.example.net/pr/p/61864", None),
+        ] {
+            assert_eq!(country_from_url(url).as_deref(), country, "{}", url);
+        }
+    }
+
+    /// Rating of the first star element in `html`.
+    fn star_rating(html: &str) -> Option<f64> {
+        let doc = Html::parse_fragment(html);
+        let sel = Selector::parse(STAR_RATING_SELECTOR).unwrap();
+        doc.select(&sel)
+            .find_map(|el| star_element_rating(el.value()))
+    }
+
+    #[test]
+    fn star_rating_from_slash_title() {
+        let html = r#"<a class="stars" title="4.8/5 - 42,328 Reviews"></a>"#;
+        assert_eq!(star_rating(html), Some(4.8));
+    }
+
+    #[test]
+    fn star_rating_from_out_of_title() {
+        let html = r#"<a class="stars" title="Rated 4.8 out of 5"></a>"#;
+        assert_eq!(star_rating(html), Some(4.8));
+    }
+
+    #[test]
+    fn star_rating_from_aria_label() {
+        let html = r#"<div role="img" aria-label="4,8 out of 5 stars"></div>"#;
+        assert_eq!(star_rating(html), Some(4.8));
+    }
+
+    #[test]
+    fn star_rating_from_star_width() {
+        let html = r#"<span class="stars" style="display: block; width: 96%;"></span>"#;
+        assert_eq!(star_rating(html), Some(4.8));
+    }
+
+    #[test]
+    fn star_rating_rejects_out_of_range_values() {
+        assert_eq!(star_rating(r#"<a class="stars" title="48/5"></a>"#), None);
     }
 }
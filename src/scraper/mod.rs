@@ -1,5 +1,8 @@
+pub mod categories;
 pub mod extract;
 pub mod helpers;
 pub mod navigation;
 pub mod product;
+pub mod qa;
 pub mod search;
+pub mod selectors;
@@ -1,49 +1,184 @@
+use crate::cli::WaitStrategy;
+use crate::config::AppConfig;
 use crate::error::IherbError;
+use chromiumoxide::cdp::browser_protocol::network::{
+    EventLoadingFailed, EventLoadingFinished, EventRequestWillBeSent, RequestId,
+};
 use chromiumoxide::Page;
+use futures::stream::{BoxStream, StreamExt};
+use rand::Rng;
+use std::collections::HashSet;
+use std::sync::OnceLock;
 use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 
 const MAX_CLOUDFLARE_RETRIES: u32 = 3;
 const CLOUDFLARE_WAIT_SECS: u64 = 12;
 const CLOUDFLARE_TITLE_MARKERS: &[&str] = &["Just a moment", "Attention Required"];
+const SELECTOR_WAIT_SECS: u64 = 10;
+const SELECTOR_POLL_MS: u64 = 250;
+const RESPONSE_WAIT_SECS: u64 = 2;
+/// How long the network must be quiet before `networkidle` considers the page settled.
+const NETWORK_IDLE_MS: u64 = 500;
+/// Upper bound on the `networkidle` wait, for pages that never stop polling.
+const NETWORK_IDLE_MAX_SECS: u64 = 10;
+/// Wait before the first retry of a server error; it doubles with each further retry.
+const SERVER_ERROR_BACKOFF_SECS: u64 = 5;
+/// Maximum relative deviation of a jittered delay from `delay_ms`.
+const DELAY_JITTER: f64 = 0.3;
+
+/// Selector whose presence means search results have rendered.
+pub const SEARCH_READY_SELECTOR: &str = "div.product-cell-container";
+/// Selector whose presence means a product page has rendered.
+pub const PRODUCT_READY_SELECTOR: &str = "h1#name";
+
+/// Outcome of a successful navigation.
+pub struct NavigationResult {
+    /// HTTP status of the main document response, when Chrome reported one.
+    pub status: Option<u16>,
+    pub html: String,
+    /// Storefront country code of the final URL (e.g. "us", "de").
+    pub effective_country: Option<String>,
+    /// Whether a Cloudflare challenge was shown (and passed) on the way.
+    pub challenged: bool,
+}
 
 pub struct Navigator {
     delay_ms: u64,
+    product_delay_ms: u64,
+    jitter: bool,
+    rate_limiter: Option<&'static RateLimiter>,
+    timeout: Duration,
+    server_error_retries: u32,
+    wait_strategy: WaitStrategy,
+    country: String,
+}
+
+/// Token bucket capping page requests per minute across every `Navigator` in the
+/// process. The bucket holds a single token, so no 60s window ever exceeds the cap.
+struct RateLimiter {
+    tokens_per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// The process-wide limiter. The first caller's `max_rpm` wins; it comes from
+    /// the run's config, so every caller passes the same value.
+    fn shared(max_rpm: u32) -> &'static RateLimiter {
+        static LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+        LIMITER.get_or_init(|| RateLimiter {
+            tokens_per_sec: f64::from(max_rpm) / 60.0,
+            bucket: Mutex::new(Bucket {
+                tokens: 1.0,
+                refilled_at: Instant::now(),
+            }),
+        })
+    }
+
+    /// Take a token, sleeping until one is available. The lock is held while
+    /// sleeping so concurrent callers queue up in order.
+    async fn acquire(&self) {
+        let mut bucket = self.bucket.lock().await;
+        let elapsed = bucket.refilled_at.elapsed().as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.tokens_per_sec).min(1.0);
+        bucket.refilled_at = Instant::now();
+
+        if bucket.tokens < 1.0 {
+            let wait = Duration::from_secs_f64((1.0 - bucket.tokens) / self.tokens_per_sec);
+            tracing::debug!("Rate limit reached, waiting {:?}", wait);
+            tokio::time::sleep(wait).await;
+            bucket.refilled_at = Instant::now();
+            bucket.tokens = 1.0;
+        }
+        bucket.tokens -= 1.0;
+    }
+}
+
+/// Start or end of a network request, as seen by the `networkidle` wait.
+enum NetworkEvent {
+    Started(RequestId),
+    Done(RequestId),
 }
 
 impl Navigator {
-    pub fn new(delay_ms: u64) -> Self {
-        Self { delay_ms }
+    pub fn new(config: &AppConfig) -> Self {
+        Self {
+            delay_ms: config.delay_ms,
+            product_delay_ms: config.product_delay_ms,
+            jitter: config.jitter,
+            rate_limiter: config.max_rpm.map(RateLimiter::shared),
+            timeout: Duration::from_secs(config.timeout_secs),
+            server_error_retries: config.server_error_retries,
+            wait_strategy: config.wait_strategy,
+            country: config.country.clone(),
+        }
+    }
+
+    /// Navigate to a URL and return the page HTML, giving up after the configured timeout.
+    ///
+    /// With a `selector`, returns as soon as it matches instead of sleeping a fixed delay.
+    /// Falls back to the delay and `document.readyState` polling if it never appears.
+    pub async fn navigate(
+        &self,
+        page: &Page,
+        url: &str,
+        selector: Option<&str>,
+    ) -> Result<NavigationResult, IherbError> {
+        if let Some(limiter) = self.rate_limiter {
+            limiter.acquire().await;
+        }
+        match tokio::time::timeout(self.timeout, self.navigate_inner(page, url, selector)).await {
+            Ok(result) => result,
+            Err(_) => Err(IherbError::Navigation(format!(
+                "Timed out after {}s navigating to {}",
+                self.timeout.as_secs(),
+                url
+            ))),
+        }
     }
 
-    pub async fn navigate(&self, page: &Page, url: &str) -> Result<String, IherbError> {
+    async fn navigate_inner(
+        &self,
+        page: &Page,
+        url: &str,
+        selector: Option<&str>,
+    ) -> Result<NavigationResult, IherbError> {
         tracing::info!("Navigating to: {}", url);
 
+        // Subscribe before navigating so requests started by the page itself are seen
+        let network_events = match self.wait_strategy {
+            WaitStrategy::NetworkIdle => Some(network_events(page).await?),
+            WaitStrategy::Ready => None,
+        };
+
         page.goto(url)
             .await
             .map_err(|e| IherbError::Navigation(format!("Failed to navigate to {}: {}", url, e)))?;
 
-        // Wait for initial page load
-        tokio::time::sleep(Duration::from_millis(self.delay_ms)).await;
+        let selector_found = match selector {
+            Some(sel) => self.wait_for_selector(page, sel).await,
+            None => false,
+        };
 
-        // Wait for document.readyState === 'complete' (up to 10s)
-        for _ in 0..20 {
-            let ready = page
-                .evaluate("document.readyState")
-                .await
-                .ok()
-                .and_then(|v| v.into_value::<String>().ok())
-                .unwrap_or_default();
-            if ready == "complete" {
-                break;
-            }
-            tokio::time::sleep(Duration::from_millis(500)).await;
+        if !selector_found {
+            // Wait for initial page load
+            tokio::time::sleep(self.jittered_delay(self.delay_ms)).await;
+            self.wait_for_ready_state(page).await;
         }
 
         // Check for and handle Cloudflare challenge
+        let mut challenged = false;
         for attempt in 1..=MAX_CLOUDFLARE_RETRIES {
             if !self.is_cloudflare_challenge(page).await {
                 break;
             }
+            challenged = true;
 
             if attempt == MAX_CLOUDFLARE_RETRIES {
                 return Err(IherbError::CloudflareBlocked(MAX_CLOUDFLARE_RETRIES));
@@ -83,43 +218,159 @@ impl Navigator {
             }
         }
 
+        if let Some(sel) = selector.filter(|_| !selector_found) {
+            // The challenge page may have delayed the real content
+            self.wait_for_selector(page, sel).await;
+        }
+
+        if let Some(events) = network_events {
+            wait_for_network_idle(events).await;
+        }
+
+        let status = self.response_status(page).await;
+        if let Some(status) = status.filter(|s| (500..600).contains(s)) {
+            return Err(IherbError::ServerError(status));
+        }
+
         let html = page
             .content()
             .await
             .map_err(|e| IherbError::Navigation(format!("Failed to get page content: {}", e)))?;
 
-        Ok(html)
+        // iHerb may redirect to a different storefront based on IP
+        let final_url = page.url().await.ok().flatten();
+        let effective_country = final_url
+            .as_deref()
+            .and_then(super::helpers::country_from_url);
+        if let Some(ref country) = effective_country {
+            if *country != self.country {
+                tracing::warn!(
+                    "Requested the '{}' storefront but iHerb served '{}' ({})",
+                    self.country,
+                    country,
+                    final_url.as_deref().unwrap_or_default()
+                );
+            }
+        }
+
+        Ok(NavigationResult {
+            status,
+            html,
+            effective_country,
+            challenged,
+        })
     }
 
+    /// Navigate, retrying failures up to `max_retries` times. HTTP 5xx responses are
+    /// usually transient on iHerb's side, so they are counted separately and retried
+    /// up to `--server-error-retries` times with a longer backoff.
     pub async fn navigate_with_retry(
         &self,
         page: &Page,
         url: &str,
+        selector: Option<&str>,
         max_retries: u32,
-    ) -> Result<String, IherbError> {
-        let mut last_err = None;
+    ) -> Result<NavigationResult, IherbError> {
+        let mut failures = 0;
+        let mut server_errors = 0;
 
-        for attempt in 1..=max_retries + 1 {
-            match self.navigate(page, url).await {
-                Ok(html) => return Ok(html),
+        loop {
+            let backoff = match self.navigate(page, url, selector).await {
+                Ok(result) => return Ok(result),
+                Err(IherbError::ServerError(status))
+                    if server_errors < self.server_error_retries =>
+                {
+                    server_errors += 1;
+                    tracing::warn!(
+                        "{} returned HTTP {} (server error {}/{})",
+                        url,
+                        status,
+                        server_errors,
+                        self.server_error_retries + 1
+                    );
+                    Duration::from_secs(SERVER_ERROR_BACKOFF_SECS << (server_errors - 1).min(6))
+                }
+                Err(e @ IherbError::ServerError(_)) => return Err(e),
                 Err(e) => {
+                    failures += 1;
                     tracing::warn!(
                         "Navigation attempt {}/{} failed: {}",
-                        attempt,
+                        failures,
                         max_retries + 1,
                         e
                     );
-                    last_err = Some(e);
-                    if attempt <= max_retries {
-                        let backoff = Duration::from_secs(2u64.pow(attempt - 1));
-                        tracing::info!("Retrying in {:?}...", backoff);
-                        tokio::time::sleep(backoff).await;
+                    if failures > max_retries {
+                        return Err(e);
                     }
+                    Duration::from_secs(2u64.pow(failures - 1))
                 }
+            };
+            tracing::info!("Retrying in {:?}...", backoff);
+            tokio::time::sleep(backoff).await;
+        }
+    }
+
+    /// Poll until `selector` matches, a Cloudflare challenge appears, or the wait
+    /// cap is reached. Returns whether the selector was found.
+    async fn wait_for_selector(&self, page: &Page, selector: &str) -> bool {
+        let script = format!(
+            "document.querySelector({}) !== null",
+            serde_json::to_string(selector).unwrap_or_default()
+        );
+        let polls = SELECTOR_WAIT_SECS * 1000 / SELECTOR_POLL_MS;
+        for _ in 0..polls {
+            let found = page
+                .evaluate(script.as_str())
+                .await
+                .ok()
+                .and_then(|v| v.into_value::<bool>().ok())
+                .unwrap_or(false);
+            if found {
+                tracing::debug!("Selector '{}' present", selector);
+                return true;
+            }
+            if self.is_cloudflare_challenge(page).await {
+                return false;
             }
+            tokio::time::sleep(Duration::from_millis(SELECTOR_POLL_MS)).await;
         }
+        tracing::debug!(
+            "Selector '{}' not found within {}s",
+            selector,
+            SELECTOR_WAIT_SECS
+        );
+        false
+    }
 
-        Err(last_err.unwrap())
+    /// HTTP status of the page's latest navigation, taken after any Cloudflare
+    /// challenge has been resolved so it reflects the real page.
+    async fn response_status(&self, page: &Page) -> Option<u16> {
+        let request = tokio::time::timeout(
+            Duration::from_secs(RESPONSE_WAIT_SECS),
+            page.wait_for_navigation_response(),
+        )
+        .await
+        .ok()?
+        .ok()??;
+        let status = request.response.as_ref()?.status;
+        tracing::debug!("Navigation response status: {}", status);
+        u16::try_from(status).ok()
+    }
+
+    /// Wait for document.readyState === 'complete' (up to 10s)
+    async fn wait_for_ready_state(&self, page: &Page) {
+        for _ in 0..20 {
+            let ready = page
+                .evaluate("document.readyState")
+                .await
+                .ok()
+                .and_then(|v| v.into_value::<String>().ok())
+                .unwrap_or_default();
+            if ready == "complete" {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(500)).await;
+        }
     }
 
     async fn is_cloudflare_challenge(&self, page: &Page) -> bool {
@@ -135,6 +386,76 @@ impl Navigator {
     }
 
     pub async fn rate_limit_delay(&self) {
-        tokio::time::sleep(Duration::from_millis(self.delay_ms)).await;
+        tokio::time::sleep(self.jittered_delay(self.delay_ms)).await;
+    }
+
+    /// The pause between two distinct products (or queries) of a batch.
+    pub async fn product_delay(&self) {
+        tokio::time::sleep(self.jittered_delay(self.product_delay_ms)).await;
+    }
+
+    /// `delay_ms`, randomized uniformly by up to ±30% so requests don't arrive at a
+    /// machine-regular cadence. The mean stays at `delay_ms`.
+    fn jittered_delay(&self, delay_ms: u64) -> Duration {
+        if !self.jitter {
+            return Duration::from_millis(delay_ms);
+        }
+        let factor = rand::thread_rng().gen_range(1.0 - DELAY_JITTER..=1.0 + DELAY_JITTER);
+        Duration::from_millis((delay_ms as f64 * factor).round() as u64)
+    }
+}
+
+/// Merged stream of request start/finish events for `page`.
+async fn network_events(page: &Page) -> Result<BoxStream<'static, NetworkEvent>, IherbError> {
+    let listen_err =
+        |e| IherbError::Navigation(format!("Failed to listen for network events: {}", e));
+    let started = page
+        .event_listener::<EventRequestWillBeSent>()
+        .await
+        .map_err(listen_err)?
+        .map(|e| NetworkEvent::Started(e.request_id.clone()));
+    let finished = page
+        .event_listener::<EventLoadingFinished>()
+        .await
+        .map_err(listen_err)?
+        .map(|e| NetworkEvent::Done(e.request_id.clone()));
+    let failed = page
+        .event_listener::<EventLoadingFailed>()
+        .await
+        .map_err(listen_err)?
+        .map(|e| NetworkEvent::Done(e.request_id.clone()));
+    Ok(futures::stream::select(started, futures::stream::select(finished, failed)).boxed())
+}
+
+/// Wait until no request has been in flight for `NETWORK_IDLE_MS`, giving up
+/// after `NETWORK_IDLE_MAX_SECS`. Events buffered since navigation started are
+/// replayed first, so requests that already completed are accounted for.
+async fn wait_for_network_idle(mut events: BoxStream<'static, NetworkEvent>) {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(NETWORK_IDLE_MAX_SECS);
+    let mut in_flight = HashSet::new();
+    loop {
+        let quiet_until = tokio::time::Instant::now() + Duration::from_millis(NETWORK_IDLE_MS);
+        match tokio::time::timeout_at(quiet_until.min(deadline), events.next()).await {
+            Ok(Some(NetworkEvent::Started(id))) => {
+                in_flight.insert(id);
+            }
+            Ok(Some(NetworkEvent::Done(id))) => {
+                in_flight.remove(&id);
+            }
+            Ok(None) => return,
+            Err(_) if in_flight.is_empty() => {
+                tracing::debug!("Network idle");
+                return;
+            }
+            Err(_) if tokio::time::Instant::now() >= deadline => {
+                tracing::debug!(
+                    "Network not idle after {}s ({} requests in flight)",
+                    NETWORK_IDLE_MAX_SECS,
+                    in_flight.len()
+                );
+                return;
+            }
+            Err(_) => {}
+        }
     }
 }
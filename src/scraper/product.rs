@@ -1,73 +1,334 @@
+use crate::cli::ExtractStrategy;
 use crate::error::IherbError;
-use crate::model::{Nutrient, ProductDetail, ReviewDistribution, SupplementFacts};
+use crate::model::{
+    CurrencySource, FieldSource, Nutrient, ProductDetail, ReviewDistribution, SupplementFacts,
+    Variation,
+};
 use chromiumoxide::Page;
 use scraper::{Html, Selector};
+use std::collections::BTreeMap;
 
 use super::helpers::{
-    debug_dump_html, detect_currency_from_html, extract_text, is_not_found_page, parse_price_str,
-    parse_review_count,
+    country_from_url, currency_or_fallback, detect_currency_from_html, expiration_sentence,
+    extract_rank_badge, extract_text, is_not_found_page, parse_amount, parse_pack_size,
+    parse_price_str, parse_review_count, parse_weight, parse_width_percent, star_element_rating,
+    STAR_RATING_SELECTOR,
 };
-
-/// Extract product detail from a page, trying JSON-LD first, then JS globals, then DOM.
+use super::selectors;
+
+/// Extract product detail with each strategy of `order` in turn until one yields a
+/// product. The default order is JSON-LD (most reliable structured data), JS globals,
+/// `__NEXT_DATA__`, then DOM scraping. If none does, the last DOM error is returned,
+/// or `ProductNotFound` when DOM scraping was not among them.
+///
+/// Without `enrich_dom`, a JSON-LD or JS globals product that already has its core
+/// fields is returned without the DOM pass (see `needs_dom_enrichment`).
 pub async fn extract_product(
     page: &Page,
     html: &str,
     product_id: &str,
     base_url: &str,
     currency: &str,
+    order: &[ExtractStrategy],
+    enrich_dom: bool,
 ) -> Result<ProductDetail, IherbError> {
-    debug_dump_html(html, &format!("product_{}", product_id));
-
-    // Try JSON-LD first (most reliable structured data)
-    if let Some(json_ld) = super::extract::extract_json_ld(html) {
-        tracing::debug!("Attempting JSON-LD extraction for product {}", product_id);
-        if let Some(mut product) = parse_from_json_ld(&json_ld, product_id, base_url) {
-            // JSON-LD has core fields; enrich with DOM-only fields
-            enrich_from_html(html, &mut product);
-            tracing::info!("Successfully extracted product from JSON-LD + DOM enrichment");
+    let mut dom_error = None;
+    for &strategy in order {
+        let extracted = match strategy {
+            ExtractStrategy::JsonLd => {
+                extract_from_json_ld(html, product_id, base_url, currency, enrich_dom)
+            }
+            ExtractStrategy::JsGlobals => {
+                extract_from_js_globals(page, html, product_id, base_url, currency, enrich_dom)
+                    .await
+            }
+            ExtractStrategy::NextData => {
+                extract_from_next_data(page, product_id, base_url, currency).await
+            }
+            ExtractStrategy::Dom => match extract_from_dom(html, product_id, base_url, currency) {
+                Ok(product) => Some(product),
+                Err(e) => {
+                    dom_error = Some(e);
+                    None
+                }
+            },
+        };
+        if let Some(product) = extracted {
             return Ok(product);
         }
-        tracing::warn!("JSON-LD extraction failed, trying JS globals");
     }
+    Err(dom_error.unwrap_or_else(|| IherbError::ProductNotFound(product_id.to_string())))
+}
 
-    // Try JS globals
-    if let Ok(Some(globals)) = super::extract::extract_js_globals(page).await {
-        tracing::debug!(
-            "Attempting JS globals extraction for product {}",
-            product_id
-        );
-        if let Some(mut product) = parse_from_js_globals(&globals, product_id, base_url, currency) {
-            enrich_from_html(html, &mut product);
+/// Explicit "no longer available" notices on a discontinued product's page.
+const DISCONTINUED_SELECTOR: &str =
+    "[data-testid='discontinued-message'], .discontinued-message, #discontinued-product, .product-discontinued";
+/// The block of suggested alternatives shown in place of a discontinued product.
+const ALTERNATIVES_SELECTOR: &str =
+    "[data-testid='product-alternatives'], .product-alternatives, #product-alternatives, .discontinued-alternatives";
+/// Where a live product page shows its price.
+const PRICE_SELECTOR: &str =
+    "#price, .price-inner-text, [itemprop='price'], input#share-email-model";
+
+/// Attributes holding a gallery image's URL, full-resolution ones first.
+const GALLERY_IMAGE_ATTRS: &[&str] = &[
+    "data-large-img",
+    "data-zoom-image",
+    "data-image-large-src",
+    "src",
+];
+
+/// Detect the page iHerb shows for a discontinued listing: not a 404, but a notice
+/// and a block of alternatives in place of the product. Recognized by an explicit
+/// notice, or by an alternatives block on a page without a price. Returns the ids of
+/// the suggested alternatives, which may be empty.
+pub fn detect_discontinued(html: &str, product_id: &str) -> Option<Vec<String>> {
+    let doc = Html::parse_document(html);
+    let has = |selector: &str| {
+        Selector::parse(selector)
+            .map(|sel| doc.select(&sel).next().is_some())
+            .unwrap_or(false)
+    };
+    let alternatives_sel = Selector::parse(ALTERNATIVES_SELECTOR).ok()?;
+    let alternatives_block = doc.select(&alternatives_sel).next();
+    let discontinued =
+        has(DISCONTINUED_SELECTOR) || (alternatives_block.is_some() && !has(PRICE_SELECTOR));
+    if !discontinued {
+        return None;
+    }
+
+    let mut alternatives = Vec::new();
+    if let (Some(block), Ok(link_sel)) = (alternatives_block, Selector::parse("a[href*='/pr/']")) {
+        for link in block.select(&link_sel) {
+            let id = link
+                .value()
+                .attr("href")
+                .and_then(super::search::extract_id_from_url);
+            if let Some(id) = id {
+                if id != product_id && !alternatives.contains(&id) {
+                    alternatives.push(id);
+                }
+            }
+        }
+    }
+    Some(alternatives)
+}
+
+/// Extract product detail from saved HTML alone, as in `--replay`. The JS globals and
+/// `__NEXT_DATA__` strategies need a live page, so only JSON-LD and the DOM are tried.
+pub fn extract_product_from_html(
+    html: &str,
+    product_id: &str,
+    base_url: &str,
+    currency: &str,
+    order: &[ExtractStrategy],
+    enrich_dom: bool,
+) -> Result<ProductDetail, IherbError> {
+    let mut dom_error = None;
+    for &strategy in order {
+        match strategy {
+            ExtractStrategy::JsonLd => {
+                if let Some(product) =
+                    extract_from_json_ld(html, product_id, base_url, currency, enrich_dom)
+                {
+                    return Ok(product);
+                }
+            }
+            ExtractStrategy::Dom => match extract_from_dom(html, product_id, base_url, currency) {
+                Ok(product) => return Ok(product),
+                Err(e) => dom_error = Some(e),
+            },
+            ExtractStrategy::JsGlobals | ExtractStrategy::NextData => {}
+        }
+    }
+    Err(dom_error.unwrap_or_else(|| IherbError::ProductNotFound(product_id.to_string())))
+}
+
+async fn extract_from_js_globals(
+    page: &Page,
+    html: &str,
+    product_id: &str,
+    base_url: &str,
+    currency: &str,
+    enrich_dom: bool,
+) -> Option<ProductDetail> {
+    let globals = super::extract::extract_js_globals(page).await.ok()??;
+    tracing::debug!(
+        "Attempting JS globals extraction for product {}",
+        product_id
+    );
+    match parse_from_js_globals(&globals, product_id, base_url, currency) {
+        Some(mut product) => {
+            record_provenance(&mut product, None, FieldSource::JsGlobals);
+            if !needs_dom_enrichment(&product, enrich_dom) {
+                tracing::info!("Successfully extracted product from JS globals alone");
+                return Some(finalize_product(product));
+            }
+            enrich_from_html(html, base_url, &mut product);
             tracing::info!("Successfully extracted product from JS globals + DOM enrichment");
-            return Ok(product);
+            Some(finalize_product(product))
+        }
+        None => {
+            tracing::warn!("JS globals extraction failed, trying other strategies");
+            None
         }
-        tracing::warn!("JS globals extraction failed, trying __NEXT_DATA__");
     }
+}
 
-    // Try __NEXT_DATA__
-    if let Ok(Some(next_data)) = super::extract::extract_next_data(page).await {
-        tracing::debug!(
-            "Attempting __NEXT_DATA__ extraction for product {}",
-            product_id
-        );
-        if let Some(product) = parse_from_next_data(&next_data, product_id, base_url) {
+async fn extract_from_next_data(
+    page: &Page,
+    product_id: &str,
+    base_url: &str,
+    currency: &str,
+) -> Option<ProductDetail> {
+    let next_data = super::extract::extract_next_data(page).await.ok()??;
+    tracing::debug!(
+        "Attempting __NEXT_DATA__ extraction for product {}",
+        product_id
+    );
+    match parse_from_next_data(&next_data, product_id, base_url, currency) {
+        Some(mut product) => {
+            record_provenance(&mut product, None, FieldSource::NextData);
             tracing::info!("Successfully extracted product from __NEXT_DATA__");
-            return Ok(product);
+            Some(finalize_product(product))
+        }
+        None => {
+            tracing::warn!("__NEXT_DATA__ extraction failed, trying other strategies");
+            None
         }
-        tracing::warn!("__NEXT_DATA__ extraction failed, falling back to DOM");
     }
+}
 
-    // Fallback to DOM scraping
+fn extract_from_dom(
+    html: &str,
+    product_id: &str,
+    base_url: &str,
+    currency: &str,
+) -> Result<ProductDetail, IherbError> {
     tracing::info!("Extracting product from DOM for {}", product_id);
-    parse_from_html(html, product_id, base_url, currency)
+    let mut product = parse_from_html(html, product_id, base_url, currency)?;
+    record_provenance(&mut product, None, FieldSource::Dom);
+    Ok(finalize_product(product))
+}
+
+fn extract_from_json_ld(
+    html: &str,
+    product_id: &str,
+    base_url: &str,
+    currency: &str,
+    enrich_dom: bool,
+) -> Option<ProductDetail> {
+    let json_ld = super::extract::extract_json_ld(html)?;
+    tracing::debug!("Attempting JSON-LD extraction for product {}", product_id);
+    match parse_from_json_ld(&json_ld, product_id, base_url, currency) {
+        Some(mut product) => {
+            record_provenance(&mut product, None, FieldSource::JsonLd);
+            if !needs_dom_enrichment(&product, enrich_dom) {
+                tracing::info!("Successfully extracted product from JSON-LD alone");
+                return Some(finalize_product(product));
+            }
+            // JSON-LD has core fields; enrich with DOM-only fields
+            enrich_from_html(html, base_url, &mut product);
+            tracing::info!("Successfully extracted product from JSON-LD + DOM enrichment");
+            Some(finalize_product(product))
+        }
+        None => {
+            tracing::warn!("JSON-LD extraction failed, trying other strategies");
+            None
+        }
+    }
+}
+
+/// Whether a structured-data product still needs the DOM pass. It always does unless
+/// `--no-enrich-dom` turned enrichment off, and even then when the name, brand or
+/// price is missing, since the overview can't do without them.
+fn needs_dom_enrichment(product: &ProductDetail, enrich_dom: bool) -> bool {
+    enrich_dom || product.name.is_empty() || product.brand.is_empty() || product.price <= 0.0
+}
+
+/// Compute fields derived from already-extracted data, regardless of which strategy succeeded.
+fn finalize_product(mut product: ProductDetail) -> ProductDetail {
+    product.shipping_weight_quantity = product.shipping_weight.as_deref().and_then(parse_weight);
+    product.pack_size = parse_pack_size(&product.name)
+        .or_else(|| product.highlights.iter().find_map(|h| parse_pack_size(h)));
+    if product.currency_source == Some(CurrencySource::Defaulted) {
+        if let Some(provenance) = product.provenance.as_mut() {
+            provenance.insert("currency".to_string(), FieldSource::ConfigFallback);
+        }
+    }
+    product
+}
+
+/// Product fields `--explain` reports a source for.
+const EXPLAINED_FIELDS: &[&str] = &[
+    "name",
+    "brand",
+    "brand_url",
+    "price",
+    "original_price",
+    "currency",
+    "rating",
+    "review_count",
+    "rank",
+    "in_stock",
+    "availability_note",
+    "description",
+    "highlights",
+    "image_urls",
+    "product_code",
+    "upc",
+    "ingredients",
+    "supplement_facts",
+    "suggested_use",
+    "warnings",
+    "expiration_note",
+    "shipping_weight",
+    "ships_from",
+    "estimated_delivery",
+    "variations",
+    "category_breadcrumb",
+    "review_distribution",
+];
+
+/// Credit `source` with every explained field that has a value and did not have the
+/// same value in `before`, the product as serialized before this extraction step.
+fn record_provenance(
+    product: &mut ProductDetail,
+    before: Option<&serde_json::Value>,
+    source: FieldSource,
+) {
+    let Ok(after) = serde_json::to_value(&*product) else {
+        return;
+    };
+    let provenance = product.provenance.get_or_insert_with(BTreeMap::new);
+    for &field in EXPLAINED_FIELDS {
+        let value = &after[field];
+        if has_value(value) && before.is_none_or(|before| &before[field] != value) {
+            provenance.insert(field.to_string(), source);
+        }
+    }
+}
+
+/// Whether a serialized field holds something; a zero price counts as missing.
+fn has_value(value: &serde_json::Value) -> bool {
+    match value {
+        serde_json::Value::Null => false,
+        serde_json::Value::String(s) => !s.is_empty(),
+        serde_json::Value::Array(items) => !items.is_empty(),
+        serde_json::Value::Object(fields) => !fields.is_empty(),
+        serde_json::Value::Number(n) => n.as_f64() != Some(0.0),
+        serde_json::Value::Bool(_) => true,
+    }
 }
 
 /// Extract price, original price, and currency from JSON-LD offers.
 /// Handles both top-level `price`/`priceCurrency` and the `priceSpecification` array.
-fn extract_prices_from_offers(offers: Option<&serde_json::Value>) -> (f64, Option<f64>, String) {
+fn extract_prices_from_offers(
+    offers: Option<&serde_json::Value>,
+) -> (f64, Option<f64>, Option<String>) {
     let offers = match offers {
         Some(o) => o,
-        None => return (0.0, None, "USD".to_string()),
+        None => return (0.0, None, None),
     };
 
     // Try top-level offers.price
@@ -84,7 +345,7 @@ fn extract_prices_from_offers(offers: Option<&serde_json::Value>) -> (f64, Optio
         .map(|s| s.to_string());
 
     if let Some(price) = top_price {
-        return (price, None, top_currency.unwrap_or_else(|| "USD".to_string()));
+        return (price, None, top_currency);
     }
 
     // Fall back to priceSpecification array
@@ -124,14 +385,10 @@ fn extract_prices_from_offers(offers: Option<&serde_json::Value>) -> (f64, Optio
 
         let price = current_price.unwrap_or(0.0);
         let original = strikethrough_price.filter(|&op| op > price);
-        let currency = currency
-            .or(top_currency)
-            .unwrap_or_else(|| "USD".to_string());
-
-        return (price, original, currency);
+        return (price, original, currency.or(top_currency));
     }
 
-    (0.0, None, top_currency.unwrap_or_else(|| "USD".to_string()))
+    (0.0, None, top_currency)
 }
 
 /// Parse product from JSON-LD structured data.
@@ -139,6 +396,7 @@ fn parse_from_json_ld(
     data: &serde_json::Value,
     product_id: &str,
     base_url: &str,
+    currency: &str,
 ) -> Option<ProductDetail> {
     let name = data.get("name").and_then(|v| v.as_str())?.to_string();
 
@@ -159,13 +417,16 @@ fn parse_from_json_ld(
     let offers = data.get("offers");
 
     // Try top-level offers.price first, then fall back to priceSpecification
-    let (price, original_price, currency) = extract_prices_from_offers(offers);
+    let (price, original_price, offer_currency) = extract_prices_from_offers(offers);
+    let (currency, currency_source) = currency_or_fallback(offer_currency, currency);
 
-    let in_stock = offers
+    let availability = offers
         .and_then(|o| o.get("availability"))
-        .and_then(|v| v.as_str())
-        .map(|s| s.contains("InStock"))
-        .unwrap_or(true);
+        .and_then(|v| v.as_str());
+    let in_stock = availability.map(|s| s.contains("InStock")).unwrap_or(true);
+    let availability_note = availability
+        .filter(|_| !in_stock)
+        .and_then(schema_availability_note);
 
     let agg = data.get("aggregateRating");
     let rating = agg.and_then(|a| {
@@ -206,27 +467,130 @@ fn parse_from_json_ld(
         .map(|s| s.to_string())
         .unwrap_or_else(|| format!("{}/pr/p/{}", base_url, product_id));
 
+    let supplement_facts = parse_json_ld_nutrition(data);
+
     Some(ProductDetail {
         name,
         brand,
+        brand_url: None,
         price,
         original_price,
         currency,
+        currency_source: Some(currency_source),
         rating,
         review_count,
+        rank: None,
         product_url,
         product_id: product_id.to_string(),
         in_stock,
+        availability_note,
         description,
+        highlights: Vec::new(),
+        image_urls: Vec::new(),
         product_code,
         upc,
-        ingredients: None,      // enriched from DOM
-        supplement_facts: None, // enriched from DOM
-        suggested_use: None,    // enriched from DOM
-        warnings: None,         // enriched from DOM
-        shipping_weight: None,  // enriched from DOM
+        ingredients: None,     // enriched from DOM
+        supplement_facts,      // serving info only; nutrients enriched from DOM
+        suggested_use: None,   // enriched from DOM
+        warnings: None,        // enriched from DOM
+        expiration_note: None, // enriched from DOM
+        shipping_weight: None, // enriched from DOM
+        shipping_weight_quantity: None,
+        pack_size: None,
+        ships_from: None,
+        estimated_delivery: None,
+        variations: parse_json_ld_variants(data, product_id),
         category_breadcrumb: None,
         review_distribution: None, // enriched from DOM
+        qa: None,
+        effective_country: None,
+        provenance: None,
+    })
+}
+
+/// The variants of a JSON-LD `ProductGroup`, from its `hasVariant` nodes. A variant's
+/// id is its `productID`, or else the number its `url` ends in; its label is the
+/// `size` or `color` it varies by, or else its name.
+fn parse_json_ld_variants(data: &serde_json::Value, product_id: &str) -> Vec<Variation> {
+    let variants = match data.get("hasVariant") {
+        Some(serde_json::Value::Array(variants)) => variants.as_slice(),
+        Some(variant) => std::slice::from_ref(variant),
+        None => &[],
+    };
+    let mut variations: Vec<Variation> = Vec::new();
+    for variant in variants {
+        let id = variant
+            .get("productID")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+            .or_else(|| {
+                let url = variant.get("url").and_then(|v| v.as_str())?;
+                let tail = url.trim_end_matches('/').rsplit('/').next()?;
+                tail.chars()
+                    .all(|c| c.is_ascii_digit())
+                    .then(|| tail.to_string())
+            })
+            .filter(|id| !id.is_empty());
+        let label = ["size", "color", "name"]
+            .iter()
+            .find_map(|key| variant.get(*key).and_then(|v| v.as_str()))
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty());
+        let (Some(id), Some(label)) = (id, label) else {
+            continue;
+        };
+        if variations.iter().any(|v| v.product_id == id) {
+            continue;
+        }
+        let offers = variant.get("offers");
+        let (price, _, _) = extract_prices_from_offers(offers);
+        let in_stock = offers
+            .and_then(|o| o.get("availability"))
+            .and_then(|v| v.as_str())
+            .map(|s| s.contains("InStock"))
+            .unwrap_or(true);
+        variations.push(Variation {
+            label,
+            product_id: id,
+            price: Some(price).filter(|&p| p > 0.0),
+            in_stock,
+        });
+    }
+
+    // A group with only the current product isn't a real choice
+    if variations.len() == 1 && variations[0].product_id == product_id {
+        variations.clear();
+    }
+    variations
+}
+
+/// Parse serving info from a JSON-LD `nutrition` (NutritionInformation) object,
+/// falling back to product-level `servingSize`.
+fn parse_json_ld_nutrition(data: &serde_json::Value) -> Option<SupplementFacts> {
+    let nutrition = data.get("nutrition");
+    let text_field = |key: &str| {
+        nutrition
+            .and_then(|n| n.get(key))
+            .or_else(|| data.get(key))
+            .and_then(|v| {
+                v.as_str()
+                    .map(|s| s.trim().to_string())
+                    .or_else(|| v.as_f64().map(|n| n.to_string()))
+            })
+            .filter(|s| !s.is_empty())
+    };
+
+    let serving_size = text_field("servingSize");
+    let servings_per_container = text_field("servingsPerContainer");
+
+    if serving_size.is_none() && servings_per_container.is_none() {
+        return None;
+    }
+
+    Some(SupplementFacts {
+        serving_size,
+        servings_per_container,
+        nutrients: Vec::new(),
     })
 }
 
@@ -269,59 +633,137 @@ fn parse_from_js_globals(
         .and_then(|v| v.as_str())
         .map(|s| s.to_string());
 
+    // Rating fields go by different names in the two globals
+    let number = |obj: Option<&serde_json::Value>, keys: &[&str]| {
+        let obj = obj?;
+        keys.iter()
+            .find_map(|key| obj.get(key).and_then(js_global_number))
+    };
+    let rating = number(ihr, &["avgRating", "rtg"])
+        .or_else(|| number(pd, &["averageRating", "rating"]))
+        .filter(|r| *r > 0.0);
+    let review_count = number(ihr, &["rvwCnt", "rvwCount"])
+        .or_else(|| number(pd, &["reviewCount", "totalRatingCount"]))
+        .map(|n| n as u32);
+
     Some(ProductDetail {
         name,
         brand,
+        brand_url: None,
         price,
         original_price: None,
         currency: currency.to_string(),
-        rating: None,
-        review_count: None,
+        currency_source: Some(CurrencySource::Defaulted),
+        rating,
+        review_count,
+        rank: None,
         product_url: format!("{}/pr/p/{}", base_url, product_id),
         product_id: product_id.to_string(),
         in_stock: true,
+        availability_note: None,
         description: None,
+        highlights: Vec::new(),
+        image_urls: Vec::new(),
         product_code,
         upc: None,
         ingredients: None,
         supplement_facts: None,
         suggested_use: None,
         warnings: None,
+        expiration_note: None,
         shipping_weight: None,
+        shipping_weight_quantity: None,
+        pack_size: None,
+        ships_from: None,
+        estimated_delivery: None,
+        variations: Vec::new(),
         category_breadcrumb: None,
         review_distribution: None,
+        qa: None,
+        effective_country: None,
+        provenance: None,
     })
 }
 
-/// Enrich a ProductDetail with fields only available in the DOM (ingredients, supplement facts, etc.)
-fn enrich_from_html(html: &str, product: &mut ProductDetail) {
+/// Read a JS-globals number, which may be serialized as a JSON number or a string
+/// such as "4.8" or "1,234".
+fn js_global_number(value: &serde_json::Value) -> Option<f64> {
+    value
+        .as_f64()
+        .or_else(|| value.as_str()?.replace(',', "").trim().parse().ok())
+}
+
+/// Fill in and correct a structured-data product from the page's DOM. Fields this
+/// changes are credited to the DOM in the product's provenance.
+fn enrich_from_html(html: &str, base_url: &str, product: &mut ProductDetail) {
+    let before = serde_json::to_value(&*product).ok();
     let doc = Html::parse_document(html);
 
     if product.brand.is_empty() {
-        if let Some(brand) = extract_text(
-            &doc,
-            "#brand a span bdi, #brand a[data-testid='product-brand-link'] span bdi",
-        ) {
+        if let Some(brand) = extract_text(&doc, selectors::get("product_brand")) {
             product.brand = brand;
         }
     }
+    if product.brand_url.is_none() {
+        product.brand_url = extract_brand_url(&doc, base_url);
+    }
+    if product.image_urls.is_empty() {
+        product.image_urls = extract_image_urls(&doc, base_url);
+    }
 
     enrich_pricing(&doc, product);
     enrich_rating_and_reviews(&doc, product);
 
-    if let Some(stock_text) = extract_text(&doc, "#stock-status .stock-status-content strong") {
+    if let Some(stock_text) = extract_text(&doc, selectors::get("product_stock")) {
         product.in_stock = stock_text.to_lowercase().contains("in stock");
     }
+    // The page's stock message is more specific (e.g. a restock date) than structured data
+    if product.in_stock {
+        product.availability_note = None;
+    } else if let Some(note) = extract_availability_note(&doc) {
+        product.availability_note = Some(note);
+    }
+
+    if product.rank.is_none() {
+        product.rank = extract_rank_badge(&doc.root_element());
+    }
 
     enrich_product_specs(&doc, product);
+    enrich_shipping(&doc, product);
     parse_overview_sections(html, product);
+    if product.category_breadcrumb.is_none() {
+        product.category_breadcrumb = extract_breadcrumb(html, &doc);
+    }
 
-    if product.supplement_facts.is_none() {
-        product.supplement_facts = parse_supplement_facts_html(&doc);
+    if let Some(dom_facts) = parse_supplement_facts_html(&doc) {
+        product.supplement_facts = Some(match product.supplement_facts.take() {
+            Some(facts) => merge_supplement_facts(facts, dom_facts),
+            None => dom_facts,
+        });
     }
     if product.review_distribution.is_none() {
         product.review_distribution = parse_review_distribution_html(&doc);
     }
+    // The page's option labels ("120 Softgels") read better than JSON-LD variant names
+    let dom_variations = parse_variations_html(&doc, &product.product_id);
+    if !dom_variations.is_empty() {
+        product.variations = dom_variations;
+    }
+    record_provenance(product, before.as_ref(), FieldSource::Dom);
+}
+
+/// Fill gaps in structured-data supplement facts from the DOM table.
+fn merge_supplement_facts(mut facts: SupplementFacts, dom: SupplementFacts) -> SupplementFacts {
+    if facts.serving_size.is_none() {
+        facts.serving_size = dom.serving_size;
+    }
+    if facts.servings_per_container.is_none() {
+        facts.servings_per_container = dom.servings_per_container;
+    }
+    if facts.nutrients.is_empty() {
+        facts.nutrients = dom.nutrients;
+    }
+    facts
 }
 
 fn enrich_pricing(doc: &Html, product: &mut ProductDetail) {
@@ -359,7 +801,7 @@ fn enrich_rating_and_reviews(doc: &Html, product: &mut ProductDetail) {
         product.rating = extract_rating_from_stars(doc);
     }
     if product.review_count.is_none() {
-        if let Some(text) = extract_text(doc, "a.rating-count span") {
+        if let Some(text) = extract_text(doc, selectors::get("product_review_count")) {
             product.review_count = parse_review_count(&text);
         }
     }
@@ -375,6 +817,18 @@ fn enrich_product_specs(doc: &Html, product: &mut ProductDetail) {
     if product.upc.is_none() {
         product.upc = extract_spec(doc, "UPC");
     }
+    if product.expiration_note.is_none() {
+        product.expiration_note = extract_expiration_spec(doc);
+    }
+}
+
+fn enrich_shipping(doc: &Html, product: &mut ProductDetail) {
+    if product.ships_from.is_none() {
+        product.ships_from = extract_shipping_line(doc, SHIPS_FROM_LABELS);
+    }
+    if product.estimated_delivery.is_none() {
+        product.estimated_delivery = extract_shipping_line(doc, DELIVERY_LABELS);
+    }
 }
 
 /// Parse structured sections (Suggested Use, Warnings, Ingredients, Description) from product overview.
@@ -394,42 +848,66 @@ fn parse_overview_sections(html: &str, product: &mut ProductDetail) {
 
     for h3 in doc.select(&h3_sel) {
         let heading: String = h3.text().collect::<Vec<_>>().join("").trim().to_lowercase();
-        let content = match extract_sibling_div_text(&h3) {
-            Some(text) if !text.is_empty() => text,
-            _ => continue,
+        let Some(div) = sibling_div(&h3) else {
+            continue;
         };
+
+        // Bullet lists in the description are highlights (vegan, non-GMO, ...),
+        // kept apart from the free-text description.
+        let is_description = heading.contains("description");
+        if is_description && product.highlights.is_empty() {
+            product.highlights = list_items(&div);
+        }
+
+        let content = div_text(&div, is_description);
+        if content.is_empty() {
+            continue;
+        }
+        if product.expiration_note.is_none() {
+            product.expiration_note = expiration_sentence(&content);
+        }
         assign_section_by_heading(&heading, content, product);
     }
 }
 
-/// Extract text content from the first sibling `<div>` after a heading element.
-fn extract_sibling_div_text(heading: &scraper::ElementRef) -> Option<String> {
-    let mut next = heading.next_sibling();
-    while let Some(node) = next {
-        if let Some(el) = node.value().as_element() {
-            if el.name() == "div" {
-                let text: String = node
-                    .children()
-                    .filter_map(|child| {
-                        if let Some(text) = child.value().as_text() {
-                            Some(text.to_string())
-                        } else if child.value().is_element() {
-                            let el_ref = scraper::ElementRef::wrap(child)?;
-                            Some(el_ref.text().collect::<Vec<_>>().join(" "))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect::<Vec<_>>()
-                    .join(" ")
-                    .trim()
-                    .to_string();
-                return Some(text);
+/// The first sibling `<div>` after a heading element.
+fn sibling_div<'a>(heading: &scraper::ElementRef<'a>) -> Option<scraper::ElementRef<'a>> {
+    heading
+        .next_siblings()
+        .filter_map(scraper::ElementRef::wrap)
+        .find(|el| el.value().name() == "div")
+}
+
+/// Text content of a section `<div>`, optionally leaving out its bullet lists.
+fn div_text(div: &scraper::ElementRef, skip_lists: bool) -> String {
+    div.children()
+        .filter_map(|child| {
+            if let Some(text) = child.value().as_text() {
+                Some(text.to_string())
+            } else {
+                let el_ref = scraper::ElementRef::wrap(child)?;
+                if skip_lists && matches!(el_ref.value().name(), "ul" | "ol") {
+                    return None;
+                }
+                Some(el_ref.text().collect::<Vec<_>>().join(" "))
             }
-        }
-        next = node.next_sibling();
-    }
-    None
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim()
+        .to_string()
+}
+
+/// Text of every bullet item in a section `<div>`.
+fn list_items(div: &scraper::ElementRef) -> Vec<String> {
+    let Ok(li_sel) = Selector::parse("ul > li, ol > li") else {
+        return Vec::new();
+    };
+    div.select(&li_sel)
+        .map(|li| li.text().collect::<Vec<_>>().join(" "))
+        .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|text| !text.is_empty())
+        .collect()
 }
 
 fn assign_section_by_heading(heading: &str, content: String, product: &mut ProductDetail) {
@@ -442,6 +920,164 @@ fn assign_section_by_heading(heading: &str, content: String, product: &mut Produ
     }
 }
 
+/// Spec labels giving a date or period the product keeps until, as iHerb words them.
+const EXPIRATION_SPEC_LABELS: &[&str] = &[
+    "Best By",
+    "Best by",
+    "Best Before",
+    "Best before",
+    "Expiration Date",
+    "Expiration date",
+    "Use By",
+    "Use by",
+    "Shelf Life",
+    "Shelf life",
+];
+
+/// The expiration or best-by line of the specs list, e.g. "Best By: 11/2027".
+fn extract_expiration_spec(doc: &Html) -> Option<String> {
+    EXPIRATION_SPEC_LABELS
+        .iter()
+        .find_map(|label| extract_spec(doc, label).map(|value| format!("{}: {}", label, value)))
+}
+
+/// Parse the size/flavor variation selector. Each option links to its own product id;
+/// the current product's option is included so prices can be compared side by side.
+fn parse_variations_html(doc: &Html, product_id: &str) -> Vec<Variation> {
+    let option_sel = match Selector::parse(
+        "#product-grouping-row [data-pid], .product-grouping-row [data-pid], \
+         [data-testid='product-variations'] [data-product-id]",
+    ) {
+        Ok(sel) => sel,
+        Err(_) => return Vec::new(),
+    };
+    let label_sel = Selector::parse(".attribute-name, .attribute-value, .name").ok();
+    let price_sel = Selector::parse(".price, .attribute-price").ok();
+
+    let mut variations: Vec<Variation> = Vec::new();
+    for el in doc.select(&option_sel) {
+        let attrs = el.value();
+        let Some(id) = attrs
+            .attr("data-pid")
+            .or_else(|| attrs.attr("data-product-id"))
+            .filter(|id| !id.is_empty())
+        else {
+            continue;
+        };
+        if variations.iter().any(|v| v.product_id == id) {
+            continue;
+        }
+
+        let label = attrs
+            .attr("data-val")
+            .or_else(|| attrs.attr("title"))
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .or_else(|| {
+                label_sel
+                    .as_ref()
+                    .and_then(|sel| el.select(sel).next())
+                    .map(|l| l.text().collect::<String>().trim().to_string())
+            })
+            .filter(|s| !s.is_empty());
+        let Some(label) = label else {
+            continue;
+        };
+
+        let price = price_sel
+            .as_ref()
+            .and_then(|sel| el.select(sel).next())
+            .and_then(|p| parse_price_str(&p.text().collect::<String>()));
+        let classes = attrs.attr("class").unwrap_or("").to_lowercase();
+        let in_stock = attrs
+            .attr("data-is-out-of-stock")
+            .map(|s| s.to_lowercase() != "true")
+            .unwrap_or(!classes.contains("out-of-stock") && !classes.contains("disabled"));
+
+        variations.push(Variation {
+            label,
+            product_id: id.to_string(),
+            price,
+            in_stock,
+        });
+    }
+
+    // A selector with only the current product isn't a real choice
+    if variations.len() == 1 && variations[0].product_id == product_id {
+        variations.clear();
+    }
+    variations
+}
+
+/// Readable note for a schema.org `availability` value such as
+/// "https://schema.org/OutOfStock", used when the page has no stock message.
+fn schema_availability_note(availability: &str) -> Option<String> {
+    let value = availability.rsplit('/').next().unwrap_or(availability);
+    let note = match value {
+        "OutOfStock" => "Out of stock",
+        "SoldOut" => "Sold out",
+        "Discontinued" => "Discontinued",
+        "BackOrder" => "On backorder",
+        "PreOrder" | "PreSale" => "Available for pre-order",
+        "LimitedAvailability" => "Limited availability",
+        _ => return None,
+    };
+    Some(note.to_string())
+}
+
+/// Extract the full stock-status message (e.g. "Out of stock. Expected back in stock on ...").
+fn extract_availability_note(doc: &Html) -> Option<String> {
+    let sel = Selector::parse("#stock-status").ok()?;
+    let el = doc.select(&sel).next()?;
+    let text = el.text().collect::<Vec<_>>().join(" ");
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.is_empty() {
+        None
+    } else {
+        Some(text)
+    }
+}
+
+/// Containers that hold the shipping origin and delivery estimate.
+const SHIPPING_BLOCK_SELECTOR: &str = "#product-shipping, .product-shipping, \
+    [data-testid='shipping-info'], .shipping-info, .delivery-estimate, #product-specs-list";
+const SHIPS_FROM_LABELS: &[&str] = &["Ships from", "Shipped from", "Fulfilled by"];
+const DELIVERY_LABELS: &[&str] = &["Estimated delivery", "Delivery", "Arrives"];
+/// Lines longer than this are whole containers, not a single labeled value.
+const MAX_SHIPPING_LINE_LEN: usize = 120;
+
+/// Find the first line in the shipping block starting with one of `labels`
+/// and return the text after the label (and colon, if any).
+fn extract_shipping_line(doc: &Html, labels: &[&str]) -> Option<String> {
+    let block_sel = Selector::parse(SHIPPING_BLOCK_SELECTOR).ok()?;
+    let line_sel = Selector::parse("li, p, div, span").ok()?;
+    for block in doc.select(&block_sel) {
+        for el in block.select(&line_sel) {
+            let text = el.text().collect::<Vec<_>>().join(" ");
+            let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+            if text.len() > MAX_SHIPPING_LINE_LEN {
+                continue;
+            }
+            for label in labels {
+                let starts_with_label = text
+                    .get(..label.len())
+                    .is_some_and(|prefix| prefix.eq_ignore_ascii_case(label));
+                if !starts_with_label {
+                    continue;
+                }
+                let value = text[label.len()..]
+                    .trim_start_matches([':', ' '])
+                    .trim()
+                    .to_string();
+                if !value.is_empty() {
+                    return Some(value);
+                }
+            }
+        }
+    }
+    None
+}
+
 /// Extract a value from #product-specs-list by label prefix.
 fn extract_spec(doc: &Html, label: &str) -> Option<String> {
     if let Ok(sel) = Selector::parse("#product-specs-list li") {
@@ -477,6 +1113,7 @@ pub fn parse_from_next_data(
     data: &serde_json::Value,
     product_id: &str,
     base_url: &str,
+    currency: &str,
 ) -> Option<ProductDetail> {
     let props = data.get("props")?.get("pageProps")?;
 
@@ -515,11 +1152,13 @@ pub fn parse_from_next_data(
         .and_then(|v| v.as_f64())
         .filter(|&p| p > price);
 
-    let currency = product
-        .get("currency")
-        .and_then(|v| v.as_str())
-        .unwrap_or("USD")
-        .to_string();
+    let (currency, currency_source) = currency_or_fallback(
+        product
+            .get("currency")
+            .and_then(|v| v.as_str())
+            .map(str::to_string),
+        currency,
+    );
 
     let rating = product
         .get("rating")
@@ -573,24 +1212,39 @@ pub fn parse_from_next_data(
     Some(ProductDetail {
         name,
         brand,
+        brand_url: None,
         price,
         original_price,
         currency,
+        currency_source: Some(currency_source),
         rating,
         review_count,
+        rank: None,
         product_url,
         product_id: product_id.to_string(),
         in_stock,
+        availability_note: None,
         description,
+        highlights: Vec::new(),
+        image_urls: Vec::new(),
         product_code,
         upc,
         ingredients,
         supplement_facts: None,
         suggested_use,
         warnings,
+        expiration_note: None,
         shipping_weight,
+        shipping_weight_quantity: None,
+        pack_size: None,
+        ships_from: None,
+        estimated_delivery: None,
+        variations: Vec::new(),
         category_breadcrumb: None,
         review_distribution: None,
+        qa: None,
+        effective_country: None,
+        provenance: None,
     })
 }
 
@@ -603,32 +1257,25 @@ pub fn parse_from_html(
 ) -> Result<ProductDetail, IherbError> {
     let doc = Html::parse_document(html);
 
-    if is_not_found_page(html) {
+    let country = country_from_url(base_url).unwrap_or_default();
+    if is_not_found_page(html, &country) {
         return Err(IherbError::ProductNotFound(product_id.to_string()));
     }
 
-    let name = extract_text(&doc, "h1#name, h1[data-testid='product-name'], h1")
-        .unwrap_or_default();
+    let name = extract_text(&doc, selectors::get("product_name")).unwrap_or_default();
 
     // If we couldn't extract a meaningful product name, this is not a valid product page
     if name.is_empty() || name == "Unknown Product" {
         return Err(IherbError::ProductNotFound(product_id.to_string()));
     }
 
-    let brand = extract_text(
-        &doc,
-        "#brand a span bdi, #brand a[data-testid='product-brand-link'] span bdi",
-    )
-    .unwrap_or_default();
+    let brand = extract_text(&doc, selectors::get("product_brand")).unwrap_or_default();
 
     // Price from share-email hidden input (most reliable)
     let (price, original_price) = extract_prices_from_input(&doc).unwrap_or_else(|| {
-        let p = extract_text(
-            &doc,
-            ".purchase-option-one-time .list-price, #product-price .list-price, .price",
-        )
-        .and_then(|s| parse_price_str(&s))
-        .unwrap_or(0.0);
+        let p = extract_text(&doc, selectors::get("product_price"))
+            .and_then(|s| parse_price_str(&s))
+            .unwrap_or(0.0);
         (p, None)
     });
 
@@ -636,48 +1283,71 @@ pub fn parse_from_html(
     let rating = extract_rating_from_stars(&doc);
 
     // Review count
-    let review_count =
-        extract_text(&doc, "a.rating-count span").and_then(|s| parse_review_count(&s));
+    let review_count = extract_text(&doc, selectors::get("product_review_count"))
+        .and_then(|s| parse_review_count(&s));
 
     // Availability
-    let in_stock = extract_text(&doc, "#stock-status .stock-status-content strong")
+    let in_stock = extract_text(&doc, selectors::get("product_stock"))
         .map(|s| s.to_lowercase().contains("in stock"))
         .unwrap_or(!html.contains("Out of Stock"));
+    let availability_note = if in_stock {
+        None
+    } else {
+        extract_availability_note(&doc)
+    };
 
     let product_code = extract_spec(&doc, "Product Code");
     let upc = extract_spec(&doc, "UPC");
     let shipping_weight = extract_spec(&doc, "Shipping Weight");
+    let ships_from = extract_shipping_line(&doc, SHIPS_FROM_LABELS);
+    let estimated_delivery = extract_shipping_line(&doc, DELIVERY_LABELS);
 
     let supplement_facts = parse_supplement_facts_html(&doc);
     let review_distribution = parse_review_distribution_html(&doc);
+    let variations = parse_variations_html(&doc, product_id);
 
     // Detect actual currency from the page, falling back to config currency
-    let detected_currency =
-        detect_currency_from_html(&doc).unwrap_or_else(|| currency.to_string());
+    let (detected_currency, currency_source) =
+        currency_or_fallback(detect_currency_from_html(&doc), currency);
 
     let product_url = format!("{}/pr/p/{}", base_url, product_id);
 
     let mut product = ProductDetail {
         name,
         brand,
+        brand_url: extract_brand_url(&doc, base_url),
         price,
         original_price,
         currency: detected_currency,
+        currency_source: Some(currency_source),
         rating,
         review_count,
+        rank: extract_rank_badge(&doc.root_element()),
         product_url,
         product_id: product_id.to_string(),
         in_stock,
+        availability_note,
         description: None,
+        highlights: Vec::new(),
+        image_urls: extract_image_urls(&doc, base_url),
         product_code,
         upc,
         ingredients: None,
         supplement_facts,
         suggested_use: None,
         warnings: None,
+        expiration_note: extract_expiration_spec(&doc),
         shipping_weight,
-        category_breadcrumb: None,
+        shipping_weight_quantity: None,
+        pack_size: None,
+        ships_from,
+        estimated_delivery,
+        variations,
+        category_breadcrumb: extract_breadcrumb(html, &doc),
         review_distribution,
+        qa: None,
+        effective_country: None,
+        provenance: None,
     };
 
     // Parse structured overview sections
@@ -704,12 +1374,69 @@ fn extract_prices_from_input(doc: &Html) -> Option<(f64, Option<f64>)> {
     }
 }
 
+/// The product's category path, e.g. ["Supplements", "Vitamins", "Vitamin C"], from
+/// the JSON-LD breadcrumb or else the breadcrumb links on the page.
+fn extract_breadcrumb(html: &str, doc: &Html) -> Option<Vec<String>> {
+    if let Some(names) = super::extract::extract_json_ld_breadcrumb(html) {
+        return Some(names);
+    }
+    let sel =
+        Selector::parse("#breadCrumbs a, .breadcrumb a, nav[aria-label='breadcrumb'] a").ok()?;
+    let names: Vec<String> = doc
+        .select(&sel)
+        .map(|a| a.text().collect::<String>().trim().to_string())
+        .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case("home"))
+        .collect();
+    (!names.is_empty()).then_some(names)
+}
+
+/// The link behind the brand name, made absolute against `base_url`.
+fn extract_brand_url(doc: &Html, base_url: &str) -> Option<String> {
+    let sel = Selector::parse(selectors::get("product_brand_link")).ok()?;
+    let href = doc.select(&sel).next()?.value().attr("href")?.trim();
+    if href.is_empty() {
+        return None;
+    }
+    let base = url::Url::parse(base_url).ok()?;
+    base.join(href).ok().map(String::from)
+}
+
+/// Full-resolution URLs of every image in the product gallery, in gallery order.
+/// Thumbnails carry the large image in a data attribute; `src` is only the
+/// fallback. Relative URLs are made absolute and repeats are dropped.
+fn extract_image_urls(doc: &Html, base_url: &str) -> Vec<String> {
+    let Ok(sel) = Selector::parse(selectors::get("product_gallery_image")) else {
+        return Vec::new();
+    };
+    let Ok(base) = url::Url::parse(base_url) else {
+        return Vec::new();
+    };
+    let mut urls: Vec<String> = Vec::new();
+    for img in doc.select(&sel) {
+        let el = img.value();
+        let Some(src) = GALLERY_IMAGE_ATTRS
+            .iter()
+            .filter_map(|attr| el.attr(attr))
+            .map(str::trim)
+            .find(|src| !src.is_empty() && !src.starts_with("data:"))
+        else {
+            continue;
+        };
+        let Ok(url) = base.join(src) else {
+            continue;
+        };
+        let url = String::from(url);
+        if !urls.contains(&url) {
+            urls.push(url);
+        }
+    }
+    urls
+}
+
 fn extract_rating_from_stars(doc: &Html) -> Option<f64> {
-    let sel = Selector::parse("a.stars.scroll-to, a.stars").ok()?;
-    let el = doc.select(&sel).next()?;
-    let title = el.value().attr("title")?;
-    // Title format: "4.8/5 - 42,328 Reviews"
-    title.split('/').next()?.trim().parse::<f64>().ok()
+    let sel = Selector::parse(STAR_RATING_SELECTOR).ok()?;
+    doc.select(&sel)
+        .find_map(|el| star_element_rating(el.value()))
 }
 
 fn parse_supplement_facts_html(doc: &Html) -> Option<SupplementFacts> {
@@ -725,8 +1452,9 @@ fn parse_supplement_facts_html(doc: &Html) -> Option<SupplementFacts> {
     let mut servings_per_container = None;
 
     for row in table.select(&row_sel) {
-        let cells: Vec<String> = row
-            .select(&cell_sel)
+        let cell_els: Vec<_> = row.select(&cell_sel).collect();
+        let cells: Vec<String> = cell_els
+            .iter()
             .map(|c| c.text().collect::<Vec<_>>().join("").trim().to_string())
             .collect();
 
@@ -757,10 +1485,13 @@ fn parse_supplement_facts_html(doc: &Html) -> Option<SupplementFacts> {
                 continue;
             }
 
+            let amount = cells.get(1).cloned().unwrap_or_default();
             nutrients.push(Nutrient {
                 name: cells[0].clone(),
-                amount: cells.get(1).cloned().unwrap_or_default(),
+                amount_quantity: parse_amount(&amount),
+                amount,
                 daily_value: cells.get(2).cloned().filter(|s| !s.is_empty()),
+                indent_level: detect_indent_level(&row, &cell_els[0]),
             });
         }
     }
@@ -776,6 +1507,87 @@ fn parse_supplement_facts_html(doc: &Html) -> Option<SupplementFacts> {
     })
 }
 
+/// Class names labels use to mark a supplement facts row as a sub-nutrient, matched
+/// as whole tokens, optionally followed by a depth (`indent-2`, `sub-row2`).
+const INDENT_CLASSES: &[&str] = &[
+    "indent",
+    "indented",
+    "sub-row",
+    "subrow",
+    "sub-nutrient",
+    "sub-item",
+    "child",
+    "child-row",
+    "nested",
+];
+
+/// Detect how deeply a supplement facts row is nested under a parent nutrient.
+/// Labels mark sub-nutrients either with an indent/child CSS class, an inline
+/// left padding, or leading (non-breaking) spaces in the name cell.
+fn detect_indent_level(row: &scraper::ElementRef, name_cell: &scraper::ElementRef) -> u8 {
+    for el in [row, name_cell] {
+        if let Some(depth) = el
+            .value()
+            .attr("class")
+            .and_then(|class| class.split_whitespace().find_map(indent_class_depth))
+        {
+            return depth;
+        }
+    }
+
+    if let Some(style) = name_cell.value().attr("style") {
+        let has_padding = style.split(';').any(|prop| {
+            let prop = prop.trim();
+            (prop.starts_with("padding-left")
+                || prop.starts_with("margin-left")
+                || prop.starts_with("text-indent"))
+                && prop
+                    .split(':')
+                    .nth(1)
+                    .and_then(parse_css_length)
+                    .is_some_and(|v| v > 0.0)
+        });
+        if has_padding {
+            return 1;
+        }
+    }
+
+    let raw: String = name_cell.text().collect();
+    let leading = raw
+        .chars()
+        .take_while(|c| *c == ' ' || *c == '\u{a0}')
+        .count();
+    if leading >= 2 {
+        return (leading / 2).min(3) as u8;
+    }
+
+    0
+}
+
+/// The depth a class token marks, if it is one of `INDENT_CLASSES`: its trailing
+/// number, or 1 without one.
+fn indent_class_depth(token: &str) -> Option<u8> {
+    let lower = token.to_lowercase();
+    let name = lower.trim_end_matches(|c: char| c.is_ascii_digit());
+    let depth = &lower[name.len()..];
+    let name = name.strip_suffix(['-', '_']).unwrap_or(name);
+    INDENT_CLASSES
+        .contains(&name)
+        .then(|| depth.parse::<u8>().ok().filter(|&d| d > 0).unwrap_or(1))
+}
+
+/// Parse a CSS length like "12px", "1.5em" or "0" into its number. Other units
+/// are not recognized.
+fn parse_css_length(value: &str) -> Option<f64> {
+    let value = value.trim().trim_end_matches("!important").trim();
+    let number = value
+        .strip_suffix("px")
+        .or_else(|| value.strip_suffix("rem"))
+        .or_else(|| value.strip_suffix("em"))
+        .unwrap_or(value);
+    number.trim().parse().ok()
+}
+
 fn parse_review_distribution_html(doc: &Html) -> Option<ReviewDistribution> {
     // iHerb uses a <ugc-review-progress-bar> custom element containing
     // a <button class="item"> for each star level (5 down to 1).
@@ -840,20 +1652,211 @@ fn parse_review_distribution_html(doc: &Html) -> Option<ReviewDistribution> {
     })
 }
 
-/// Parse a percentage value from a CSS width style like "width: 84%;".
-fn parse_width_percent(style: &str) -> Option<f64> {
-    style
-        .split(';')
-        .filter_map(|prop| {
-            let prop = prop.trim();
-            if prop.starts_with("width") {
-                prop.split(':')
-                    .nth(1)
-                    .and_then(|v| v.trim().strip_suffix('%'))
-                    .and_then(|v| v.trim().parse::<f64>().ok())
-            } else {
-                None
-            }
-        })
-        .next()
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BASE_URL: &str = "https://www.iherb.com";
+
+    /// Extract a product from a page whose only data is `json_ld`, without the DOM pass.
+    fn from_json_ld(json_ld: &str, product_id: &str) -> ProductDetail {
+        let html = format!(
+            r#"<html><head><script type="application/ld+json">{}</script></head><body></body></html>"#,
+            json_ld
+        );
+        extract_product_from_html(
+            &html,
+            product_id,
+            BASE_URL,
+            "USD",
+            &[ExtractStrategy::JsonLd],
+            false,
+        )
+        .expect("product from JSON-LD")
+    }
+
+    #[test]
+    fn json_ld_array_type() {
+        let product = from_json_ld(
+            r#"{
+                "@context": "https://schema.org",
+                "@type": ["Product", "IndividualProduct"],
+                "name": "Vitamin C, 1,000 mg, 120 Veggie Capsules",
+                "brand": {"@type": "Brand", "name": "California Gold Nutrition"},
+                "offers": {"@type": "Offer", "price": "9.50", "priceCurrency": "USD",
+                           "availability": "https://schema.org/InStock"}
+            }"#,
+            "61864",
+        );
+        assert_eq!(product.name, "Vitamin C, 1,000 mg, 120 Veggie Capsules");
+        assert_eq!(product.price, 9.5);
+        assert_eq!(product.currency, "USD");
+        assert!(product.variations.is_empty());
+    }
+
+    #[test]
+    fn json_ld_individual_product() {
+        let product = from_json_ld(
+            r#"{
+                "@context": "https://schema.org",
+                "@type": "IndividualProduct",
+                "name": "Magnesium Glycinate, 180 Tablets",
+                "brand": {"@type": "Brand", "name": "Doctor's Best"},
+                "offers": {"@type": "Offer", "price": 17.24, "priceCurrency": "USD"}
+            }"#,
+            "15",
+        );
+        assert_eq!(product.name, "Magnesium Glycinate, 180 Tablets");
+        assert_eq!(product.brand, "Doctor's Best");
+        assert_eq!(product.price, 17.24);
+        assert!(product.variations.is_empty());
+    }
+
+    #[test]
+    fn json_ld_product_group_uses_default_variant() {
+        let product = from_json_ld(
+            r#"{
+                "@context": "https://schema.org",
+                "@type": "ProductGroup",
+                "name": "Omega-3 Premium Fish Oil",
+                "url": "https://www.iherb.com/pr/omega-3/82846",
+                "brand": {"@type": "Brand", "name": "California Gold Nutrition"},
+                "aggregateRating": {"@type": "AggregateRating", "ratingValue": "4.8", "reviewCount": "51234"},
+                "variesBy": "https://schema.org/size",
+                "hasVariant": [
+                    {"@type": "Product", "name": "Omega-3 Premium Fish Oil, 100 Softgels",
+                     "size": "100 Softgels", "url": "https://www.iherb.com/pr/omega-3/82845",
+                     "offers": {"@type": "Offer", "price": "7.50", "priceCurrency": "USD",
+                                "availability": "https://schema.org/OutOfStock"}},
+                    {"@type": "Product", "name": "Omega-3 Premium Fish Oil, 240 Softgels",
+                     "size": "240 Softgels", "url": "https://www.iherb.com/pr/omega-3/82846",
+                     "offers": {"@type": "Offer", "price": "15.00", "priceCurrency": "USD",
+                                "availability": "https://schema.org/InStock"}}
+                ]
+            }"#,
+            "82846",
+        );
+        // The variant at the group's URL supplies the name and price, the group the rest
+        assert_eq!(product.name, "Omega-3 Premium Fish Oil, 240 Softgels");
+        assert_eq!(product.price, 15.0);
+        assert_eq!(product.brand, "California Gold Nutrition");
+        assert_eq!(product.rating, Some(4.8));
+
+        let variants: Vec<(&str, &str, Option<f64>, bool)> = product
+            .variations
+            .iter()
+            .map(|v| (v.label.as_str(), v.product_id.as_str(), v.price, v.in_stock))
+            .collect();
+        assert_eq!(
+            variants,
+            vec![
+                ("100 Softgels", "82845", Some(7.5), false),
+                ("240 Softgels", "82846", Some(15.0), true),
+            ]
+        );
+    }
+
+    /// JS globals as saved from a product page: `window.IHR_DL.product` with its
+    /// rating and review count as strings, and `window.PRODUCT_DETAILS`.
+    const JS_GLOBALS: &str = r#"{
+        "ihrProduct": {
+            "prdNm": "Omega-3 Fish Oil, 180 Softgels",
+            "brndNm": "California Gold Nutrition",
+            "prc": "$14.00",
+            "prtNum": "CGN-01084",
+            "avgRating": "4.8",
+            "rvwCnt": "12,345"
+        },
+        "productDetails": {
+            "name": "Omega-3 Fish Oil, 180 Softgels",
+            "code": "CGN-01084",
+            "averageRating": 4.1,
+            "reviewCount": 99
+        }
+    }"#;
+
+    #[test]
+    fn js_globals_rating_and_review_count() {
+        let globals: serde_json::Value = serde_json::from_str(JS_GLOBALS).unwrap();
+        let product = parse_from_js_globals(&globals, "62118", BASE_URL, "USD").unwrap();
+        assert_eq!(product.name, "Omega-3 Fish Oil, 180 Softgels");
+        assert_eq!(product.price, 14.0);
+        assert_eq!(product.rating, Some(4.8));
+        assert_eq!(product.review_count, Some(12345));
+    }
+
+    #[test]
+    fn js_globals_rating_under_short_keys() {
+        let globals = serde_json::json!({
+            "ihrProduct": {"prdNm": "Magnesium Glycinate", "rtg": 4.6, "rvwCount": 871}
+        });
+        let product = parse_from_js_globals(&globals, "85093", BASE_URL, "USD").unwrap();
+        assert_eq!(product.rating, Some(4.6));
+        assert_eq!(product.review_count, Some(871));
+    }
+
+    #[test]
+    fn js_globals_rating_from_product_details() {
+        let mut globals: serde_json::Value = serde_json::from_str(JS_GLOBALS).unwrap();
+        let ihr = globals["ihrProduct"].as_object_mut().unwrap();
+        ihr.remove("avgRating");
+        ihr.remove("rvwCnt");
+        let product = parse_from_js_globals(&globals, "62118", BASE_URL, "USD").unwrap();
+        assert_eq!(product.rating, Some(4.1));
+        assert_eq!(product.review_count, Some(99));
+    }
+
+    /// Indent level of the supplement facts row `tr`.
+    fn indent_of(tr: &str) -> u8 {
+        let doc = Html::parse_fragment(&format!("<table>{}</table>", tr));
+        let row_sel = Selector::parse("tr").unwrap();
+        let cell_sel = Selector::parse("td").unwrap();
+        let row = doc.select(&row_sel).next().unwrap();
+        let name_cell = row.select(&cell_sel).next().unwrap();
+        detect_indent_level(&row, &name_cell)
+    }
+
+    #[test]
+    fn indent_from_class_tokens() {
+        for (tr, depth) in [
+            (r#"<tr class="indent"><td>Thiamin</td></tr>"#, 1),
+            (r#"<tr class="row indent-2"><td>Thiamin</td></tr>"#, 2),
+            (r#"<tr><td class="sub-row">Thiamin</td></tr>"#, 1),
+            (r#"<tr class="child"><td>Thiamin</td></tr>"#, 1),
+        ] {
+            assert_eq!(indent_of(tr), depth, "{}", tr);
+        }
+    }
+
+    #[test]
+    fn classes_merely_containing_indent_words_are_not_indents() {
+        for tr in [
+            r#"<tr class="subtotal"><td>Total</td></tr>"#,
+            r#"<tr class="subheader"><td>Amount</td></tr>"#,
+            r#"<tr class="children-hidden"><td>Zinc</td></tr>"#,
+        ] {
+            assert_eq!(indent_of(tr), 0, "{}", tr);
+        }
+    }
+
+    #[test]
+    fn indent_from_css_padding() {
+        for (tr, depth) in [
+            (r#"<tr><td style="padding-left: 12px">Thiamin</td></tr>"#, 1),
+            (r#"<tr><td style="margin-left:1.5em;">Thiamin</td></tr>"#, 1),
+            (r#"<tr><td style="padding-left: 0px">Zinc</td></tr>"#, 0),
+            (r#"<tr><td style="padding-left: 0">Zinc</td></tr>"#, 0),
+        ] {
+            assert_eq!(indent_of(tr), depth, "{}", tr);
+        }
+    }
+
+    #[test]
+    fn css_lengths() {
+        assert_eq!(parse_css_length(" 12px"), Some(12.0));
+        assert_eq!(parse_css_length("1.5em"), Some(1.5));
+        assert_eq!(parse_css_length("2rem !important"), Some(2.0));
+        assert_eq!(parse_css_length("0"), Some(0.0));
+        assert_eq!(parse_css_length("auto"), None);
+    }
 }
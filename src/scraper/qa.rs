@@ -0,0 +1,113 @@
+use crate::model::QaEntry;
+use scraper::{Html, Selector};
+
+use super::helpers::extract_element_text;
+
+/// Question blocks on the Q&A page, each holding a question and its answers.
+const QUESTION_SELECTOR: &str =
+    "[data-testid='question-item'], .question-item, .qa-item, .ugc-question";
+const QUESTION_TEXT_SELECTOR: &str =
+    "[data-testid='question-text'], .question-text, .question-title, .question-content";
+const ANSWER_TEXT_SELECTOR: &str =
+    "[data-testid='answer-text'], .answer-text, .answer-content, .answer-body";
+
+/// URL of a product's customer questions page.
+pub fn build_qa_url(base_url: &str, product_id: &str) -> String {
+    format!("{}/ugc/questions/{}", base_url, product_id)
+}
+
+/// Parse customer questions with their top answer, from schema.org `Question`
+/// structured data first, then from the question list in the DOM. Unanswered
+/// questions are left out.
+pub fn parse_qa_from_html(html: &str) -> Vec<QaEntry> {
+    let doc = Html::parse_document(html);
+    let entries = parse_qa_json_ld(&doc);
+    if !entries.is_empty() {
+        return entries;
+    }
+    parse_qa_dom(&doc)
+}
+
+fn parse_qa_json_ld(doc: &Html) -> Vec<QaEntry> {
+    let Ok(sel) = Selector::parse(r#"script[type="application/ld+json"]"#) else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+    for el in doc.select(&sel) {
+        let text: String = el.text().collect();
+        if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&text) {
+            collect_json_ld_questions(&parsed, &mut entries);
+        }
+    }
+    entries
+}
+
+/// Walk a JSON-LD value for `Question` nodes, which may sit at the top level, in an
+/// `@graph`, or under a `QAPage`'s `mainEntity`.
+fn collect_json_ld_questions(value: &serde_json::Value, entries: &mut Vec<QaEntry>) {
+    match value {
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_json_ld_questions(item, entries);
+            }
+        }
+        serde_json::Value::Object(_) => {
+            if super::extract::is_json_ld_type(value, "Question") {
+                if let Some(entry) = json_ld_question(value) {
+                    entries.push(entry);
+                }
+                return;
+            }
+            for key in ["@graph", "mainEntity"] {
+                if let Some(nested) = value.get(key) {
+                    collect_json_ld_questions(nested, entries);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn json_ld_question(node: &serde_json::Value) -> Option<QaEntry> {
+    let question = node
+        .get("name")
+        .or_else(|| node.get("text"))
+        .and_then(|v| v.as_str())
+        .map(clean_text)
+        .filter(|q| !q.is_empty())?;
+    let answer_node = match node.get("acceptedAnswer") {
+        Some(accepted) => accepted,
+        None => match node.get("suggestedAnswer")? {
+            serde_json::Value::Array(answers) => answers.first()?,
+            answer => answer,
+        },
+    };
+    let answer = answer_node
+        .get("text")
+        .and_then(|v| v.as_str())
+        .map(clean_text)
+        .filter(|a| !a.is_empty())?;
+    Some(QaEntry { question, answer })
+}
+
+fn parse_qa_dom(doc: &Html) -> Vec<QaEntry> {
+    let Ok(sel) = Selector::parse(QUESTION_SELECTOR) else {
+        return Vec::new();
+    };
+    doc.select(&sel)
+        .filter_map(|item| {
+            let question = extract_element_text(&item, QUESTION_TEXT_SELECTOR)?;
+            let answer = extract_element_text(&item, ANSWER_TEXT_SELECTOR)?;
+            Some(QaEntry {
+                question: clean_text(&question),
+                answer: clean_text(&answer),
+            })
+        })
+        .filter(|entry| !entry.question.is_empty() && !entry.answer.is_empty())
+        .collect()
+}
+
+/// Collapse the whitespace of user-written text to single spaces.
+fn clean_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
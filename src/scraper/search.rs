@@ -1,21 +1,60 @@
-use crate::cli::SortOrder;
+use crate::cli::{RatingPrior, SortOrder, ValueWeights};
 use crate::error::IherbError;
 use crate::model::{ProductSummary, SearchResult};
 use chromiumoxide::Page;
 use scraper::{Html, Selector};
+use std::collections::HashMap;
 
 use super::helpers::{
-    debug_dump_html, detect_currency_from_html, extract_element_text, parse_price_str,
-    parse_review_count,
+    currency_or_fallback, detect_currency_from_html, extract_element_text, extract_rank_badge,
+    parse_price_str, parse_review_count, slugify, star_element_rating, STAR_RATING_SELECTOR,
 };
+use super::selectors;
+
+/// Products iHerb shows per listing page.
+pub const RESULTS_PER_PAGE: usize = 48;
+
+/// Canonical form of a search query: trimmed, whitespace collapsed, lowercased.
+/// iHerb treats queries differing only in case or spacing as the same search.
+pub fn normalize_query(query: &str) -> String {
+    query
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// `--min-price` / `--max-price`, in the storefront's currency. Either end may be open.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PriceRange {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl PriceRange {
+    pub fn is_set(&self) -> bool {
+        self.min.is_some() || self.max.is_some()
+    }
 
-const RESULTS_PER_PAGE: usize = 48;
+    pub fn contains(&self, price: f64) -> bool {
+        self.min.is_none_or(|min| price >= min) && self.max.is_none_or(|max| price <= max)
+    }
+
+    /// The range as iHerb's price facet value, e.g. `10-25`, `10-` or `-25`. Also used
+    /// in cache keys.
+    pub fn facet_value(&self) -> Option<String> {
+        let end = |bound: Option<f64>| bound.map(|b| b.to_string()).unwrap_or_default();
+        self.is_set()
+            .then(|| format!("{}-{}", end(self.min), end(self.max)))
+    }
+}
 
 pub fn build_search_url(
     base_url: &str,
     query: &str,
     sort: SortOrder,
     category: Option<&str>,
+    price: PriceRange,
     page_num: usize,
 ) -> String {
     let sort_param = sort.as_url_param();
@@ -25,6 +64,12 @@ pub fn build_search_url(
         None => String::new(),
     };
 
+    // Narrows the listing server-side; results are still checked against the range
+    let price_param = match price.facet_value() {
+        Some(range) => format!("&price={}", range),
+        None => String::new(),
+    };
+
     let page_param = if page_num > 1 {
         format!("&p={}", page_num)
     } else {
@@ -32,15 +77,29 @@ pub fn build_search_url(
     };
 
     format!(
-        "{}/search?kw={}{}{}{}",
+        "{}/search?kw={}{}{}{}{}",
         base_url,
-        urlencoded(query),
+        urlencoded(&normalize_query(query)),
         sort_param,
         category_param,
+        price_param,
         page_param
     )
 }
 
+/// Build the URL of a brand's landing page, e.g. `/c/now-foods`.
+pub fn build_brand_url(base_url: &str, brand: &str, sort: SortOrder, page_num: usize) -> String {
+    let mut params = sort.as_url_param().to_string();
+    if page_num > 1 {
+        params.push_str(&format!("&p={}", page_num));
+    }
+    let query = match params.strip_prefix('&') {
+        Some(rest) => format!("?{}", rest),
+        None => params,
+    };
+    format!("{}/c/{}{}", base_url, slugify(brand), query)
+}
+
 fn urlencoded(s: &str) -> String {
     url::form_urlencoded::byte_serialize(s.as_bytes()).collect()
 }
@@ -53,12 +112,10 @@ pub async fn extract_search(
     base_url: &str,
     currency: &str,
 ) -> Result<SearchResult, IherbError> {
-    debug_dump_html(html, &format!("search_{}", query.replace(' ', "_")));
-
     // Try __NEXT_DATA__ first (may exist on some page versions)
     if let Ok(Some(next_data)) = super::extract::extract_next_data(page).await {
         tracing::debug!("Attempting __NEXT_DATA__ extraction for search");
-        if let Some(result) = parse_search_from_next_data(&next_data, query, base_url) {
+        if let Some(result) = parse_search_from_next_data(&next_data, query, base_url, currency) {
             tracing::info!("Successfully extracted search results from __NEXT_DATA__");
             return Ok(result);
         }
@@ -74,6 +131,7 @@ pub fn parse_search_from_next_data(
     data: &serde_json::Value,
     query: &str,
     base_url: &str,
+    currency: &str,
 ) -> Option<SearchResult> {
     let props = data.get("props")?.get("pageProps")?;
 
@@ -91,7 +149,7 @@ pub fn parse_search_from_next_data(
 
     let products: Vec<ProductSummary> = products_arr
         .iter()
-        .filter_map(|item| parse_product_summary_json(item, base_url))
+        .filter_map(|item| parse_product_summary_json(item, base_url, currency))
         .collect();
 
     if products.is_empty() {
@@ -102,10 +160,15 @@ pub fn parse_search_from_next_data(
         query: query.to_string(),
         total_results: total,
         products,
+        effective_country: None,
     })
 }
 
-fn parse_product_summary_json(item: &serde_json::Value, base_url: &str) -> Option<ProductSummary> {
+fn parse_product_summary_json(
+    item: &serde_json::Value,
+    base_url: &str,
+    currency: &str,
+) -> Option<ProductSummary> {
     let name = item
         .get("title")
         .or_else(|| item.get("name"))
@@ -144,11 +207,12 @@ fn parse_product_summary_json(item: &serde_json::Value, base_url: &str) -> Optio
         .and_then(|v| v.as_f64())
         .filter(|&p| p > price);
 
-    let currency = item
-        .get("currency")
-        .and_then(|v| v.as_str())
-        .unwrap_or("USD")
-        .to_string();
+    let (currency, currency_source) = currency_or_fallback(
+        item.get("currency")
+            .and_then(|v| v.as_str())
+            .map(str::to_string),
+        currency,
+    );
 
     let rating = item.get("rating").and_then(|v| v.as_f64());
     let review_count = item
@@ -180,8 +244,11 @@ fn parse_product_summary_json(item: &serde_json::Value, base_url: &str) -> Optio
         price,
         original_price,
         currency,
+        currency_source: Some(currency_source),
         rating,
         review_count,
+        adjusted_rating: None,
+        rank: None,
         product_url,
         product_id,
         in_stock,
@@ -197,20 +264,12 @@ pub fn parse_search_from_html(
 ) -> Result<SearchResult, IherbError> {
     let doc = Html::parse_document(html);
     let total_results = extract_total_results(&doc);
-    let detected_currency = detect_currency_from_html(&doc).unwrap_or_else(|| currency.to_string());
-
-    let mut products = Vec::new();
-    let card_sel = Selector::parse("div.product-cell-container").ok();
-    let link_sel = Selector::parse("a.absolute-link.product-link, a.product-link").ok();
-
-    if let (Some(card_sel), Some(link_sel)) = (card_sel, link_sel) {
-        let cards: Vec<_> = doc.select(&card_sel).collect();
-        tracing::debug!("Found {} product-cell-container cards", cards.len());
-        products.extend(
-            cards
-                .iter()
-                .filter_map(|card| parse_product_card(card, &link_sel, &detected_currency, base_url)),
-        );
+    let (detected_currency, currency_source) =
+        currency_or_fallback(detect_currency_from_html(&doc), currency);
+
+    let mut products = extract_product_cards(&doc, base_url, &detected_currency);
+    for product in &mut products {
+        product.currency_source = Some(currency_source);
     }
 
     if !products.is_empty() {
@@ -223,9 +282,26 @@ pub fn parse_search_from_html(
         query: query.to_string(),
         total_results,
         products,
+        effective_country: None,
     })
 }
 
+/// Extract every product card in a listing grid (search results, brand pages).
+pub fn extract_product_cards(doc: &Html, base_url: &str, currency: &str) -> Vec<ProductSummary> {
+    let card_sel = Selector::parse(selectors::get("search_card")).ok();
+    let link_sel = Selector::parse(selectors::get("search_card_link")).ok();
+
+    let (Some(card_sel), Some(link_sel)) = (card_sel, link_sel) else {
+        return Vec::new();
+    };
+    let cards: Vec<_> = doc.select(&card_sel).collect();
+    tracing::debug!("Found {} product-cell-container cards", cards.len());
+    cards
+        .iter()
+        .filter_map(|card| parse_product_card(card, &link_sel, currency, base_url))
+        .collect()
+}
+
 fn parse_product_card(
     card_el: &scraper::ElementRef,
     link_sel: &Selector,
@@ -275,7 +351,7 @@ fn parse_product_card(
 
     let rating = extract_card_rating(card_el);
 
-    let review_count = extract_element_text(card_el, "a.rating-count span")
+    let review_count = extract_element_text(card_el, selectors::get("search_card_review_count"))
         .and_then(|s| parse_review_count(&s));
 
     let in_stock = extract_card_stock_status(card_el, link_attrs);
@@ -297,8 +373,11 @@ fn parse_product_card(
         price,
         original_price,
         currency: currency.to_string(),
+        currency_source: None,
         rating,
         review_count,
+        adjusted_rating: None,
+        rank: extract_rank_badge(card_el),
         product_url,
         product_id,
         in_stock,
@@ -306,10 +385,10 @@ fn parse_product_card(
 }
 
 fn extract_card_rating(card_el: &scraper::ElementRef) -> Option<f64> {
-    let sel = Selector::parse("a.stars").ok()?;
-    let el = card_el.select(&sel).next()?;
-    let title = el.value().attr("title")?;
-    title.split('/').next()?.trim().parse::<f64>().ok()
+    let sel = Selector::parse(STAR_RATING_SELECTOR).ok()?;
+    card_el
+        .select(&sel)
+        .find_map(|el| star_element_rating(el.value()))
 }
 
 fn extract_card_stock_status(
@@ -329,6 +408,142 @@ fn extract_card_stock_status(
         .unwrap_or(true)
 }
 
+/// Sort products client-side for orders that can be derived from summary fields.
+/// Orders only the server knows (relevance, best-selling, newest) are left untouched.
+pub fn sort_products(products: &mut [ProductSummary], sort: SortOrder) {
+    match sort {
+        SortOrder::PriceAsc => products.sort_by(|a, b| a.price.total_cmp(&b.price)),
+        SortOrder::PriceDesc => products.sort_by(|a, b| b.price.total_cmp(&a.price)),
+        SortOrder::Rating => products.sort_by(|a, b| {
+            sort_rating(b)
+                .unwrap_or(-1.0)
+                .total_cmp(&sort_rating(a).unwrap_or(-1.0))
+        }),
+        SortOrder::DiscountDesc => {
+            products.sort_by(|a, b| discount_percent(b).total_cmp(&discount_percent(a)))
+        }
+        // Value needs prices per serving from product pages; see `sort_by_value`
+        SortOrder::Relevance | SortOrder::BestSelling | SortOrder::Newest | SortOrder::Value => {}
+    }
+}
+
+/// The rating products are sorted and filtered by: the adjusted rating when
+/// `--bayesian-rating` computed one, else the rating shown on iHerb.
+pub fn sort_rating(product: &ProductSummary) -> Option<f64> {
+    product.adjusted_rating.or(product.rating)
+}
+
+/// Set each product's `adjusted_rating` to its Bayesian average: the rating pulled
+/// toward `prior.mean` as if the product also had `prior.weight` reviews at that
+/// rating, so a handful of perfect reviews ranks below thousands of near-perfect ones.
+/// A product with a rating but no review count is treated as having none.
+pub fn apply_bayesian_rating(products: &mut [ProductSummary], prior: RatingPrior) {
+    for product in products {
+        product.adjusted_rating = bayesian_rating(product, prior);
+    }
+}
+
+/// `product`'s rating as `apply_bayesian_rating` adjusts it; `None` when unrated.
+pub fn bayesian_rating(product: &ProductSummary, prior: RatingPrior) -> Option<f64> {
+    product.rating.map(|rating| {
+        let reviews = f64::from(product.review_count.unwrap_or(0));
+        (prior.weight * prior.mean + reviews * rating) / (prior.weight + reviews)
+    })
+}
+
+/// Sort products by descending "value" score, given each product's price per serving
+/// (keyed by product id). Each component is normalized to 0..1 within the result set:
+///
+/// - rating: `rating / 5`
+/// - reviews: `ln(1 + reviews) / ln(1 + most reviews)`
+/// - price: `cheapest price per serving / price per serving`
+///
+/// The score is the weighted sum of the components; a missing value scores 0.
+pub fn sort_by_value(
+    products: &mut [ProductSummary],
+    price_per_serving: &HashMap<String, f64>,
+    weights: ValueWeights,
+) {
+    let most_reviews = products
+        .iter()
+        .filter_map(|p| p.review_count)
+        .max()
+        .map_or(0.0, |most| f64::from(most).ln_1p());
+    let cheapest = price_per_serving
+        .values()
+        .copied()
+        .filter(|pps| *pps > 0.0)
+        .min_by(f64::total_cmp);
+
+    let score = |p: &ProductSummary| {
+        let rating = p.rating.unwrap_or(0.0) / 5.0;
+        let reviews = match p.review_count {
+            Some(count) if most_reviews > 0.0 => f64::from(count).ln_1p() / most_reviews,
+            _ => 0.0,
+        };
+        let price = match (price_per_serving.get(&p.product_id), cheapest) {
+            (Some(&pps), Some(cheapest)) if pps > 0.0 => cheapest / pps,
+            _ => 0.0,
+        };
+        weights.rating * rating + weights.reviews * reviews + weights.price * price
+    };
+    products.sort_by(|a, b| score(b).total_cmp(&score(a)));
+}
+
+/// Check whether products are already in the requested order, i.e. iHerb honored the sort.
+pub fn is_in_sort_order(products: &[ProductSummary], sort: SortOrder) -> bool {
+    let mut sorted = products.to_vec();
+    sort_products(&mut sorted, sort);
+    sorted
+        .iter()
+        .zip(products)
+        .all(|(a, b)| a.product_id == b.product_id)
+}
+
+fn discount_percent(product: &ProductSummary) -> f64 {
+    match product.original_price {
+        Some(orig) if orig > product.price => (orig - product.price) / orig * 100.0,
+        _ => 0.0,
+    }
+}
+
+/// Whether the page's sort control shows the sort requested by `url`'s `sr`
+/// parameter as active. `None` when the page has no recognizable sort indicator.
+pub fn sort_indicator_matches(html: &str, url: &str) -> Option<bool> {
+    let active = active_sort_param(&Html::parse_document(html))?;
+    Some(active == sr_from_href(url).unwrap_or_default())
+}
+
+/// The `sr` value of the active sort option, or "" when relevance (no `sr`) is active.
+fn active_sort_param(doc: &Html) -> Option<String> {
+    let option_sel = Selector::parse(
+        "select[name='sr'] option[selected], select#sort-by option[selected], \
+         select.sort-select option[selected]",
+    )
+    .ok()?;
+    if let Some(option) = doc.select(&option_sel).next() {
+        let value = option.value().attr("value").unwrap_or_default();
+        // Some dropdowns carry the whole query string instead of the bare value
+        return Some(sr_from_href(value).unwrap_or_else(|| value.trim().to_string()));
+    }
+
+    let link_sel = Selector::parse(
+        ".sort-by a.selected, .sort-by a.active, .sort-by li.active a, \
+         a[aria-current][href*='sr=']",
+    )
+    .ok()?;
+    let link = doc.select(&link_sel).next()?;
+    let href = link.value().attr("href")?;
+    Some(sr_from_href(href).unwrap_or_default())
+}
+
+fn sr_from_href(href: &str) -> Option<String> {
+    let query = href.split_once('?').map_or(href, |(_, q)| q);
+    url::form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _)| key == "sr")
+        .map(|(_, value)| value.into_owned())
+}
+
 /// Calculate how many pages needed for the desired limit.
 pub fn pages_needed(limit: usize) -> usize {
     limit.div_ceil(RESULTS_PER_PAGE)
@@ -344,7 +559,7 @@ fn extract_card_attr(el: &scraper::ElementRef, selector: &str, attr: &str) -> Op
         .filter(|s| !s.is_empty())
 }
 
-fn extract_id_from_url(url: &str) -> Option<String> {
+pub fn extract_id_from_url(url: &str) -> Option<String> {
     url.split('/')
         .rev()
         .find(|segment| segment.chars().all(|c| c.is_ascii_digit()) && !segment.is_empty())
@@ -392,3 +607,118 @@ fn extract_total_results(doc: &Html) -> Option<u32> {
     None
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    const BASE_URL: &str = "https://www.iherb.com";
+
+    /// Product cards as (id, price, list price, rating), in the page's order.
+    type Card = (&'static str, f64, Option<f64>, f64);
+
+    const A: Card = ("1001", 10.0, Some(20.0), 4.2);
+    const B: Card = ("1002", 25.0, None, 4.9);
+    const C: Card = ("1003", 5.0, Some(6.0), 4.5);
+    const D: Card = ("1004", 40.0, Some(60.0), 3.9);
+
+    /// A hand-built search results page shaped like iHerb's: the sort dropdown with
+    /// `active_sr` selected, followed by `cards`. Whether iHerb itself honors each
+    /// sort is checked by the `live-tests` feature.
+    fn results_page(active_sr: &str, cards: &[Card]) -> String {
+        let options: String = ["", "4", "3", "1", "2", "5", "6"]
+            .iter()
+            .map(|sr| {
+                let selected = if *sr == active_sr { " selected" } else { "" };
+                format!(r#"<option value="{}"{}>Sort</option>"#, sr, selected)
+            })
+            .collect();
+        let cards: String = cards
+            .iter()
+            .map(|(id, price, list_price, rating)| {
+                let list_price = list_price
+                    .map(|p| format!(r#"<span class="price-olp"><bdi>${:.2}</bdi></span>"#, p))
+                    .unwrap_or_default();
+                format!(
+                    r#"<div class="product-cell-container"><div class="product ga-product" data-is-out-of-stock="false">
+                    <a class="absolute-link product-link" href="{base}/pr/product/{id}" data-product-id="{id}"
+                       data-ga-brand-name="Brand" data-ga-discount-price="${price:.2}" title="Product {id}"></a>
+                    {list_price}
+                    <a class="stars" title="{rating}/5 - 100 Reviews"></a>
+                    </div></div>"#,
+                    base = BASE_URL,
+                )
+            })
+            .collect();
+        format!(
+            r#"<html><body><select name="sr" class="sort-select">{}</select>{}</body></html>"#,
+            options, cards
+        )
+    }
+
+    fn search_url(sort: SortOrder) -> String {
+        build_search_url(BASE_URL, "vitamin c", sort, None, PriceRange::default(), 1)
+    }
+
+    fn products(page: &str) -> Vec<ProductSummary> {
+        parse_search_from_html(page, "vitamin c", BASE_URL, "USD")
+            .unwrap()
+            .products
+    }
+
+    #[test]
+    fn active_sort_indicator_is_compared_with_the_url() {
+        for &sort in SortOrder::value_variants() {
+            let url = search_url(sort);
+            let sr = sr_from_href(&url).unwrap_or_default();
+            let page = results_page(&sr, &[A, B, C, D]);
+            assert_eq!(
+                sort_indicator_matches(&page, &url),
+                Some(true),
+                "{:?}",
+                sort
+            );
+
+            for &other in SortOrder::value_variants() {
+                if other.as_url_param() != sort.as_url_param() {
+                    let other_url = search_url(other);
+                    assert_eq!(
+                        sort_indicator_matches(&page, &other_url),
+                        Some(false),
+                        "{:?} page read as {:?}",
+                        sort,
+                        other
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sorted_pages_are_in_sort_order() {
+        let relevance = products(&results_page("", &[A, B, C, D]));
+        for (sort, sr, cards) in [
+            (SortOrder::PriceAsc, "4", [C, A, B, D]),
+            (SortOrder::PriceDesc, "3", [D, B, A, C]),
+            (SortOrder::Rating, "1", [B, C, A, D]),
+            (SortOrder::DiscountDesc, "6", [A, D, C, B]),
+        ] {
+            let sorted = products(&results_page(sr, &cards));
+            assert_eq!(sorted.len(), 4);
+            assert!(is_in_sort_order(&sorted, sort), "{:?}", sort);
+            assert!(!is_in_sort_order(&relevance, sort), "{:?}", sort);
+        }
+    }
+
+    #[test]
+    fn server_side_sorts_accept_any_order() {
+        let page = products(&results_page("", &[D, A, C, B]));
+        for sort in [
+            SortOrder::Relevance,
+            SortOrder::BestSelling,
+            SortOrder::Newest,
+        ] {
+            assert!(is_in_sort_order(&page, sort), "{:?}", sort);
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use scraper::Selector;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+/// Selectors for the page elements most likely to move when iHerb changes its
+/// markup, by the name `--set-selector NAME=CSS` overrides them with.
+const DEFAULTS: &[(&str, &str)] = &[
+    ("product_name", "h1#name, h1[data-testid='product-name'], h1"),
+    (
+        "product_brand",
+        "#brand a span bdi, #brand a[data-testid='product-brand-link'] span bdi",
+    ),
+    ("product_brand_link", "#brand a[href]"),
+    (
+        "product_price",
+        ".purchase-option-one-time .list-price, #product-price .list-price, .price",
+    ),
+    ("product_review_count", "a.rating-count span"),
+    (
+        "product_stock",
+        "#stock-status .stock-status-content strong",
+    ),
+    (
+        "product_gallery_image",
+        "#product-image img, .product-easyzoom img, .thumbnail-container img, [data-testid='product-image-thumbnail'] img",
+    ),
+    ("search_card", "div.product-cell-container"),
+    (
+        "search_card_link",
+        "a.absolute-link.product-link, a.product-link",
+    ),
+    ("search_card_review_count", "a.rating-count span"),
+];
+
+/// This run's `--set-selector` overrides, installed once at startup.
+static OVERRIDES: OnceLock<HashMap<String, String>> = OnceLock::new();
+
+/// The selector named `name`: this run's override, or else the built-in one.
+pub fn get(name: &str) -> &'static str {
+    if let Some(css) = OVERRIDES.get().and_then(|overrides| overrides.get(name)) {
+        return css;
+    }
+    DEFAULTS
+        .iter()
+        .find(|(known, _)| *known == name)
+        .map(|(_, css)| *css)
+        .expect("selector name is listed in DEFAULTS")
+}
+
+/// Install the run's overrides. Only the first call has an effect.
+pub fn set_overrides(overrides: &[(String, String)]) {
+    if !overrides.is_empty() {
+        let _ = OVERRIDES.set(overrides.iter().cloned().collect());
+    }
+}
+
+/// Parse a `--set-selector` value of the form `name=css`, checking that the name is
+/// one of the overridable selectors and that the CSS parses.
+pub fn parse_override(s: &str) -> Result<(String, String), String> {
+    let (name, css) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected 'name=css', got '{}'", s))?;
+    let (name, css) = (name.trim(), css.trim());
+    if !DEFAULTS.iter().any(|(known, _)| *known == name) {
+        let known: Vec<&str> = DEFAULTS.iter().map(|(known, _)| *known).collect();
+        return Err(format!(
+            "unknown selector '{}'; expected one of: {}",
+            name,
+            known.join(", ")
+        ));
+    }
+    Selector::parse(css).map_err(|e| format!("invalid CSS selector '{}': {}", css, e))?;
+    Ok((name.to_string(), css.to_string()))
+}